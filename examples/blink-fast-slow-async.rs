@@ -1,13 +1,14 @@
 use anyhow::Result;
 use log::{error, info};
 use rppal_pfd::{
-    ChipSelect, HardwareAddress, InterruptMode, Level, PiFaceDigital, SpiBus, SpiMode,
+    ActiveLevel, ChipSelect, HardwareAddress, InterruptMode, Level, PfdInterrupt, PiFaceDigital,
+    SpiBus, SpiMode,
 };
 use std::{sync::mpsc::channel, time::Duration};
 
 #[derive(Debug)]
 enum HardwareInterfaceMessage {
-    InterruptReceived,
+    InterruptReceived(PfdInterrupt),
 }
 
 fn main() -> Result<()> {
@@ -33,9 +34,15 @@ fn main() -> Result<()> {
     let mut quit_button = pfd.get_pull_up_input_pin(2)?;
     let led = pfd.get_output_pin_low(2)?;
 
-    faster_button.set_interrupt(InterruptMode::BothEdges)?;
-    slower_button.set_interrupt(InterruptMode::BothEdges)?;
-    quit_button.set_interrupt(InterruptMode::BothEdges)?;
+    // The buttons pull their inputs low when pressed so have the MCP23S17 invert them.
+    faster_button.set_active_level(ActiveLevel::Low)?;
+    slower_button.set_active_level(ActiveLevel::Low)?;
+    quit_button.set_active_level(ActiveLevel::Low)?;
+
+    // Keep the buttons in scope so that their interrupts stay enabled.
+    let faster_button = faster_button.into_interrupts_enabled(InterruptMode::BothEdges)?;
+    let slower_button = slower_button.into_interrupts_enabled(InterruptMode::BothEdges)?;
+    let quit_button = quit_button.into_interrupts_enabled(InterruptMode::BothEdges)?;
 
     let mut period = 1.0;
     let mut led_state = Level::High;
@@ -43,41 +50,37 @@ fn main() -> Result<()> {
 
     let (tx, rx) = channel();
 
-    pfd.subscribe_async_interrupts(move |_| {
-        tx.send(HardwareInterfaceMessage::InterruptReceived)
+    pfd.subscribe_async_interrupts(move |interrupt| {
+        tx.send(HardwareInterfaceMessage::InterruptReceived(interrupt))
             .expect("Failed to send message");
     })?;
 
-    // If there's an interrupt already active, we'll not detect the next one and never
-    // service it, so read INTCAP which will clear it if it is already active.
-    let _ = pfd.get_interrupt_capture()?;
-
     while !quit {
         led.write(led_state)?;
 
         match rx.recv_timeout(Duration::from_secs_f64(period / 2.0)) {
-            Ok(msg) => {
-                println!("An interrupt happened (msg={msg:?})...");
+            Ok(HardwareInterfaceMessage::InterruptReceived(interrupt)) => {
+                println!("An interrupt happened ({interrupt:?})...");
 
-                let flags = pfd.get_interrupt_flags()?;
-                let inputs = pfd.get_interrupt_capture()?;
-                if (flags & 0x01) != 0 {
-                    println!("Got button 1 (0x{flags:02x})");
-                    if (inputs & 0x01) == 0 {
+                let flags = interrupt.flags;
+                let inputs = interrupt.capture;
+                if flags.contains(faster_button.get_pin_number()) {
+                    println!("Got button 1 ({flags})");
+                    if inputs.contains(faster_button.get_pin_number()) {
                         period /= 2.0;
                     }
-                } else if (flags & 0x02) != 0 {
-                    println!("Got button 2 (0x{flags:02x})");
-                    if (inputs & 0x02) == 0 {
+                } else if flags.contains(slower_button.get_pin_number()) {
+                    println!("Got button 2 ({flags})");
+                    if inputs.contains(slower_button.get_pin_number()) {
                         period *= 2.0;
                     }
-                } else if (flags & 0x04) != 0 {
-                    println!("Got button 3 (0x{flags:02x})");
-                    if (inputs & 0x04) == 0 {
+                } else if flags.contains(quit_button.get_pin_number()) {
+                    println!("Got button 3 ({flags})");
+                    if inputs.contains(quit_button.get_pin_number()) {
                         quit = true;
                     }
                 } else {
-                    error!("Got unmatched 0x{flags:02x}");
+                    error!("Got unmatched {flags}");
                 }
             }
             Err(_) => {
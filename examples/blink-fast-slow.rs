@@ -19,9 +19,9 @@
 use anyhow::Result;
 use log::{error, info};
 use rppal_pfd::{
-    ChipSelect, HardwareAddress, InterruptMode, Level, PiFaceDigital, SpiBus, SpiMode,
+    ActiveLevel, ChipSelect, HardwareAddress, InterruptMode, Level, PiFaceDigital, SpiBus, SpiMode,
 };
-use std::{cmp::max, ptr, time::Duration};
+use std::{cmp::max, time::Duration};
 
 fn main() -> Result<()> {
     env_logger::init();
@@ -46,11 +46,17 @@ fn main() -> Result<()> {
     let mut quit_button = pfd.get_pull_up_input_pin(2)?;
     let led = pfd.get_output_pin_low(2)?;
 
+    // The buttons pull their inputs low when pressed so have the MCP23S17 invert them
+    // so that a pressed button reads as Level::High.
+    faster_button.set_active_level(ActiveLevel::Low)?;
+    slower_button.set_active_level(ActiveLevel::Low)?;
+    quit_button.set_active_level(ActiveLevel::Low)?;
+
     // Generate interrupts on both edges as this simplifies the logic
     // to avoid perpetual re-interrupts whilst the button is pressed.
-    faster_button.set_interrupt(InterruptMode::BothEdges)?;
-    slower_button.set_interrupt(InterruptMode::BothEdges)?;
-    quit_button.set_interrupt(InterruptMode::BothEdges)?;
+    let faster_button = faster_button.into_interrupts_enabled(InterruptMode::BothEdges)?;
+    let slower_button = slower_button.into_interrupts_enabled(InterruptMode::BothEdges)?;
+    let quit_button = quit_button.into_interrupts_enabled(InterruptMode::BothEdges)?;
 
     let mut period = 1000;
     let mut led_state = Level::High;
@@ -68,26 +74,22 @@ fn main() -> Result<()> {
             Ok(Some(interrupts)) => {
                 for (pin, level) in interrupts {
                     match pin {
-                        p if ptr::eq(p, &faster_button) && level == Level::Low => {
+                        p if p == faster_button.get_pin_number() && level == Level::High => {
                             period = max(period / 2, 125);
                             println!("Going faster: {} Hz", 1000.0 / period as f32);
                         }
 
-                        p if ptr::eq(p, &slower_button) && level == Level::Low => {
+                        p if p == slower_button.get_pin_number() && level == Level::High => {
                             period *= 2;
                             println!("Going slower: {} Hz", 1000.0 / period as f32);
                         }
 
-                        p if ptr::eq(p, &quit_button) && level == Level::Low => {
+                        p if p == quit_button.get_pin_number() && level == Level::High => {
                             quit = true;
                         }
 
                         p => {
-                            info!(
-                                "Ignoring button: {} going {}",
-                                p.get_pin_number() + 1,
-                                level
-                            );
+                            info!("Ignoring button: {} going {}", u8::from(p) + 1, level);
                         }
                     }
                 }
@@ -1,7 +1,8 @@
 // Check reliability of operation of the SPI bus at different speeds.
 //
-// Writes alternating data to an output pin and reads back the value to check that data
-// was reliably written.
+// Uses the PiFaceDigital's clock calibration to write test patterns to a register and
+// read them back at a range of SPI clock rates, reporting the results at each rate and
+// the clock rate that the calibration settled on.
 //
 // Note that if you just compile this with something like:
 //
@@ -13,48 +14,32 @@
 // dependencies. Copy this code into a new project or temporarily comment out the
 // dev-dependency on rppal-mcp23s17 and use the mainline dependency instead.
 
-use std::time::Instant;
-
 use anyhow::Result;
 use log::info;
-use rppal_pfd::{ChipSelect, HardwareAddress, Level, PiFaceDigital, SpiBus, SpiMode};
+use rppal_pfd::{ChipSelect, HardwareAddress, PiFaceDigital, SpiBus, SpiMode};
 
 fn main() -> Result<()> {
     env_logger::init();
 
     info!("Speed test started!");
 
-    for clock_speed in (100_000..5_000_001).step_by(100_000) {
-        info!("Speed test started at {clock_speed} Hz!");
-
-        let mut pfd = PiFaceDigital::new(
-            HardwareAddress::new(0).unwrap(),
-            SpiBus::Spi0,
-            ChipSelect::Cs0,
-            clock_speed,
-            SpiMode::Mode0,
-        )?;
-        pfd.init()?;
-
-        let output_pin = pfd.get_output_pin(2)?;
-        let mut good_count = 0;
-        let mut bad_count = 0;
-
-        let start_time = Instant::now();
-        for i in 0..1000 {
-            let request_level: Level = ((i & 1) as u8).into();
-            output_pin.write(request_level)?;
-            if request_level == output_pin.read()? {
-                good_count += 1
-            } else {
-                bad_count += 1
-            }
-        }
-        let time_taken = Instant::now() - start_time;
-        println!(
-            "Speed: {clock_speed}  Good: {good_count} Bad: {bad_count}  Duration: {time_taken:?}"
-        );
+    let mut pfd = PiFaceDigital::new(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )?;
+    pfd.init()?;
+
+    let calibration = pfd.calibrate_clock(100_000..=5_000_000)?;
+    for step in &calibration.steps {
+        println!("{step}");
     }
+    println!(
+        "\nHighest reliable clock: {} Hz  Selected clock: {} Hz",
+        calibration.highest_reliable_clock, calibration.selected_clock
+    );
 
     Ok(())
 }
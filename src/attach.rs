@@ -0,0 +1,187 @@
+//! Warm attachment to a board that is already running.
+//!
+//! [`PiFaceDigital::init()`] puts every register back to its default, which includes
+//! turning all the outputs off. When an application restarts while the equipment
+//! wired to the outputs stays up, that momentary drop can be disruptive (power-cycling
+//! whatever a relay controls, for example). [`PiFaceDigital::attach()`] instead adopts
+//! the board as it is: the outputs are left at their current levels and only
+//! configuration registers that don't hold the values `init()` would have written are
+//! corrected.
+
+use std::fmt;
+
+use log::{info, warn};
+#[cfg(not(any(test, feature = "mockspi")))]
+use rppal::gpio::Trigger;
+
+#[cfg(not(any(test, feature = "mockspi")))]
+use crate::PiFaceDigitalError;
+use crate::{
+    ErrorContext, ExpectedConfig, PiFaceDigital, PinSet, RESET_REGISTER_STATES, RegisterAddress,
+    RegisterMismatch, Result, context::Context, default_iocon,
+};
+
+/// What [`PiFaceDigital::attach()`] found and changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttachReport {
+    /// The configuration registers that were corrected: what they should have held
+    /// and what was found.
+    pub corrections: Vec<RegisterMismatch>,
+    /// The outputs that were high, as found in `OLATA` and left unchanged.
+    pub outputs: PinSet,
+}
+
+impl AttachReport {
+    /// Was the board already configured as the driver expects?
+    pub fn was_configured(&self) -> bool {
+        self.corrections.is_empty()
+    }
+}
+
+impl fmt::Display for AttachReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Attached with outputs {} high", self.outputs)?;
+        if self.was_configured() {
+            return write!(f, "; configuration correct");
+        }
+        write!(f, "; corrected ")?;
+        for (i, correction) in self.corrections.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{correction}")?;
+        }
+        Ok(())
+    }
+}
+
+impl PiFaceDigital {
+    /// Attach to the PiFace Digital I/O board without disturbing its outputs.
+    ///
+    /// An alternative to [`PiFaceDigital::init()`] for when the board may already be
+    /// running, for example because the application has restarted. `IOCON` is
+    /// checked, and corrected if necessary, first because the other registers can't be
+    /// read reliably until it is right. The remaining configuration registers,
+    /// including the directions in `IODIRA` and `IODIRB`, are then read in one burst
+    /// and only those that differ from the defaults written by `init()` are
+    /// rewritten. `GPIOA` and `OLATA` are never written so the outputs keep their
+    /// current levels, which become the levels that the health monitor expects.
+    ///
+    /// If the board had been configured by a previous instance, any input pull-ups,
+    /// interrupts and so on that it set up are turned off (just as `init()` would)
+    /// and appear among the corrections. Any pending interrupt is cleared so that the
+    /// interrupt line is released.
+    ///
+    /// Failed accesses are retried according to the [`RetryPolicy`] but never
+    /// escalated: until `OLATA` has been read there is nothing to restore the outputs
+    /// to, so a reset would turn them all off.
+    ///
+    /// [`RetryPolicy`]: crate::RetryPolicy
+    ///
+    /// ```no_run
+    /// use rppal_pfd::{ChipSelect, HardwareAddress, PiFaceDigital, SpiBus, SpiMode};
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    ///
+    /// let report = pfd.attach().expect("Failed to attach!");
+    /// if !report.was_configured() {
+    ///     println!("{report}");
+    /// }
+    /// ```
+    pub fn attach(&mut self) -> Result<AttachReport> {
+        info!("Attach to PiFaceDigital preserving outputs");
+        let attaching = ErrorContext::operation("attaching");
+        let mut corrections = Vec::new();
+
+        // Correct IOCON first so that register addressing and sequential reads work.
+        let iocon = default_iocon();
+        let found = self
+            .read_register_without_escalation(RegisterAddress::IOCON)
+            .context(attaching)?;
+        if found != iocon {
+            let pfd_state = self.pfd_state.borrow();
+            if pfd_state.write_iocon(iocon).context(attaching)? != iocon {
+                return Err(pfd_state.no_hardware_detected());
+            }
+            corrections.push(RegisterMismatch {
+                register: RegisterAddress::IOCON,
+                expected: iocon,
+                actual: found,
+            });
+        }
+
+        // Read everything up to GPPUB in one burst and correct whatever is wrong. GPIOA
+        // lies beyond the burst so the outputs are never written.
+        let mut configuration = [0u8; RegisterAddress::GPPUB as usize + 1];
+        {
+            let pfd_state = self.pfd_state.borrow();
+            pfd_state
+                .attempt(|mcp23s17| {
+                    pfd_state
+                        .burst
+                        .read(mcp23s17, RegisterAddress::IODIRA, &mut configuration)
+                })
+                .context(attaching.register(RegisterAddress::IODIRA))?;
+        }
+        for (register, default_value) in RESET_REGISTER_STATES {
+            let Some(expected) = default_value else {
+                continue;
+            };
+            let Some(&actual) = configuration.get(register as usize) else {
+                continue;
+            };
+            if actual != expected {
+                self.write_register(register, expected).context(attaching)?;
+                corrections.push(RegisterMismatch {
+                    register,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        // Release the interrupt line in case an interrupt was left pending.
+        let _ = self
+            .read_register_without_escalation(RegisterAddress::INTCAPB)
+            .context(attaching)?;
+
+        let outputs = self
+            .read_register_without_escalation(RegisterAddress::OLATA)
+            .context(attaching)?;
+        let report = AttachReport {
+            corrections,
+            outputs: PinSet::from_bits(outputs),
+        };
+        if report.was_configured() {
+            info!("{report}");
+        } else {
+            warn!("{report}");
+        }
+
+        // The health monitor should expect the configuration as it now is.
+        {
+            let mut pfd_state = self.pfd_state.borrow_mut();
+            pfd_state.expected = ExpectedConfig {
+                olata: outputs,
+                ..ExpectedConfig::default()
+            };
+            pfd_state.input_cache.invalidate();
+        }
+
+        #[cfg(not(any(test, feature = "mockspi")))]
+        self.pfd_state
+            .borrow()
+            .lock_interrupt_pin()
+            .set_interrupt(Trigger::FallingEdge, None)
+            .map_err(PiFaceDigitalError::from)
+            .context(attaching)?;
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,333 @@
+// Terminal UI for a virtual PiFace Digital.
+//
+// Draws the eight output LEDs and the two relays, updating live as an application
+// writes its outputs, and lets the keyboard work the inputs:
+//
+// - 0-3 press (and release) the four switches, S1-S4, on inputs 0-3.
+// - 4-7 toggle inputs 4-7 between high and low.
+// - q quits.
+//
+// USAGE:
+//
+//   pfd-virtual [SOCKET]
+//
+// Start it listening on SOCKET (default $RPPAL_PFD_VIRTUAL or /tmp/rppal-pfd-virtual.sock)
+// and then run the application, built with the `mockspi` feature, connected to the same
+// socket with PiFaceDigitalBuilder::virtual_board() or
+// PiFaceDigital::connect_virtual_board(). One application can be connected at a time.
+
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rppal_pfd::{Level, PiFaceDigital, PinNumber, PinSet, VirtualMessage};
+
+/// How long a switch is held down when its key is pressed.
+const PRESS_TIME: Duration = Duration::from_millis(200);
+
+/// The inputs wired to the board's switches.
+const SWITCHES: u8 = 4;
+
+/// The outputs wired to the board's relays.
+const RELAYS: u8 = 2;
+
+enum Event {
+    Key(u8),
+    Connected(UnixStream),
+    Received(VirtualMessage),
+    Disconnected,
+}
+
+/// The board as seen from the terminal.
+struct Board {
+    socket: PathBuf,
+    application: Option<UnixStream>,
+    outputs: PinSet,
+    /// The inputs that are high: all of them until pulled low, as the board has
+    /// pull-ups.
+    inputs: PinSet,
+    /// Switches held down and when to release them.
+    releases: Vec<(Instant, PinNumber)>,
+}
+
+fn main() -> io::Result<()> {
+    let socket = env::args_os()
+        .nth(1)
+        .or_else(|| env::var_os(PiFaceDigital::VIRTUAL_BOARD_VAR))
+        .map_or_else(
+            || PathBuf::from(PiFaceDigital::VIRTUAL_BOARD_SOCKET),
+            PathBuf::from,
+        );
+
+    // A socket left behind by an earlier run would stop the bind.
+    let _ = fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket)?;
+
+    let (events, received) = mpsc::channel();
+    spawn_listener(listener, events.clone());
+    spawn_keyboard(events);
+
+    let raw_mode = RawMode::enter()?;
+    let mut board = Board {
+        socket,
+        application: None,
+        outputs: PinSet::EMPTY,
+        inputs: PinSet::ALL,
+        releases: Vec::new(),
+    };
+    board.draw()?;
+
+    loop {
+        let timeout = board
+            .releases
+            .iter()
+            .map(|(release_at, _)| release_at.saturating_duration_since(Instant::now()))
+            .min()
+            .unwrap_or(Duration::from_secs(1));
+        match received.recv_timeout(timeout) {
+            Ok(Event::Key(b'q' | b'Q' | 0x03)) => break,
+            Ok(Event::Key(key @ b'0'..=b'7')) => {
+                let pin = PinNumber::new(key - b'0').expect("Keys are valid pins");
+                if u8::from(pin) < SWITCHES {
+                    board.press(pin);
+                } else {
+                    board.toggle(pin);
+                }
+            }
+            Ok(Event::Key(_)) => (),
+            Ok(Event::Connected(application)) => board.connect(application),
+            Ok(Event::Received(VirtualMessage::Outputs(outputs))) => board.outputs = outputs,
+            Ok(Event::Received(_)) => (),
+            Ok(Event::Disconnected) => board.application = None,
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        board.release_switches();
+        board.draw()?;
+    }
+
+    drop(raw_mode);
+    let _ = fs::remove_file(&board.socket);
+    Ok(())
+}
+
+/// Accept applications, one at a time, passing on what they send.
+fn spawn_listener(listener: UnixListener, events: Sender<Event>) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let Ok(reader) = stream.try_clone() else {
+                continue;
+            };
+            if events.send(Event::Connected(stream)).is_err() {
+                return;
+            }
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if let Ok(message) = line.parse() {
+                    if events.send(Event::Received(message)).is_err() {
+                        return;
+                    }
+                }
+            }
+            if events.send(Event::Disconnected).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Pass on every key pressed.
+fn spawn_keyboard(events: Sender<Event>) {
+    thread::spawn(move || {
+        for key in io::stdin().lock().bytes() {
+            let Ok(key) = key else {
+                return;
+            };
+            if events.send(Event::Key(key)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+impl Board {
+    /// Take on a newly connected application and tell it the level on every input.
+    fn connect(&mut self, application: UnixStream) {
+        self.application = Some(application);
+        for pin in 0..8 {
+            self.send_input(PinNumber::new(pin).expect("Valid pin"));
+        }
+    }
+
+    /// Press a switch, which pulls its input low until released.
+    fn press(&mut self, pin: PinNumber) {
+        let release_at = Instant::now() + PRESS_TIME;
+        self.releases.retain(|&(_, held)| held != pin);
+        self.releases.push((release_at, pin));
+        if self.inputs.contains(pin) {
+            self.inputs.remove(pin);
+            self.send_input(pin);
+        }
+    }
+
+    /// Release the switches whose time is up.
+    fn release_switches(&mut self) {
+        let now = Instant::now();
+        let (released, held) = self
+            .releases
+            .iter()
+            .partition(|&&(release_at, _)| release_at <= now);
+        self.releases = held;
+        for (_, pin) in released {
+            self.inputs.insert(pin);
+            self.send_input(pin);
+        }
+    }
+
+    /// Toggle an input between high and low.
+    fn toggle(&mut self, pin: PinNumber) {
+        if self.inputs.contains(pin) {
+            self.inputs.remove(pin);
+        } else {
+            self.inputs.insert(pin);
+        }
+        self.send_input(pin);
+    }
+
+    fn send_input(&mut self, pin: PinNumber) {
+        let level = if self.inputs.contains(pin) {
+            Level::High
+        } else {
+            Level::Low
+        };
+        if let Some(application) = &mut self.application {
+            if writeln!(application, "{}", VirtualMessage::Input(pin, level)).is_err() {
+                self.application = None;
+            }
+        }
+    }
+
+    /// Redraw the whole board. The terminal is in raw mode, so each line ends with
+    /// an explicit carriage return.
+    fn draw(&self) -> io::Result<()> {
+        const RESET: &str = "\x1b[0m";
+        const LIT: &str = "\x1b[1;31m";
+        const DIM: &str = "\x1b[2m";
+
+        let pins = || {
+            (0..8)
+                .rev()
+                .map(|pin| PinNumber::new(pin).expect("Valid pin"))
+        };
+        let mut screen = String::from("\x1b[?25l\x1b[2J\x1b[H");
+        screen += &format!("Virtual PiFace Digital on {}\r\n", self.socket.display());
+        screen += match self.application {
+            Some(_) => "Application connected\r\n\r\n",
+            None => "Waiting for application...\r\n\r\n",
+        };
+
+        screen += "Output  ";
+        for pin in pins() {
+            screen += &format!("  {pin}");
+        }
+        screen += "\r\nLED     ";
+        for pin in pins() {
+            if self.outputs.contains(pin) {
+                screen += &format!("  {LIT}●{RESET}");
+            } else {
+                screen += &format!("  {DIM}○{RESET}");
+            }
+        }
+        screen += "\r\n\r\n";
+        for relay in (0..RELAYS).rev() {
+            let on = self
+                .outputs
+                .contains(PinNumber::new(relay).expect("Valid pin"));
+            let state = if on {
+                format!("{LIT}on {RESET}")
+            } else {
+                format!("{DIM}off{RESET}")
+            };
+            screen += &format!("Relay {relay}: {state}   ");
+        }
+        screen += "\r\n\r\n";
+
+        screen += "Input   ";
+        for pin in pins() {
+            screen += &format!("  {pin}");
+        }
+        screen += "\r\nLevel   ";
+        for pin in pins() {
+            screen += if self.inputs.contains(pin) {
+                "  H"
+            } else {
+                "  L"
+            };
+        }
+        screen += "\r\n";
+        for switch in 0..SWITCHES {
+            let pressed = !self
+                .inputs
+                .contains(PinNumber::new(switch).expect("Valid pin"));
+            let state = if pressed {
+                format!("{LIT}pressed {RESET}")
+            } else {
+                format!("{DIM}released{RESET}")
+            };
+            screen += &format!("\r\nSwitch S{}: {state}", switch + 1);
+        }
+        screen += "\r\n\r\nKeys: 0-3 press switch S1-S4, 4-7 toggle input, q quit\r\n";
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(screen.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// The terminal in raw mode (keys delivered as pressed, without echo), restored when
+/// dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enter() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Ok(RawMode {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+        print!("\x1b[?25h\r\n");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Run `stty` on the terminal.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            "stty failed: is standard input a terminal?",
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
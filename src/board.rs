@@ -0,0 +1,252 @@
+//! The physical components of the PiFace Digital.
+//!
+//! Rather than anonymous pins, [`PiFaceDigital::split()`] hands out the components
+//! actually fitted to the board:
+//!
+//! - Every output drives one of the eight [`Led`]s (and an open-collector output on
+//!   the screw terminals).
+//! - Outputs 0 and 1 also drive the two [`Relay`]s, so their LEDs are reached through
+//!   the relays with [`Relay::led()`].
+//! - Inputs 0 to 3 are connected to the four push-button [`Switch`]es (and the
+//!   screw terminals).
+//! - Inputs 4 to 7 are only available on the screw terminals so are handed out as
+//!   plain [`InputPin`]s, with their pull-ups enabled as after
+//!   [`PiFaceDigital::init()`].
+//!
+//! Each component holds its pin, so can only be handed out once: splitting the board
+//! again, or asking for one of its pins with [`PiFaceDigital::get_output_pin()`] and
+//! the like, fails with `PinNotAvailable` until the component is dropped.
+//! (`rppal_mcp23s17` never frees a dropped input pin itself, so the driver keeps
+//! dropped input pins and hands them out again.)
+
+use crate::{
+    ActiveLevel, InputPin, InterruptMode, InterruptState, InterruptsDisabled, InterruptsEnabled,
+    Level, OutputPin, PiFaceDigital, PiFaceDigitalError, PinNumber, PullUp, Result,
+};
+
+/// The components of a PiFace Digital returned by [`PiFaceDigital::split()`].
+#[derive(Debug)]
+pub struct Components {
+    /// The relays on outputs 0 and 1.
+    pub relays: [Relay; 2],
+    /// The LEDs on outputs 2 to 7 (indexed from 0, so `leds[0]` is on output 2). Use
+    /// [`Components::led()`] to get any of the LEDs by output number.
+    pub leds: [Led; 6],
+    /// The push-button switches on inputs 0 to 3.
+    pub switches: [Switch; 4],
+    /// The inputs 4 to 7 that are only connected to the screw terminals (indexed from
+    /// 0, so `inputs[0]` is input 4).
+    pub inputs: [InputPin<PullUp>; 4],
+}
+
+impl Components {
+    /// Get the LED on an output (0-7).
+    ///
+    /// The LEDs on outputs 0 and 1 are those of the relays (see [`Relay::led()`]), so
+    /// turning them on also energises the relays.
+    pub fn led<N>(&self, output: N) -> Result<&Led>
+    where
+        N: TryInto<PinNumber>,
+        PiFaceDigitalError: From<N::Error>,
+    {
+        let output = usize::from(u8::from(output.try_into()?));
+        Ok(match output {
+            0 | 1 => self.relays[output].led(),
+            _ => &self.leds[output - 2],
+        })
+    }
+}
+
+/// One of the two relays, which are energised when their output is high.
+#[derive(Debug)]
+pub struct Relay {
+    led: Led,
+}
+
+impl Relay {
+    /// Energise the relay, closing its normally-open contacts.
+    pub fn energise(&self) -> Result<()> {
+        self.led.pin.set_high()
+    }
+
+    /// De-energise the relay, closing its normally-closed contacts.
+    pub fn de_energise(&self) -> Result<()> {
+        self.led.pin.set_low()
+    }
+
+    /// Is the relay energised?
+    pub fn is_energised(&self) -> Result<bool> {
+        self.led.pin.is_high()
+    }
+
+    /// Get the relay's number (0 or 1), which is also its output pin number.
+    pub fn number(&self) -> PinNumber {
+        self.led.number()
+    }
+
+    /// Get the LED next to the relay, which shares its output so is lit whenever the
+    /// relay is energised.
+    pub fn led(&self) -> &Led {
+        &self.led
+    }
+
+    /// Give up the relay for its underlying output pin.
+    pub fn into_output_pin(self) -> OutputPin {
+        self.led.pin
+    }
+}
+
+/// One of the eight LEDs, one on each output, which are lit when their output is high.
+#[derive(Debug)]
+pub struct Led {
+    pin: OutputPin,
+}
+
+impl Led {
+    /// Turn the LED on.
+    pub fn on(&self) -> Result<()> {
+        self.pin.set_high()
+    }
+
+    /// Turn the LED off.
+    pub fn off(&self) -> Result<()> {
+        self.pin.set_low()
+    }
+
+    /// Turn the LED on if it is off and off if it is on.
+    pub fn toggle(&self) -> Result<()> {
+        match self.pin.read()? {
+            Level::Low => self.on(),
+            Level::High => self.off(),
+        }
+    }
+
+    /// Is the LED on?
+    pub fn is_on(&self) -> Result<bool> {
+        self.pin.is_high()
+    }
+
+    /// Get the LED's output pin number (0-7).
+    pub fn number(&self) -> PinNumber {
+        self.pin.get_pin_number()
+    }
+
+    /// Give up the LED for its underlying output pin.
+    pub fn into_output_pin(self) -> OutputPin {
+        self.pin
+    }
+}
+
+/// One of the four push-button switches on inputs 0 to 3.
+///
+/// A pressed switch pulls its input to ground, so the input has its pull-up enabled
+/// and is [`ActiveLevel::Low`]: the switch reads as [`Level::High`] and is active
+/// while pressed, including in interrupts.
+#[derive(Debug)]
+pub struct Switch<I = InterruptsDisabled> {
+    pin: InputPin<PullUp, I>,
+}
+
+impl<I: InterruptState> Switch<I> {
+    /// Is the switch pressed?
+    pub fn is_pressed(&self) -> Result<bool> {
+        self.pin.is_active()
+    }
+
+    /// Is the switch released?
+    pub fn is_released(&self) -> Result<bool> {
+        self.pin.is_inactive()
+    }
+
+    /// Get the switch's number (0-3), which is also its input pin number.
+    pub fn number(&self) -> PinNumber {
+        self.pin.get_pin_number()
+    }
+
+    /// Get the underlying input pin.
+    pub fn input_pin(&self) -> &InputPin<PullUp, I> {
+        &self.pin
+    }
+
+    /// Get the underlying input pin mutably, for example to poll it for interrupts
+    /// with [`InputPin::poll_interrupt()`].
+    pub fn input_pin_mut(&mut self) -> &mut InputPin<PullUp, I> {
+        &mut self.pin
+    }
+
+    /// Give up the switch for its underlying input pin.
+    pub fn into_input_pin(self) -> InputPin<PullUp, I> {
+        self.pin
+    }
+}
+
+impl Switch<InterruptsDisabled> {
+    /// Enable interrupts on the switch (see [`InputPin::into_interrupts_enabled()`]).
+    ///
+    /// As the switch is [`ActiveLevel::Low`], [`InterruptMode::ActiveHigh`] interrupts
+    /// for as long as the switch is pressed.
+    pub fn into_interrupts_enabled(self, mode: InterruptMode) -> Result<Switch<InterruptsEnabled>> {
+        Ok(Switch {
+            pin: self.pin.into_interrupts_enabled(mode)?,
+        })
+    }
+}
+
+impl Switch<InterruptsEnabled> {
+    /// Disable interrupts on the switch.
+    pub fn into_interrupts_disabled(self) -> Result<Switch<InterruptsDisabled>> {
+        Ok(Switch {
+            pin: self.pin.into_interrupts_disabled()?,
+        })
+    }
+}
+
+impl PiFaceDigital {
+    /// Split the PiFace Digital into its relays, LEDs, switches and spare inputs.
+    ///
+    /// The outputs keep their current state. Fails if any of the pins is already in
+    /// use, which is also the case if the board has already been split and the
+    /// components are still in use.
+    ///
+    /// ```no_run
+    /// use rppal_pfd::{ChipSelect, Components, HardwareAddress, PiFaceDigital, SpiBus, SpiMode};
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    /// pfd.init().expect("Failed to initialise!");
+    ///
+    /// let components = pfd.split().expect("Pins in use");
+    /// if components.switches[0].is_pressed().expect("Bad read") {
+    ///     components.relays[0].energise().expect("Bad write");
+    ///     components.led(7).expect("Bad output").on().expect("Bad write");
+    /// }
+    /// ```
+    pub fn split(&self) -> Result<Components> {
+        let led = |pin: u8| -> Result<Led> {
+            Ok(Led {
+                pin: self.get_output_pin(pin)?,
+            })
+        };
+        let switch = |pin: u8| -> Result<Switch> {
+            let mut pin = self.get_pull_up_input_pin(pin)?;
+            pin.set_active_level(ActiveLevel::Low)?;
+            Ok(Switch { pin })
+        };
+        Ok(Components {
+            relays: [Relay { led: led(0)? }, Relay { led: led(1)? }],
+            leds: [led(2)?, led(3)?, led(4)?, led(5)?, led(6)?, led(7)?],
+            switches: [switch(0)?, switch(1)?, switch(2)?, switch(3)?],
+            inputs: [
+                self.get_pull_up_input_pin(4)?,
+                self.get_pull_up_input_pin(5)?,
+                self.get_pull_up_input_pin(6)?,
+                self.get_pull_up_input_pin(7)?,
+            ],
+        })
+    }
+}
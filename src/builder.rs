@@ -0,0 +1,300 @@
+//! Construction of a [`PiFaceDigital`] from named settings.
+//!
+//! [`PiFaceDigital::new()`] takes its settings as positional arguments, doesn't check
+//! the SPI clock and leaves the board to be initialised by a separate call to
+//! [`PiFaceDigital::init()`]. The builder names each setting, falls back to the same
+//! defaults as [`PiFaceDigital::with_defaults()`], validates everything before any
+//! hardware is touched and returns a board that is ready to use.
+
+use std::path::PathBuf;
+
+use log::{info, warn};
+
+use crate::{
+    ChipSelect, HardwareAddress, PiFaceDigital, PiFaceDigitalError, PiFaceDigitalState, PinSet,
+    RegisterAddress, Result, SpiBus, SpiMode, context::Context, lock,
+};
+
+/// How [`PiFaceDigitalBuilder::build()`] initialises the MCP23S17.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InitPolicy {
+    /// Initialise the MCP23S17 to the default configuration with
+    /// [`PiFaceDigital::init()`].
+    #[default]
+    Reset,
+    /// Adopt the MCP23S17 as it is, keeping the outputs at their current levels and
+    /// only correcting configuration that is wrong, with [`PiFaceDigital::attach()`].
+    WarmAttach,
+    /// Leave the MCP23S17 as it is, because another instance has already initialised
+    /// it (see [`PiFaceDigital::init()`] for when that makes sense).
+    Skip,
+}
+
+/// Builder for a [`PiFaceDigital`] returned by [`PiFaceDigital::builder()`].
+///
+/// ```no_run
+/// use rppal_pfd::{ChipSelect, InitPolicy, PiFaceDigital, PinSet, SpiBus};
+///
+/// let pfd = PiFaceDigital::builder()
+///     .address(1)
+///     .spi_bus(SpiBus::Spi0)
+///     .chip_select(ChipSelect::Cs0)
+///     .spi_clock(1_000_000)
+///     .init_policy(InitPolicy::Reset)
+///     // Turn all the outputs off whenever the application isn't running.
+///     .safe_state(PinSet::EMPTY)
+///     .build()
+///     .expect("Failed to build PiFace Digital");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PiFaceDigitalBuilder {
+    address: u8,
+    spi_bus: SpiBus,
+    chip_select: ChipSelect,
+    spi_clock: u32,
+    spi_mode: SpiMode,
+    interrupt_gpio: u8,
+    init_policy: InitPolicy,
+    safe_state: Option<PinSet>,
+    exclusive: bool,
+    virtual_board: Option<PathBuf>,
+}
+
+impl Default for PiFaceDigitalBuilder {
+    /// The settings used by [`PiFaceDigital::with_defaults()`], on the PiFace
+    /// Digital's interrupt GPIO and initialising the board.
+    fn default() -> Self {
+        PiFaceDigitalBuilder {
+            address: 0,
+            spi_bus: SpiBus::Spi0,
+            chip_select: ChipSelect::Cs0,
+            spi_clock: 100_000,
+            spi_mode: SpiMode::Mode0,
+            interrupt_gpio: PiFaceDigital::DEFAULT_INTERRUPT_GPIO,
+            init_policy: InitPolicy::Reset,
+            safe_state: None,
+            exclusive: lock::EXCLUSIVE_BY_DEFAULT,
+            virtual_board: None,
+        }
+    }
+}
+
+impl PiFaceDigitalBuilder {
+    /// Set the hardware address, as set by `JP1` and `JP2` on the board (default `0`).
+    ///
+    /// Either a [`HardwareAddress`] or a `u8`, which is checked by
+    /// [`PiFaceDigitalBuilder::build()`].
+    pub fn address(mut self, address: impl Into<u8>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    /// Set the SPI bus (default `Spi0`).
+    pub fn spi_bus(mut self, spi_bus: SpiBus) -> Self {
+        self.spi_bus = spi_bus;
+        self
+    }
+
+    /// Set the SPI chip select (default `Cs0`).
+    pub fn chip_select(mut self, chip_select: ChipSelect) -> Self {
+        self.chip_select = chip_select;
+        self
+    }
+
+    /// Set the SPI clock rate in Hz (default 100kHz), which can be no more than
+    /// [`PiFaceDigital::MAX_SPI_CLOCK`].
+    pub fn spi_clock(mut self, spi_clock: u32) -> Self {
+        self.spi_clock = spi_clock;
+        self
+    }
+
+    /// Set the SPI mode (default `Mode0`).
+    pub fn spi_mode(mut self, spi_mode: SpiMode) -> Self {
+        self.spi_mode = spi_mode;
+        self
+    }
+
+    /// Set the Raspberry Pi GPIO (BCM numbering) that the MCP23S17's interrupt output
+    /// is connected to (default [`PiFaceDigital::DEFAULT_INTERRUPT_GPIO`]).
+    ///
+    /// Only worth changing if the board has been modified or is connected other than
+    /// through the Raspberry Pi's header.
+    pub fn interrupt_gpio(mut self, interrupt_gpio: u8) -> Self {
+        self.interrupt_gpio = interrupt_gpio;
+        self
+    }
+
+    /// Set how the MCP23S17 is initialised (default [`InitPolicy::Reset`]).
+    pub fn init_policy(mut self, init_policy: InitPolicy) -> Self {
+        self.init_policy = init_policy;
+        self
+    }
+
+    /// Set the outputs to drive high whenever the application isn't in control of them
+    /// (by default none are set).
+    ///
+    /// The safe state is written to the outputs as soon as the board has been
+    /// initialised and again when the [`PiFaceDigital`], and all the pins taken from
+    /// it, have been dropped. With [`InitPolicy::WarmAttach`] the outputs are kept as
+    /// they are when the board is built, so the safe state is only applied when it is
+    /// dropped.
+    pub fn safe_state(mut self, safe_state: PinSet) -> Self {
+        self.safe_state = Some(safe_state);
+        self
+    }
+
+    /// Set whether to lock the board against use by other processes (by default it is
+    /// locked).
+    ///
+    /// The lock is keyed by SPI bus, chip select and hardware address and is held
+    /// until the [`PiFaceDigital`], and all the pins taken from it, have been dropped.
+    /// If another process holds it, [`PiFaceDigitalBuilder::build()`] returns
+    /// `Err(`[`PiFaceDigitalError::BoardInUse`]`)` naming the process if it can. Only
+    /// turn this off when the board is being shared deliberately, for example with
+    /// each process using different pins and [`InitPolicy::Skip`].
+    ///
+    /// When using the mock SPI there's no hardware to protect, so boards are only
+    /// locked if this is set.
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Connect to a virtual board's user interface listening on the socket at `path`
+    /// (by default there is none), before the board is initialised.
+    ///
+    /// Only has an effect when using the mock SPI (the `mockspi` feature), otherwise
+    /// it is logged and ignored, so an application can pass on
+    /// [`PiFaceDigital::VIRTUAL_BOARD_VAR`] whatever it is built for:
+    ///
+    /// ```no_run
+    /// use rppal_pfd::PiFaceDigital;
+    ///
+    /// let mut builder = PiFaceDigital::builder();
+    /// if let Some(path) = std::env::var_os(PiFaceDigital::VIRTUAL_BOARD_VAR) {
+    ///     builder = builder.virtual_board(path);
+    /// }
+    /// let pfd = builder.build().expect("Failed to build PiFace Digital");
+    /// ```
+    pub fn virtual_board(mut self, path: impl Into<PathBuf>) -> Self {
+        self.virtual_board = Some(path.into());
+        self
+    }
+
+    /// Check the settings without touching any hardware.
+    pub fn validate(&self) -> Result<()> {
+        HardwareAddress::new(self.address)?;
+        if self.spi_clock == 0 || self.spi_clock > PiFaceDigital::MAX_SPI_CLOCK {
+            return Err(PiFaceDigitalError::SpiClockOutOfRange(self.spi_clock));
+        }
+        if self.interrupt_gpio > PiFaceDigital::MAX_INTERRUPT_GPIO {
+            return Err(PiFaceDigitalError::InvalidInterruptGpio {
+                gpio: self.interrupt_gpio,
+                reason: "not on the Raspberry Pi's header",
+            });
+        }
+        if spi_bus_gpios(self.spi_bus).contains(&self.interrupt_gpio) {
+            return Err(PiFaceDigitalError::InvalidInterruptGpio {
+                gpio: self.interrupt_gpio,
+                reason: "used by the SPI bus",
+            });
+        }
+        Ok(())
+    }
+
+    /// Validate the settings, open the SPI bus and interrupt GPIO and initialise the
+    /// board according to the [`InitPolicy`].
+    pub fn build(self) -> Result<PiFaceDigital> {
+        self.validate()?;
+        let address = HardwareAddress::new(self.address)?;
+        let mut pfd = PiFaceDigital::open(
+            address,
+            self.spi_bus,
+            self.chip_select,
+            self.spi_clock,
+            self.spi_mode,
+            self.interrupt_gpio,
+            self.exclusive,
+        )?;
+        if let Some(path) = &self.virtual_board {
+            #[cfg(any(test, feature = "mockspi"))]
+            pfd.connect_virtual_board(path)
+                .context(pfd.pfd_state.borrow().context("connecting virtual board"))?;
+            #[cfg(not(any(test, feature = "mockspi")))]
+            warn!(
+                "Ignore virtual board {} without the mock SPI",
+                path.display()
+            );
+        }
+        match self.init_policy {
+            InitPolicy::Reset => pfd.init()?,
+            InitPolicy::WarmAttach => {
+                pfd.attach()?;
+            }
+            InitPolicy::Skip => info!("Attach to PiFace Digital without initialising"),
+        }
+        if let Some(safe_state) = self.safe_state {
+            let mut pfd_state = pfd.pfd_state.borrow_mut();
+            pfd_state.safe_state = Some(safe_state);
+            if self.init_policy != InitPolicy::WarmAttach {
+                info!("Apply safe state {safe_state}");
+                pfd_state
+                    .apply_safe_state(safe_state)
+                    .context(pfd_state.context("applying safe state"))?;
+            }
+        }
+        Ok(pfd)
+    }
+}
+
+/// The GPIOs (BCM numbering) used by an SPI bus for its clock, data and chip selects.
+fn spi_bus_gpios(spi_bus: SpiBus) -> &'static [u8] {
+    match spi_bus {
+        SpiBus::Spi0 => &[7, 8, 9, 10, 11],
+        SpiBus::Spi1 => &[16, 17, 18, 19, 20, 21],
+        // Only on the Compute Module, on GPIOs beyond the header.
+        SpiBus::Spi2 => &[],
+        SpiBus::Spi3 => &[0, 1, 2, 3, 24],
+        SpiBus::Spi4 => &[4, 5, 6, 7, 25],
+        SpiBus::Spi5 => &[12, 13, 14, 15, 26],
+        SpiBus::Spi6 => &[18, 19, 20, 21, 27],
+    }
+}
+
+impl PiFaceDigital {
+    /// The Raspberry Pi GPIO (BCM numbering) that the PiFace Digital connects the
+    /// MCP23S17's interrupt output to.
+    pub const DEFAULT_INTERRUPT_GPIO: u8 = 25;
+
+    /// The highest numbered GPIO (BCM numbering) on the Raspberry Pi's header.
+    pub const MAX_INTERRUPT_GPIO: u8 = 27;
+
+    /// Start building a [`PiFaceDigital`] from named settings.
+    pub fn builder() -> PiFaceDigitalBuilder {
+        PiFaceDigitalBuilder::default()
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Drive the outputs to the safe state.
+    pub(crate) fn apply_safe_state(&mut self, safe_state: PinSet) -> Result<()> {
+        let olata = safe_state.bits();
+        self.retry(|mcp23s17| Ok(mcp23s17.write(RegisterAddress::OLATA, olata)?))
+            .context(self.board_context().register(RegisterAddress::OLATA))?;
+        self.expected.olata = olata;
+        Ok(())
+    }
+}
+
+impl Drop for PiFaceDigitalState {
+    /// Return the outputs to the safe state, if there is one, once the board and all
+    /// its pins have gone.
+    fn drop(&mut self) {
+        if let Some(safe_state) = self.safe_state {
+            info!("Return outputs to safe state {safe_state}");
+            if let Err(e) = self.apply_safe_state(safe_state) {
+                warn!("Failed to apply safe state on Drop: {e}");
+            }
+        }
+    }
+}
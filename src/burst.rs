@@ -0,0 +1,144 @@
+//! Reads of consecutive registers in one SPI transaction.
+//!
+//! [`PiFaceDigital::init()`][crate::PiFaceDigital::init()] enables the MCP23S17's
+//! sequential mode, in which the address pointer advances after each byte clocked
+//! out, so a run of consecutive registers can be read in one transaction of the
+//! control byte, the start address and then a dummy byte per register. For example,
+//! `INTFB`, `INTCAPA`, `INTCAPB`, `GPIOA` and `GPIOB` are adjacent so an interrupt is
+//! serviced in one transaction rather than several, and a snapshot of all the
+//! registers takes one transaction rather than 22.
+//!
+//! `rppal_mcp23s17` only transfers one register at a time, so the bursts are made over
+//! a separate connection to the SPI bus with the same settings.
+
+#[cfg(not(any(test, feature = "mockspi")))]
+use rppal::spi::Spi;
+#[cfg(any(test, feature = "mockspi"))]
+use std::cell::Cell;
+
+use rppal_mcp23s17::{ChipSelect, RegisterAddress, SpiBus, SpiMode};
+
+use crate::{HardwareAddress, Mcp23s17, Result};
+
+/// Makes burst reads of consecutive MCP23S17 registers.
+#[derive(Debug)]
+pub(crate) struct BurstReader {
+    #[cfg(not(any(test, feature = "mockspi")))]
+    spi: Spi,
+    #[cfg(not(any(test, feature = "mockspi")))]
+    control_byte: u8,
+    #[cfg(any(test, feature = "mockspi"))]
+    transactions: Cell<usize>,
+    #[cfg(test)]
+    failing_reads: Cell<usize>,
+}
+
+impl BurstReader {
+    /// Open the SPI bus for burst reads from the device at `address`.
+    #[cfg(not(any(test, feature = "mockspi")))]
+    pub(crate) fn open(
+        address: HardwareAddress,
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+    ) -> Result<Self> {
+        use rppal_mcp23s17::Mcp23s17Error;
+
+        let spi = Spi::new(spi_bus, chip_select.into(), spi_clock, spi_mode)
+            .map_err(Mcp23s17Error::from)?;
+        Ok(BurstReader {
+            spi,
+            control_byte: 0x41 | u8::from(address) << 1,
+        })
+    }
+
+    /// Mock version of opening the SPI bus, which has nothing to open.
+    #[cfg(any(test, feature = "mockspi"))]
+    pub(crate) fn open(
+        _address: HardwareAddress,
+        _spi_bus: SpiBus,
+        _chip_select: ChipSelect,
+        _spi_clock: u32,
+        _spi_mode: SpiMode,
+    ) -> Result<Self> {
+        Ok(BurstReader {
+            transactions: Cell::new(0),
+            #[cfg(test)]
+            failing_reads: Cell::new(0),
+        })
+    }
+
+    /// Read `data.len()` consecutive registers starting at `start` in one transaction.
+    ///
+    /// The `mcp23s17` is only used by the mock version.
+    #[cfg(not(any(test, feature = "mockspi")))]
+    pub(crate) fn read(
+        &self,
+        _mcp23s17: &Mcp23s17,
+        start: RegisterAddress,
+        data: &mut [u8],
+    ) -> Result<()> {
+        self.transfer(start, data)
+    }
+
+    /// Read `data.len()` consecutive registers starting at `start` in one transaction
+    /// without reference to the main connection, which can't be shared between
+    /// threads.
+    #[cfg(not(any(test, feature = "mockspi")))]
+    pub(crate) fn transfer(&self, start: RegisterAddress, data: &mut [u8]) -> Result<()> {
+        use rppal_mcp23s17::Mcp23s17Error;
+
+        debug_assert!(start as usize + data.len() <= RegisterAddress::LENGTH);
+        let length = data.len() + 2;
+        let mut read_buffer = [0u8; RegisterAddress::LENGTH + 2];
+        let mut write_buffer = [0u8; RegisterAddress::LENGTH + 2];
+        write_buffer[0] = self.control_byte;
+        write_buffer[1] = start as u8;
+        let read_length = self
+            .spi
+            .transfer(&mut read_buffer[..length], &write_buffer[..length])
+            .map_err(Mcp23s17Error::from)?;
+        if read_length != length {
+            return Err(Mcp23s17Error::UnexpectedReadLength(read_length).into());
+        }
+        data.copy_from_slice(&read_buffer[2..length]);
+        Ok(())
+    }
+
+    /// Mock version of a burst read, which collects the mock registers' data.
+    ///
+    /// The burst is counted as one transaction, rather than as a read of each of the
+    /// registers by the mock SPI.
+    #[cfg(any(test, feature = "mockspi"))]
+    pub(crate) fn read(
+        &self,
+        mcp23s17: &Mcp23s17,
+        start: RegisterAddress,
+        data: &mut [u8],
+    ) -> Result<()> {
+        #[cfg(test)]
+        if self.failing_reads.get() > 0 {
+            self.failing_reads.set(self.failing_reads.get() - 1);
+            return Err(rppal_mcp23s17::Mcp23s17Error::UnexpectedReadLength(0).into());
+        }
+        for (offset, byte) in data.iter_mut().enumerate() {
+            let register = RegisterAddress::try_from(start as usize + offset)?;
+            *byte = mcp23s17.get_mock_data(register).0;
+        }
+        self.transactions.set(self.transactions.get() + 1);
+        Ok(())
+    }
+
+    /// The number of burst reads made by the mock.
+    #[cfg(any(test, feature = "mockspi"))]
+    pub(crate) fn transactions(&self) -> usize {
+        self.transactions.get()
+    }
+
+    /// Make the mock's next `count` burst reads fail as if the SPI transfer was short.
+    #[cfg(test)]
+    pub(crate) fn fail_reads(&self, count: usize) {
+        self.failing_reads.set(count);
+    }
+}
@@ -0,0 +1,175 @@
+//! Cached input state for boards whose inputs are all covered by interrupts.
+//!
+//! Reading an [`InputPin`][crate::InputPin] normally costs an SPI transaction. But if
+//! interrupts are enabled on a pin for both edges (its bit is set in `GPINTENB` and
+//! clear in `INTCONB`) its level can't change without the MCP23S17 raising an
+//! interrupt, and every interrupt is serviced with a burst that includes `GPIOB`. So
+//! once the input cache is enabled, the driver keeps the most recent `GPIOB` in memory
+//! and reads of covered pins are answered from it.
+//!
+//! Pins whose interrupts compare against `DEFVALB` (set in `INTCONB`, for
+//! [`InterruptMode::ActiveHigh`] and [`InterruptMode::ActiveLow`]) aren't covered: they
+//! raise no interrupt when they return to the reference level.
+//!
+//! [`InterruptMode::ActiveHigh`]: crate::InterruptMode::ActiveHigh
+//! [`InterruptMode::ActiveLow`]: crate::InterruptMode::ActiveLow
+//!
+//! The cache is only trusted for a limited time: an interrupt that is missed (for
+//! example, because the interrupt line was stuck) would otherwise leave the cache wrong
+//! indefinitely. Once the cached inputs are older than the staleness bound, the next
+//! read goes to the device and refreshes the cache.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+
+use crate::{PiFaceDigital, PiFaceDigitalState, PinSet, RegisterAddress, Result, context::Context};
+
+/// The most recent contents of `GPIOB` and when they were read.
+#[derive(Clone, Copy, Debug)]
+struct CachedInputs {
+    inputs: PinSet,
+    read_at: Instant,
+}
+
+/// The input cache.
+///
+/// The cached inputs are shared so that interrupts read in the background (see
+/// [`InterruptReader`][crate::dispatch::InterruptReader]) keep them up to date, whether
+/// or not the cache is enabled.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InputCache {
+    /// The staleness bound, or `None` if the cache is disabled.
+    max_age: Option<Duration>,
+    latest: Arc<Mutex<Option<CachedInputs>>>,
+}
+
+impl InputCache {
+    /// Is the cache enabled?
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_age.is_some()
+    }
+
+    /// Get the cached inputs if the cache is enabled and they are within the staleness
+    /// bound.
+    fn get(&self) -> Option<PinSet> {
+        let max_age = self.max_age?;
+        let latest = (*self.latest.lock().ok()?)?;
+        (latest.read_at.elapsed() < max_age).then_some(latest.inputs)
+    }
+
+    /// Record the contents of `GPIOB` read at `read_at`.
+    pub(crate) fn update(&self, inputs: PinSet, read_at: Instant) {
+        if let Ok(mut latest) = self.latest.lock() {
+            // An interrupt read in the background can arrive after a later read by the
+            // main thread, so only ever move forward.
+            if latest.is_none_or(|latest| latest.read_at <= read_at) {
+                *latest = Some(CachedInputs { inputs, read_at });
+            }
+        }
+    }
+
+    /// Discard the cached inputs so that the next read goes to the device.
+    pub(crate) fn invalidate(&self) {
+        if let Ok(mut latest) = self.latest.lock() {
+            *latest = None;
+        }
+    }
+}
+
+impl PiFaceDigital {
+    /// Enable the input cache.
+    ///
+    /// Once enabled, [`InputPin::read()`][crate::InputPin::read()] and
+    /// [`PiFaceDigital::read_inputs()`] answer from an in-memory copy of `GPIOB`,
+    /// without any SPI transaction, whenever interrupts are enabled on all the pins
+    /// being read with [`InterruptMode::BothEdges`][crate::InterruptMode::BothEdges]
+    /// and the copy was read no more than `max_age` ago. The copy is refreshed by every
+    /// interrupt serviced and by every read that has to go to the device.
+    ///
+    /// If the interrupt line is asserted when a read is made (and interrupts aren't
+    /// being read in the background) the interrupt is serviced first, so the read
+    /// reflects it and the interrupt is queued for the pins as usual.
+    ///
+    /// `max_age` bounds how long a missed interrupt can go unnoticed. Pins without
+    /// interrupts on both edges are always read from the device, as they can change
+    /// without raising an interrupt.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use rppal_pfd::{ChipSelect, HardwareAddress, InterruptMode, PiFaceDigital, SpiBus, SpiMode};
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    /// pfd.init().expect("Failed to initialise!");
+    /// pfd.enable_input_cache(Duration::from_secs(1));
+    ///
+    /// let switch = pfd
+    ///     .get_pull_up_input_pin(0)
+    ///     .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+    ///     .expect("Bad pin");
+    ///
+    /// // Only the first read in each second touches the SPI bus.
+    /// loop {
+    ///     if switch.is_low().expect("Bad read") {
+    ///         println!("Pressed");
+    ///     }
+    /// }
+    /// ```
+    pub fn enable_input_cache(&self, max_age: Duration) {
+        info!("Enable input cache with maximum age {max_age:?}");
+        let input_cache = &mut self.pfd_state.borrow_mut().input_cache;
+        input_cache.max_age = Some(max_age);
+        // Reads made while the cache was disabled may have cleared interrupts without
+        // updating it.
+        input_cache.invalidate();
+    }
+
+    /// Disable the input cache so that every read goes to the device.
+    pub fn disable_input_cache(&self) {
+        let input_cache = &mut self.pfd_state.borrow_mut().input_cache;
+        input_cache.max_age = None;
+        input_cache.invalidate();
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Read the input pins, answering from the cache if it is enabled, fresh and all of
+    /// `pins` have interrupts enabled on both edges.
+    ///
+    /// Returns the levels of all the inputs, not just those of `pins`.
+    pub(crate) fn read_inputs(&mut self, pins: PinSet) -> Result<PinSet> {
+        #[cfg(any(test, feature = "mockspi"))]
+        self.pump_virtual_board();
+        if self.input_cache.is_enabled() {
+            // Reading GPIOB would clear a pending interrupt without queueing it.
+            if self.interrupt_line_asserted() && !self.reads_interrupts_in_background() {
+                self.read_and_queue_interrupt()?;
+            }
+            // Pins comparing against DEFVALB raise no interrupt on returning to it.
+            let covered = self.expected.gpintenb & !self.expected.intconb;
+            if pins.is_subset(PinSet::from_bits(covered)) {
+                if let Some(inputs) = self.input_cache.get() {
+                    debug!("Inputs {inputs} read from cache");
+                    return Ok(inputs);
+                }
+            }
+        }
+
+        let read_at = Instant::now();
+        let inputs = self
+            .retry(|mcp23s17| Ok(mcp23s17.read(RegisterAddress::GPIOB)?))
+            .map(PinSet::from_bits)
+            .context(self.board_context().register(RegisterAddress::GPIOB))?;
+        self.input_cache.update(inputs, read_at);
+        Ok(inputs)
+    }
+}
@@ -0,0 +1,252 @@
+//! Automatic selection of the SPI clock rate.
+//!
+//! The maximum reliable SPI clock depends on the length and quality of the connection
+//! between the Raspberry Pi and the PiFace Digital rather than just the MCP23S17's
+//! 10MHz limit. Calibration sweeps a range of clock rates, exercising a register with
+//! a test pattern at each rate, and then settles on the highest rate that was
+//! completely reliable less a safety margin.
+
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::{
+    Mcp23s17, PiFaceDigital, PiFaceDigitalError, RegisterAddress, Result, burst::BurstReader,
+};
+
+/// Results of exercising the SPI bus at one clock rate during calibration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockStep {
+    /// The SPI clock rate (Hz).
+    pub clock: u32,
+    /// Number of test patterns that were written and read back correctly.
+    pub good: u32,
+    /// Number of test patterns that failed to read back correctly.
+    pub bad: u32,
+    /// Time taken to exercise the bus at this clock rate.
+    pub duration: Duration,
+}
+
+impl ClockStep {
+    /// Was the bus completely reliable at this clock rate?
+    pub fn is_reliable(&self) -> bool {
+        self.bad == 0
+    }
+}
+
+impl fmt::Display for ClockStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Speed: {}  Good: {} Bad: {}  Duration: {:?}",
+            self.clock, self.good, self.bad, self.duration
+        )
+    }
+}
+
+/// Report of a calibration of the SPI clock rate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClockCalibration {
+    /// The results at each clock rate tried, in ascending order of clock rate.
+    pub steps: Vec<ClockStep>,
+    /// The highest clock rate at which the bus was reliable (Hz).
+    pub highest_reliable_clock: u32,
+    /// The clock rate that the bus has been re-opened at (Hz).
+    pub selected_clock: u32,
+}
+
+impl PiFaceDigital {
+    /// The highest SPI clock rate that the MCP23S17 supports (Hz).
+    pub const MAX_SPI_CLOCK: u32 = 10_000_000;
+
+    /// Number of clock rates tried across the calibration range.
+    pub const CALIBRATION_STEPS: u32 = 20;
+
+    /// Number of test patterns written and read back at each clock rate.
+    pub const CALIBRATION_PATTERNS: u32 = 100;
+
+    /// Percentage below the highest reliable clock rate that gets selected.
+    pub const CALIBRATION_MARGIN_PERCENT: u32 = 25;
+
+    /// Find the highest reliable SPI clock rate within `range` and re-open the SPI bus
+    /// at that rate less a safety margin.
+    ///
+    /// [`PiFaceDigital::CALIBRATION_STEPS`] clock rates evenly spread across `range`
+    /// are tried in ascending order. At each, [`PiFaceDigital::CALIBRATION_PATTERNS`]
+    /// test patterns are written to and read back from the `DEFVALA` register (which
+    /// has no effect because interrupts are never enabled on the output port). The
+    /// sweep stops at the first rate that is not completely reliable. The selected
+    /// rate is [`PiFaceDigital::CALIBRATION_MARGIN_PERCENT`] below the highest reliable
+    /// rate and `DEFVALA` is restored before returning.
+    ///
+    /// The bus can only be re-opened when there are no [`InputPin`][crate::InputPin]s
+    /// or [`OutputPin`][crate::OutputPin]s in use and interrupts have never been
+    /// handled in the background (through callbacks, subscriptions or the interrupt
+    /// file descriptor), whose connections to the device would be left at the old
+    /// rate. Otherwise [`PiFaceDigitalError::PinsInUse`] is returned. If the bus isn't
+    /// reliable even at the bottom of `range`,
+    /// [`PiFaceDigitalError::ClockCalibrationFailed`] is returned. Whatever the error,
+    /// the bus is left at its original clock rate and `DEFVALA` is restored.
+    ///
+    /// ```no_run
+    /// use rppal_pfd::{ChipSelect, HardwareAddress, PiFaceDigital, SpiBus, SpiMode};
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    /// pfd.init().expect("Failed to initialise!");
+    ///
+    /// let calibration = pfd.calibrate_clock(100_000..=5_000_000).expect("Calibration failed");
+    /// for step in &calibration.steps {
+    ///     println!("{step}");
+    /// }
+    /// println!("SPI clock now {} Hz", calibration.selected_clock);
+    /// ```
+    pub fn calibrate_clock(&mut self, range: RangeInclusive<u32>) -> Result<ClockCalibration> {
+        let (&start, &end) = (range.start(), range.end());
+        for clock in [start, end] {
+            if clock == 0 || clock > Self::MAX_SPI_CLOCK {
+                return Err(PiFaceDigitalError::SpiClockOutOfRange(clock));
+            }
+        }
+        let in_use = {
+            let pfd_state = self.pfd_state.borrow();
+            Rc::strong_count(&self.pfd_state) > 1
+                || pfd_state.callback_dispatcher.is_some()
+                || pfd_state.interrupt_notifier.is_some()
+                || pfd_state.background_interrupts.is_some()
+        };
+        if in_use {
+            return Err(PiFaceDigitalError::PinsInUse);
+        }
+
+        let defvala = self
+            .pfd_state
+            .borrow()
+            .mcp23s17
+            .read(RegisterAddress::DEFVALA)?;
+        self.sweep_clock(start, end, defvala).inspect_err(|_| {
+            if let Err(e) = self
+                .pfd_state
+                .borrow()
+                .mcp23s17
+                .write(RegisterAddress::DEFVALA, defvala)
+            {
+                warn!("Failed to restore DEFVALA after calibration: {e}");
+            }
+        })
+    }
+
+    /// Sweep the clock rates from `start` to `end` and re-open the bus at the selected
+    /// rate, restoring `DEFVALA` on the new connection.
+    fn sweep_clock(&mut self, start: u32, end: u32, defvala: u8) -> Result<ClockCalibration> {
+        let (address, spi_bus, chip_select, spi_mode) = {
+            let pfd_state = self.pfd_state.borrow();
+            (
+                pfd_state.mcp23s17.get_hardware_address(),
+                pfd_state.mcp23s17.get_spi_bus(),
+                pfd_state.chip_select,
+                pfd_state.spi_mode,
+            )
+        };
+
+        let step_size = (end.saturating_sub(start) / (Self::CALIBRATION_STEPS - 1)).max(1);
+        let mut steps = Vec::new();
+        for clock in (start..=end).step_by(step_size as usize) {
+            let mcp23s17 = Mcp23s17::new(address, spi_bus, chip_select, clock, spi_mode)?;
+            let step = exercise(&mcp23s17, clock);
+            info!("Calibration {step}");
+            steps.push(step);
+            if !step.is_reliable() {
+                break;
+            }
+        }
+
+        let Some(highest_reliable_clock) = steps
+            .iter()
+            .take_while(|step| step.is_reliable())
+            .map(|step| step.clock)
+            .last()
+        else {
+            warn!("SPI bus unreliable at all clock rates from {start} Hz");
+            return Err(PiFaceDigitalError::ClockCalibrationFailed { clock: start });
+        };
+        let selected_clock = (highest_reliable_clock as u64
+            * (100 - Self::CALIBRATION_MARGIN_PERCENT) as u64
+            / 100) as u32;
+
+        info!("Re-open SPI bus at {selected_clock} Hz");
+        let mcp23s17 = Mcp23s17::new(address, spi_bus, chip_select, selected_clock, spi_mode)?;
+        let burst = BurstReader::open(
+            address.try_into()?,
+            spi_bus,
+            chip_select,
+            selected_clock,
+            spi_mode,
+        )?;
+
+        // The mock SPI holds its simulated registers per instance whereas the real
+        // device keeps its state, so carry the simulated registers across.
+        #[cfg(any(test, feature = "mockspi"))]
+        for register in 0..RegisterAddress::LENGTH {
+            let register = RegisterAddress::try_from(register).unwrap();
+            let data = self.pfd_state.borrow().mcp23s17.get_mock_data(register).0;
+            mcp23s17.set_mock_data(register, data);
+        }
+
+        mcp23s17.write(RegisterAddress::DEFVALA, defvala)?;
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        pfd_state.mcp23s17 = mcp23s17;
+        pfd_state.burst = Rc::new(burst);
+        pfd_state.spi_clock = selected_clock;
+
+        Ok(ClockCalibration {
+            steps,
+            highest_reliable_clock,
+            selected_clock,
+        })
+    }
+
+    /// Get the SPI clock rate (Hz) that the bus is currently opened at.
+    pub fn spi_clock(&self) -> u32 {
+        self.pfd_state.borrow().spi_clock
+    }
+}
+
+/// Write and read back test patterns at one clock rate.
+///
+/// Any SPI errors are counted as failures rather than aborting the calibration.
+fn exercise(mcp23s17: &Mcp23s17, clock: u32) -> ClockStep {
+    let mut good = 0;
+    let mut bad = 0;
+    let start_time = Instant::now();
+    for i in 0..PiFaceDigital::CALIBRATION_PATTERNS {
+        // Alternate walking-ones with their inverse to exercise every bit both ways.
+        let pattern = match i & 1 {
+            0 => 0x01 << (i / 2 % 8),
+            _ => !(0x01 << (i / 2 % 8)),
+        };
+        match mcp23s17
+            .write(RegisterAddress::DEFVALA, pattern)
+            .and_then(|_| mcp23s17.read(RegisterAddress::DEFVALA))
+        {
+            Ok(data) if data == pattern => good += 1,
+            _ => bad += 1,
+        }
+    }
+    ClockStep {
+        clock,
+        good,
+        bad,
+        duration: start_time.elapsed(),
+    }
+}
@@ -0,0 +1,444 @@
+//! Interrupt callbacks.
+//!
+//! Callbacks registered with [`InputPin::on_change()`] (and friends) or
+//! [`PiFaceDigital::subscribe_async_interrupts()`] are run on a dispatcher thread owned
+//! by the driver rather than on the caller's thread. The interrupt is decoded before
+//! the callbacks are run, so they never need to access the device themselves.
+//!
+//! On the Raspberry Pi, registering the first callback starts watching the GPIO
+//! interrupt line in the background. The MCP23S17's interrupt flags and capture are
+//! read over a separate connection to the SPI bus because the main one can't be
+//! shared between threads, retrying according to the board's [`RetryPolicy`] but
+//! without escalating. Each interrupt read is passed to the callbacks and held for the
+//! synchronous polls, which keep working and take their interrupts from there rather
+//! than from the device. Interrupts serviced by the synchronous polls are also passed
+//! to the callbacks.
+//!
+//! The callbacks are run without holding the lock on the registrations, so a slow
+//! callback doesn't hold up registering or dropping another, and a callback that
+//! panics is logged and skipped rather than stopping the dispatcher.
+//!
+//! The watcher needs the GPIO so it's compiled out of the tests and `mockspi` builds,
+//! which means that the tests can't exercise it; only the hand-off of the interrupts
+//! to the polls is tested.
+//!
+//! [`RetryPolicy`]: crate::RetryPolicy
+
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use log::{debug, error, info};
+
+use crate::{
+    InputPin, InputPinInner, InterruptsEnabled, Level, PfdInterrupt, PiFaceDigital,
+    PiFaceDigitalError, PiFaceDigitalState, PinNumber, Result, subscription::SubscriberQueue,
+};
+
+/// The direction of the change on an input that raised an interrupt.
+///
+/// The edge is derived from the level captured with the interrupt, so it's only a
+/// true edge for pins interrupting on [`InterruptMode::BothEdges`]. A pin in compare
+/// mode (see [`InputPin::into_compare_interrupt()`]), or with
+/// [`InterruptMode::ActiveHigh`] or [`InterruptMode::ActiveLow`], interrupts again
+/// and again while it differs from its reference level and not at all when it
+/// returns to it. So its interrupts are all reported with the same edge, the one
+/// away from the reference level.
+///
+/// [`InterruptMode::BothEdges`]: crate::InterruptMode::BothEdges
+/// [`InterruptMode::ActiveHigh`]: crate::InterruptMode::ActiveHigh
+/// [`InterruptMode::ActiveLow`]: crate::InterruptMode::ActiveLow
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// The input changed to [`Level::High`].
+    Rising,
+    /// The input changed to [`Level::Low`].
+    Falling,
+}
+
+impl From<Level> for Edge {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::High => Edge::Rising,
+            Level::Low => Edge::Falling,
+        }
+    }
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Edge::Rising => fmt::Display::fmt("Rising", f),
+            Edge::Falling => fmt::Display::fmt("Falling", f),
+        }
+    }
+}
+
+/// A decoded interrupt on one input pin, as passed to the pin's callbacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PinChange {
+    /// The pin that raised the interrupt.
+    pub pin: PinNumber,
+    /// The direction of the change.
+    pub edge: Edge,
+    /// The level on the pin captured by the MCP23S17 when the interrupt happened.
+    pub level: Level,
+}
+
+type Callback = Box<dyn FnMut(PinChange) + Send>;
+type BoardCallback = Box<dyn FnMut(PfdInterrupt) + Send>;
+
+/// A callback registered for one pin, optionally only for changes in one direction.
+///
+/// Each callback has a lock of its own so that it can be run once the registrations
+/// have been unlocked.
+struct Registration {
+    pin: PinNumber,
+    edge: Option<Edge>,
+    callback: Arc<Mutex<Callback>>,
+}
+
+/// The callbacks run by the dispatcher thread.
+#[derive(Default)]
+pub(crate) struct Callbacks {
+    /// Per-pin callbacks.
+    pins: Vec<Registration>,
+    /// Callback for every interrupt on the board.
+    board: Option<Arc<Mutex<BoardCallback>>>,
+    /// Queues of the broadcast subscribers.
+    pub(crate) subscribers: Vec<Arc<SubscriberQueue>>,
+}
+
+/// Messages to the dispatcher thread.
+pub(crate) enum DispatchMessage {
+    /// An interrupt to pass to the callbacks.
+    Interrupt(PfdInterrupt),
+    /// Stop the dispatcher thread.
+    Stop,
+}
+
+/// The dispatcher thread and the callbacks that it runs.
+pub(crate) struct CallbackDispatcher {
+    callbacks: Arc<Mutex<Callbacks>>,
+    sender: Sender<DispatchMessage>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl fmt::Debug for CallbackDispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registrations = self.callbacks.lock().map(|c| c.pins.len()).unwrap_or(0);
+        f.debug_struct("CallbackDispatcher")
+            .field("registrations", &registrations)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CallbackDispatcher {
+    /// Start the dispatcher thread.
+    fn new() -> Result<Self> {
+        let callbacks: Arc<Mutex<Callbacks>> = Arc::default();
+        let (sender, receiver) = mpsc::channel();
+        let thread_callbacks = Arc::clone(&callbacks);
+        let thread = thread::Builder::new()
+            .name("pfd-callbacks".to_string())
+            .spawn(move || {
+                while let Ok(DispatchMessage::Interrupt(interrupt)) = receiver.recv() {
+                    let (board, pins, subscribers) = {
+                        let Ok(mut callbacks) = thread_callbacks.lock() else {
+                            error!("Callbacks poisoned - stopping dispatcher");
+                            return;
+                        };
+                        let mut pins = Vec::new();
+                        for change in interrupt.changes() {
+                            for registration in &callbacks.pins {
+                                if registration.pin == change.pin
+                                    && registration.edge.is_none_or(|edge| edge == change.edge)
+                                {
+                                    pins.push((change, Arc::clone(&registration.callback)));
+                                }
+                            }
+                        }
+                        callbacks.subscribers.retain(|queue| !queue.is_closed());
+                        (callbacks.board.clone(), pins, callbacks.subscribers.clone())
+                    };
+                    if let Some(board) = board {
+                        run_callback(&board, interrupt);
+                    }
+                    for (change, callback) in pins {
+                        run_callback(&callback, change);
+                    }
+                    // A subscriber with Backpressure::Block can hold the dispatcher up
+                    // indefinitely, so the callbacks mustn't be locked meanwhile.
+                    for change in interrupt.changes() {
+                        for queue in &subscribers {
+                            queue.offer(change);
+                        }
+                    }
+                }
+                debug!("Callback dispatcher stopped");
+            })
+            .map_err(|source| PiFaceDigitalError::CallbackDispatcherFailed { source })?;
+        info!("Callback dispatcher started");
+        Ok(CallbackDispatcher {
+            callbacks,
+            sender,
+            thread: Some(thread),
+        })
+    }
+
+    /// Pass an interrupt to the dispatcher thread to run the callbacks.
+    pub(crate) fn dispatch(&self, interrupt: PfdInterrupt) {
+        // The thread only goes away early if the callbacks were poisoned, which has been
+        // reported.
+        let _ = self.sender.send(DispatchMessage::Interrupt(interrupt));
+    }
+
+    /// Run `f` on the callbacks.
+    pub(crate) fn with_callbacks(&self, f: impl FnOnce(&mut Callbacks)) {
+        match self.callbacks.lock() {
+            Ok(mut callbacks) => f(&mut callbacks),
+            Err(_) => error!("Callbacks poisoned"),
+        }
+    }
+}
+
+/// Run a callback, logging rather than propagating any panic.
+fn run_callback<T>(callback: &Mutex<Box<dyn FnMut(T) + Send>>, arg: T) {
+    // The panic is caught while the callback is still locked, so it isn't poisoned.
+    let Ok(mut callback) = callback.lock() else {
+        return;
+    };
+    if panic::catch_unwind(AssertUnwindSafe(|| callback(arg))).is_err() {
+        error!("Interrupt callback panicked");
+    }
+}
+
+impl Drop for CallbackDispatcher {
+    fn drop(&mut self) {
+        // Let the subscribers know that there will be no more events first, which also
+        // releases the dispatcher thread if it is waiting for a subscriber that has
+        // stopped receiving.
+        self.with_callbacks(|callbacks| {
+            for queue in callbacks.subscribers.drain(..) {
+                queue.close();
+            }
+        });
+        // Stop explicitly as the GPIO interrupt thread may still hold a sender.
+        let _ = self.sender.send(DispatchMessage::Stop);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Callback dispatcher thread panicked");
+            }
+        }
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Get the callback dispatcher, starting it if it isn't already running.
+    pub(crate) fn callback_dispatcher(&mut self) -> Result<&CallbackDispatcher> {
+        if self.callback_dispatcher.is_none() {
+            self.callback_dispatcher = Some(CallbackDispatcher::new()?);
+            #[cfg(not(any(test, feature = "mockspi")))]
+            self.watch_interrupt_line()?;
+        }
+        Ok(self.callback_dispatcher.as_ref().unwrap())
+    }
+
+    /// Watch the GPIO interrupt line in the background.
+    ///
+    /// Once callbacks or the interrupt file descriptor are in use, the interrupt
+    /// registers are read over a separate SPI connection as soon as the line is
+    /// asserted. Each interrupt is held for the polls and
+    /// [`PiFaceDigital::service_interrupt()`] to take, passed to the dispatcher and
+    /// signalled on the interrupt file descriptor. Called again whenever either starts
+    /// being used, replacing the previous watch.
+    #[cfg(not(any(test, feature = "mockspi")))]
+    pub(crate) fn watch_interrupt_line(&mut self) -> Result<()> {
+        use crate::{LineWatchdog, dispatch::InterruptReader};
+        use rppal::gpio::Trigger;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let background = self.background_interrupts.clone().unwrap_or_default();
+        let delivery = InterruptDelivery {
+            background: Arc::clone(&background),
+            sender: self
+                .callback_dispatcher
+                .as_ref()
+                .map(|dispatcher| dispatcher.sender.clone()),
+            notifier: self.interrupt_notifier.clone(),
+        };
+        let reader = Arc::new(Mutex::new(InterruptReader::new(self)?));
+        let delivered = Arc::new(AtomicUsize::new(0));
+        self.line_watchdog = None;
+        self.line_watchdog = Some(LineWatchdog::start(
+            Arc::clone(&self.interrupt_pin),
+            Arc::clone(&reader),
+            Arc::clone(&delivered),
+            Arc::clone(&self.interrupt_recoveries),
+            delivery.clone(),
+        )?);
+        self.interrupt_reader = Some(Arc::clone(&reader));
+        self.lock_interrupt_pin()
+            .set_async_interrupt(Trigger::FallingEdge, None, move |_| {
+                let Ok(reader) = reader.lock() else {
+                    error!("Interrupt reader poisoned");
+                    return;
+                };
+                match reader.read() {
+                    Ok(interrupt) => {
+                        delivered.fetch_add(1, Ordering::Relaxed);
+                        delivery.deliver(interrupt);
+                    }
+                    Err(e) => error!("Failed to read interrupt registers: {e}"),
+                }
+            })?;
+        // From now on the polls take the interrupts read in the background.
+        self.background_interrupts = Some(background);
+        Ok(())
+    }
+}
+
+/// Where the interrupts read in the background go.
+#[cfg(not(any(test, feature = "mockspi")))]
+#[derive(Clone)]
+pub(crate) struct InterruptDelivery {
+    /// Held for the polls and `service_interrupt()`.
+    background: Arc<crate::dispatch::BackgroundInterrupts>,
+    /// The callback dispatcher, if callbacks are in use.
+    sender: Option<Sender<DispatchMessage>>,
+    /// The interrupt file descriptor, if there is one.
+    notifier: Option<Arc<crate::notify::InterruptNotifier>>,
+}
+
+#[cfg(not(any(test, feature = "mockspi")))]
+impl InterruptDelivery {
+    pub(crate) fn deliver(&self, interrupt: PfdInterrupt) {
+        self.background.push(interrupt);
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(DispatchMessage::Interrupt(interrupt));
+        }
+        if let Some(notifier) = &self.notifier {
+            notifier.wake();
+        }
+    }
+}
+
+impl PiFaceDigital {
+    // Asynchronous interrupt subscription (actual docs are included from a separate file
+    // because they are extensive).
+    #[doc = include_str!("async-interrupts.md")]
+    pub fn subscribe_async_interrupts(
+        &mut self,
+        callback: impl FnMut(PfdInterrupt) + Send + 'static,
+    ) -> Result<()> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        pfd_state
+            .callback_dispatcher()?
+            .with_callbacks(|callbacks| {
+                callbacks.board = Some(Arc::new(Mutex::new(Box::new(callback))))
+            });
+        pfd_state.clear_pending_interrupt()
+    }
+
+    /// Clear the registered callback for interrupt notifications.
+    pub fn clear_async_interrupts(&mut self) -> Result<()> {
+        if let Some(dispatcher) = &self.pfd_state.borrow().callback_dispatcher {
+            dispatcher.with_callbacks(|callbacks| callbacks.board = None);
+        }
+        Ok(())
+    }
+}
+
+impl<P> InputPin<P, InterruptsEnabled> {
+    /// Register a callback to be run whenever this pin raises an interrupt.
+    ///
+    /// The callback runs on a dispatcher thread owned by the driver and is given the
+    /// decoded [`PinChange`]. Any number of callbacks can be registered and they are
+    /// all removed when interrupts are disabled or the pin is dropped.
+    ///
+    /// On the Raspberry Pi the interrupt line is watched in the background once a
+    /// callback has been registered. The synchronous polls keep working, taking the
+    /// interrupts read in the background.
+    ///
+    /// ```no_run
+    /// use rppal_pfd::{ChipSelect, HardwareAddress, InterruptMode, PiFaceDigital, SpiBus, SpiMode};
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    /// pfd.init().expect("Failed to initialise!");
+    ///
+    /// let mut button = pfd
+    ///     .get_pull_up_input_pin(0)
+    ///     .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+    ///     .expect("Bad pin");
+    /// button
+    ///     .on_change(|change| println!("Pin {} {} ({})", change.pin, change.edge, change.level))
+    ///     .expect("Failed to register callback");
+    /// ```
+    pub fn on_change(&mut self, callback: impl FnMut(PinChange) + Send + 'static) -> Result<()> {
+        self.register_callback(None, Box::new(callback))
+    }
+
+    /// Register a callback to be run whenever this pin raises an interrupt on a change
+    /// to [`Level::High`].
+    ///
+    /// See [`InputPin::on_change()`] for details. Only a pin interrupting on both edges
+    /// reports true edges (see [`Edge`]): with a compare reference of [`Level::Low`]
+    /// the callback is run repeatedly for as long as the pin is high, and with one of
+    /// [`Level::High`] it is never run.
+    pub fn on_rising(&mut self, callback: impl FnMut(PinChange) + Send + 'static) -> Result<()> {
+        self.register_callback(Some(Edge::Rising), Box::new(callback))
+    }
+
+    /// Register a callback to be run whenever this pin raises an interrupt on a change
+    /// to [`Level::Low`].
+    ///
+    /// See [`InputPin::on_change()`] for details. Only a pin interrupting on both edges
+    /// reports true edges (see [`Edge`]): with a compare reference of [`Level::High`]
+    /// the callback is run repeatedly for as long as the pin is low, and with one of
+    /// [`Level::Low`] it is never run.
+    pub fn on_falling(&mut self, callback: impl FnMut(PinChange) + Send + 'static) -> Result<()> {
+        self.register_callback(Some(Edge::Falling), Box::new(callback))
+    }
+
+    fn register_callback(&mut self, edge: Option<Edge>, callback: Callback) -> Result<()> {
+        let pin = self.get_pin_number();
+        debug!("Register {edge:?} callback on pin {pin}");
+        let mut pfd_state = self.inner.pfd_state.borrow_mut();
+        pfd_state
+            .callback_dispatcher()?
+            .with_callbacks(|callbacks| {
+                callbacks.pins.push(Registration {
+                    pin,
+                    edge,
+                    callback: Arc::new(Mutex::new(callback)),
+                })
+            });
+        pfd_state.clear_pending_interrupt()
+    }
+}
+
+impl InputPinInner {
+    /// Remove all the callbacks registered for this pin.
+    pub(crate) fn unregister_callbacks(&self) {
+        let pin = self.pin_number();
+        if let Some(dispatcher) = &self.pfd_state.borrow().callback_dispatcher {
+            dispatcher.with_callbacks(|callbacks| {
+                callbacks
+                    .pins
+                    .retain(|registration| registration.pin != pin)
+            });
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Cancellation of blocking interrupt polls.
+//!
+//! [`PiFaceDigital::poll_interrupts()`] and [`InputPin::poll_interrupt()`] can block
+//! indefinitely, which gets in the way of shutting down cleanly. A [`CancelToken`] can
+//! be handed to another thread so that it can make the polls return
+//! [`PiFaceDigitalError::Cancelled`] promptly.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use log::info;
+
+#[cfg(doc)]
+use crate::InputPin;
+use crate::{PiFaceDigital, PiFaceDigitalError, Result};
+
+/// Token for cancelling the blocking interrupt polls on a [`PiFaceDigital`], obtained
+/// from [`PiFaceDigital::cancel_token()`].
+///
+/// Tokens can be cloned and sent to other threads. Cancellation is sticky: every poll
+/// returns [`PiFaceDigitalError::Cancelled`] until the token is reset.
+///
+/// ```no_run
+/// use rppal_pfd::{ChipSelect, HardwareAddress, InterruptMode, PiFaceDigital, PiFaceDigitalError, SpiBus, SpiMode};
+/// use std::{thread, time::Duration};
+///
+/// let mut pfd = PiFaceDigital::new(
+///     HardwareAddress::new(0).unwrap(),
+///     SpiBus::Spi0,
+///     ChipSelect::Cs0,
+///     100_000,
+///     SpiMode::Mode0,
+/// ).expect("Failed to construct!");
+/// pfd.init().expect("Failed to initialise!");
+///
+/// let mut button = pfd
+///     .get_pull_up_input_pin(0)
+///     .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+///     .expect("Bad pin");
+///
+/// let cancel = pfd.cancel_token();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(10));
+///     cancel.cancel();
+/// });
+///
+/// loop {
+///     match button.poll_interrupt(false, None) {
+///         Ok(level) => println!("Button is now {level:?}"),
+///         Err(PiFaceDigitalError::Cancelled) => break,
+///         Err(e) => panic!("Poll failed with {e}"),
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// How often a blocked poll checks for cancellation, which bounds how long the
+    /// poll takes to return once cancelled.
+    pub const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Cancel any blocked polls, and all polls until the token is reset.
+    pub fn cancel(&self) {
+        info!("Interrupt polls cancelled");
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Allow polls again after a cancellation.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Release);
+    }
+
+    /// Has the token been cancelled?
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Return [`PiFaceDigitalError::Cancelled`] if the token has been cancelled.
+    pub(crate) fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(PiFaceDigitalError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+impl PiFaceDigital {
+    /// Get a token that can cancel the blocking interrupt polls on this device.
+    ///
+    /// All the tokens for a device are the same token.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.pfd_state.borrow().cancel_token.clone()
+    }
+}
@@ -0,0 +1,144 @@
+//! Context for errors raised by the MCP23S17 and the GPIO.
+//!
+//! A bare "SPI error" in a log is of little help in working out what went wrong, so
+//! errors from the hardware are annotated with the operation that was in progress,
+//! the board, the pin and the register involved, as far as they are known. Context is
+//! added as the error propagates outwards, so the innermost (most specific) context
+//! takes precedence and outer layers only fill in what is missing.
+
+use std::fmt;
+
+use crate::{
+    HardwareAddress, PiFaceDigitalError, PiFaceDigitalState, PinNumber, RegisterAddress, Result,
+};
+
+/// Where an error from the MCP23S17 or the GPIO happened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The operation that was in progress, e.g. `"reading input pin"`.
+    pub operation: Option<&'static str>,
+    /// The hardware address of the PiFace Digital.
+    pub board: Option<HardwareAddress>,
+    /// The pin number.
+    pub pin: Option<PinNumber>,
+    /// The MCP23S17 register being accessed.
+    pub register: Option<RegisterAddress>,
+}
+
+impl ErrorContext {
+    /// Context for an operation.
+    pub(crate) fn operation(operation: &'static str) -> Self {
+        ErrorContext {
+            operation: Some(operation),
+            ..Default::default()
+        }
+    }
+
+    /// Add the pin number.
+    pub(crate) fn pin(self, pin: PinNumber) -> Self {
+        ErrorContext {
+            pin: Some(pin),
+            ..self
+        }
+    }
+
+    /// Add the register being accessed.
+    pub(crate) fn register(self, register: RegisterAddress) -> Self {
+        ErrorContext {
+            register: Some(register),
+            ..self
+        }
+    }
+
+    /// Add the board address.
+    pub(crate) fn board(self, board: HardwareAddress) -> Self {
+        ErrorContext {
+            board: Some(board),
+            ..self
+        }
+    }
+
+    /// Fill in anything this context is missing from `outer`.
+    fn merge(&mut self, outer: ErrorContext) {
+        self.operation = self.operation.or(outer.operation);
+        self.board = self.board.or(outer.board);
+        self.pin = self.pin.or(outer.pin);
+        self.register = self.register.or(outer.register);
+    }
+
+    /// Is nothing known?
+    pub fn is_empty(&self) -> bool {
+        *self == ErrorContext::default()
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    /// Formats as, for example, `" while reading input pin (board 0, pin 3)"` or as
+    /// nothing if the context is empty, to follow the description of the error.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(operation) = self.operation {
+            write!(f, " while {operation}")?;
+        }
+        let mut details = Vec::new();
+        if let Some(board) = self.board {
+            details.push(format!("board {board}"));
+        }
+        if let Some(pin) = self.pin {
+            details.push(format!("pin {pin}"));
+        }
+        if let Some(register) = self.register {
+            details.push(format!("register {register}"));
+        }
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl PiFaceDigitalError {
+    /// Get the context of an error from the MCP23S17 or GPIO.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            PiFaceDigitalError::Mcp23s17Error { context, .. }
+            | PiFaceDigitalError::GpioError { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+/// Adding context to the errors in results.
+pub(crate) trait Context<T> {
+    /// Fill in any missing context of an error from the MCP23S17 or GPIO.
+    fn context(self, context: ErrorContext) -> Result<T>;
+}
+
+impl<T> Context<T> for Result<T> {
+    fn context(self, outer: ErrorContext) -> Result<T> {
+        self.map_err(|mut e| {
+            if let PiFaceDigitalError::Mcp23s17Error { context, .. }
+            | PiFaceDigitalError::GpioError { context, .. } = &mut e
+            {
+                context.merge(outer);
+            }
+            e
+        })
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Context for an operation on this board.
+    pub(crate) fn context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext {
+            operation: Some(operation),
+            ..self.board_context()
+        }
+    }
+
+    /// Context for anything on this board.
+    pub(crate) fn board_context(&self) -> ErrorContext {
+        ErrorContext::default().board(HardwareAddress(u8::from(
+            self.mcp23s17.get_hardware_address(),
+        )))
+    }
+}
@@ -0,0 +1,405 @@
+//! Central dispatch of interrupts to the pins that raised them.
+//!
+//! All the input pins share one interrupt line and reading `INTCAPB` to find the
+//! levels that caused an interrupt also clears the interrupt condition for every pin.
+//! So whichever caller services an interrupt must record the result for _all_ the
+//! pins that were flagged, not just the ones that it happens to be interested in,
+//! otherwise the others' interrupts are lost.
+//!
+//! The dispatcher reads `INTFB`, `INTCAPB` and `GPIOB` once per interrupt, in one
+//! burst, and queues the captured level for each flagged pin. Polls on any pin, from
+//! any caller, take their pin's events from the queue before waiting for further
+//! interrupts.
+//!
+//! Once the interrupt line is watched in the background (on the Raspberry Pi, when
+//! callbacks or an interrupt file descriptor are in use) the interrupts are read as
+//! they happen and held in [`BackgroundInterrupts`]. The polls then wait on that
+//! rather than on the GPIO, and take the interrupts from it rather than reading the
+//! registers themselves.
+
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    CancelToken, Edge, HealthMonitor, Level, PiFaceDigitalState, PinChange, PinNumber, PinSet,
+    RegisterAddress, Result, context::Context,
+};
+
+/// An interrupt raised by the PiFace Digital.
+///
+/// Carries the contents of the MCP23S17's interrupt flag (`INTFB`), interrupt
+/// capture (`INTCAPB`) and input (`GPIOB`) registers, read when the interrupt was
+/// serviced, so the pins that raised the interrupt and their levels can be decoded
+/// without any further access to the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PfdInterrupt {
+    /// When the driver received the interrupt.
+    pub timestamp: Instant,
+    /// The interrupt flags: the pins that raised the interrupt.
+    pub flags: PinSet,
+    /// The levels on all the input pins captured at the time of the interrupt: the
+    /// pins that were high.
+    pub capture: PinSet,
+    /// The levels on all the input pins when the interrupt was serviced: the pins
+    /// that were high.
+    pub inputs: PinSet,
+}
+
+impl PfdInterrupt {
+    /// Number of registers in the burst from `INTFB` through `INTCAPA`, `INTCAPB` and
+    /// `GPIOA` to `GPIOB`.
+    pub(crate) const BURST_LENGTH: usize = 5;
+
+    /// Create an interrupt received now.
+    pub(crate) fn new(flags: u8, capture: u8, inputs: u8) -> Self {
+        PfdInterrupt {
+            timestamp: Instant::now(),
+            flags: PinSet::from_bits(flags),
+            capture: PinSet::from_bits(capture),
+            inputs: PinSet::from_bits(inputs),
+        }
+    }
+
+    /// Decode the interrupt from a burst read of [`PfdInterrupt::BURST_LENGTH`]
+    /// registers from `INTFB`.
+    pub(crate) fn from_burst(data: [u8; Self::BURST_LENGTH]) -> Self {
+        let [flags, _intcapa, capture, _gpioa, inputs] = data;
+        PfdInterrupt::new(flags, capture, inputs)
+    }
+
+    /// Did the pin raise this interrupt?
+    pub fn is_flagged(&self, pin: PinNumber) -> bool {
+        self.flags.contains(pin)
+    }
+
+    /// The level on the pin captured at the time of the interrupt.
+    pub fn level(&self, pin: PinNumber) -> Level {
+        Level::from(self.capture.contains(pin))
+    }
+
+    /// The changes on each of the pins that raised this interrupt.
+    pub fn changes(&self) -> impl Iterator<Item = PinChange> + '_ {
+        self.flags.iter().map(|pin| {
+            let level = self.level(pin);
+            PinChange {
+                pin,
+                edge: Edge::from(level),
+                level,
+            }
+        })
+    }
+}
+
+/// Per-pin queues of interrupts that have been serviced but not yet polled.
+#[derive(Debug, Default)]
+pub(crate) struct InterruptQueues {
+    queues: [VecDeque<Level>; 8],
+}
+
+impl InterruptQueues {
+    /// Maximum number of interrupts queued for each pin. If a pin isn't polled often
+    /// enough to keep up, its oldest interrupts are discarded.
+    pub(crate) const DEPTH: usize = 16;
+
+    /// Queue the captured level for each pin flagged as having raised the interrupt.
+    pub(crate) fn push(&mut self, interrupt: &PfdInterrupt) {
+        for PinChange { pin, level, .. } in interrupt.changes() {
+            debug!("Queue interrupt on pin {pin} level {level}");
+            let queue = &mut self.queues[usize::from(u8::from(pin))];
+            if queue.len() == Self::DEPTH {
+                warn!("Interrupt queue for pin {pin} full - discarding oldest");
+                queue.pop_front();
+            }
+            queue.push_back(level);
+        }
+    }
+
+    /// Take the oldest queued interrupt for a pin.
+    pub(crate) fn pop(&mut self, pin_no: PinNumber) -> Option<Level> {
+        self.queues[usize::from(u8::from(pin_no))].pop_front()
+    }
+
+    /// Number of interrupts queued for a pin.
+    pub(crate) fn len(&self, pin_no: PinNumber) -> usize {
+        self.queues[usize::from(u8::from(pin_no))].len()
+    }
+
+    /// Discard all the interrupts queued for a pin.
+    pub(crate) fn clear(&mut self, pin_no: PinNumber) {
+        self.queues[usize::from(u8::from(pin_no))].clear();
+    }
+}
+
+/// Interrupts read in the background, waiting to be taken by the polls or
+/// [`PiFaceDigital::service_interrupt()`][crate::PiFaceDigital::service_interrupt()].
+#[derive(Debug, Default)]
+pub(crate) struct BackgroundInterrupts {
+    pending: Mutex<VecDeque<PfdInterrupt>>,
+    arrived: Condvar,
+}
+
+impl BackgroundInterrupts {
+    /// Hold an interrupt read in the background, discarding the oldest if there are
+    /// already [`InterruptQueues::DEPTH`] waiting.
+    #[cfg(any(test, not(feature = "mockspi")))]
+    pub(crate) fn push(&self, interrupt: PfdInterrupt) {
+        if let Ok(mut pending) = self.pending.lock() {
+            if pending.len() == InterruptQueues::DEPTH {
+                warn!("Too many interrupts waiting to be serviced - discarding oldest");
+                pending.pop_front();
+            }
+            pending.push_back(interrupt);
+        }
+        self.arrived.notify_all();
+    }
+
+    /// Take the oldest interrupt waiting.
+    pub(crate) fn pop(&self) -> Option<PfdInterrupt> {
+        self.pending.lock().ok()?.pop_front()
+    }
+
+    /// Wait up to `timeout` (or indefinitely) for an interrupt to be waiting, in
+    /// slices so that the wait can be cancelled.
+    ///
+    /// Returns `false` if the wait timed out.
+    fn wait(&self, cancel_token: &CancelToken, timeout: Option<Duration>) -> Result<bool> {
+        let wait_until = timeout.map(|delay| Instant::now() + delay);
+        let Ok(mut pending) = self.pending.lock() else {
+            return Ok(false);
+        };
+        loop {
+            if !pending.is_empty() {
+                return Ok(true);
+            }
+            cancel_token.check()?;
+            let remaining =
+                wait_until.map(|end_time| end_time.saturating_duration_since(Instant::now()));
+            if remaining.is_some_and(|remaining| remaining.is_zero()) {
+                return Ok(false);
+            }
+            let slice = remaining.map_or(CancelToken::CHECK_INTERVAL, |remaining| {
+                remaining.min(CancelToken::CHECK_INTERVAL)
+            });
+            pending = match self.arrived.wait_timeout(pending, slice) {
+                Ok((pending, _)) => pending,
+                Err(_) => return Ok(false),
+            };
+        }
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Take the oldest interrupt read in the background, if the interrupt line is
+    /// being watched in the background and there is one.
+    pub(crate) fn background_interrupt(&self) -> Option<PfdInterrupt> {
+        self.background_interrupts.as_ref()?.pop()
+    }
+
+    /// Take the next interrupt and queue the result for each pin.
+    ///
+    /// The interrupt is one read in the background if the interrupt line is being
+    /// watched in the background, otherwise the interrupt flags and capture are read.
+    /// Returns the decoded interrupt.
+    pub(crate) fn service_interrupt(&mut self) -> Result<PfdInterrupt> {
+        if let Some(interrupt) = self.background_interrupt() {
+            // Already passed to the callbacks when it was read.
+            self.interrupt_queues.push(&interrupt);
+            return Ok(interrupt);
+        }
+        self.read_and_queue_interrupt()
+    }
+
+    /// Read the interrupt flags and capture, which clears the interrupt, without
+    /// queueing the result.
+    ///
+    /// The registers from `INTFB` to `GPIOB` are read in one burst.
+    pub(crate) fn read_interrupt(&mut self) -> Result<PfdInterrupt> {
+        let context = self
+            .context("reading interrupt")
+            .register(RegisterAddress::INTFB);
+        let burst = self.burst.clone();
+        let mut data = [0u8; PfdInterrupt::BURST_LENGTH];
+        self.retry(|mcp23s17| burst.read(mcp23s17, RegisterAddress::INTFB, &mut data))
+            .context(context)?;
+
+        // Reading INTCAPB clears the interrupt in the real device so emulate that by
+        // clearing the mock's flags.
+        #[cfg(any(test, feature = "mockspi"))]
+        self.mcp23s17.set_mock_data(RegisterAddress::INTFB, 0x00);
+
+        let interrupt = PfdInterrupt::from_burst(data);
+        if interrupt.flags.is_empty() {
+            warn!("Interrupt with no flags set in INTFB");
+        }
+        self.input_cache
+            .update(interrupt.inputs, interrupt.timestamp);
+        Ok(interrupt)
+    }
+
+    /// Read the interrupt flags and capture and queue the result for each pin.
+    ///
+    /// Returns the decoded interrupt.
+    pub(crate) fn read_and_queue_interrupt(&mut self) -> Result<PfdInterrupt> {
+        let interrupt = self
+            .read_interrupt()
+            .context(self.context("servicing interrupt"))?;
+        self.interrupt_queues.push(&interrupt);
+        if let Some(dispatcher) = &self.callback_dispatcher {
+            dispatcher.dispatch(interrupt);
+        }
+        Ok(interrupt)
+    }
+
+    /// Wait for the MCP23S17 to raise an interrupt.
+    ///
+    /// Returns `false` if the wait timed out.
+    ///
+    /// If the interrupt line is being watched in the background this waits for an
+    /// interrupt to have been read there, otherwise it waits on the line itself. The
+    /// wait is made in slices so that it can be cancelled, returning
+    /// [`PiFaceDigitalError::Cancelled`][crate::PiFaceDigitalError::Cancelled].
+    ///
+    /// A reset of the MCP23S17 disables its interrupts, so if the health monitor is
+    /// enabled the wait also wakes to make each health check as it falls due.
+    pub(crate) fn wait_for_interrupt(
+        &mut self,
+        reset: bool,
+        timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let wait_until = timeout.map(|delay| Instant::now() + delay);
+        let mut reset = reset;
+        loop {
+            self.poll_health()?;
+            let remaining =
+                wait_until.map(|end_time| end_time.saturating_duration_since(Instant::now()));
+            let next_check = self.health_monitor.as_ref().map(HealthMonitor::until_due);
+            let slice = match (remaining, next_check) {
+                (Some(remaining), Some(next_check)) => Some(remaining.min(next_check)),
+                (remaining, next_check) => remaining.or(next_check),
+            };
+            let interrupted = match self.background_interrupts.clone() {
+                Some(background) => background.wait(&self.cancel_token, slice)?,
+                None => self.wait_for_interrupt_line(reset, slice)?,
+            };
+            if interrupted || slice == remaining {
+                return Ok(interrupted);
+            }
+            // Without a virtual board the mock returns at once rather than waiting.
+            #[cfg(any(test, feature = "mockspi"))]
+            if self.background_interrupts.is_none() && !self.virtual_board_connected() {
+                return Ok(false);
+            }
+            reset = false;
+        }
+    }
+
+    /// Wait for the MCP23S17 to raise an interrupt on the Raspberry Pi's GPIO.
+    #[cfg(not(any(test, feature = "mockspi")))]
+    fn wait_for_interrupt_line(&mut self, reset: bool, timeout: Option<Duration>) -> Result<bool> {
+        let wait_until = timeout.map(|delay| Instant::now() + delay);
+        let mut reset = reset;
+        loop {
+            self.cancel_token.check()?;
+            let remaining =
+                wait_until.map(|end_time| end_time.saturating_duration_since(Instant::now()));
+            let slice = remaining.map_or(CancelToken::CHECK_INTERVAL, |remaining| {
+                remaining.min(CancelToken::CHECK_INTERVAL)
+            });
+            if self
+                .lock_interrupt_pin()
+                .poll_interrupt(reset, Some(slice))?
+                .is_some()
+            {
+                return Ok(true);
+            }
+            if remaining.is_some_and(|remaining| remaining <= slice) {
+                return Ok(false);
+            }
+            reset = false;
+        }
+    }
+
+    /// Is the MCP23S17's interrupt output currently asserted (active-low)?
+    #[cfg(not(any(test, feature = "mockspi")))]
+    pub(crate) fn interrupt_line_asserted(&self) -> bool {
+        self.lock_interrupt_pin().is_low()
+    }
+
+    /// Lock the interrupt GPIO, which is shared with the interrupt line watchdog.
+    #[cfg(not(any(test, feature = "mockspi")))]
+    pub(crate) fn lock_interrupt_pin(&self) -> std::sync::MutexGuard<'_, rppal::gpio::InputPin> {
+        // Nothing is left half-done by a panic while it's locked.
+        self.interrupt_pin
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Mock version of checking the interrupt line, which is asserted whenever the
+    /// mock `INTFB` register has any flags set.
+    #[cfg(any(test, feature = "mockspi"))]
+    pub(crate) fn interrupt_line_asserted(&self) -> bool {
+        self.mcp23s17.get_mock_data(RegisterAddress::INTFB).0 != 0
+    }
+
+    /// Mock version of waiting for an interrupt.
+    ///
+    /// There is no GPIO to wait on, so the interrupt line is treated as asserted
+    /// whenever the mock `INTFB` register has any flags set. Without a virtual board
+    /// it returns immediately as if the wait timed out; with one it waits for the
+    /// virtual board to raise an interrupt.
+    #[cfg(any(test, feature = "mockspi"))]
+    fn wait_for_interrupt_line(&mut self, _reset: bool, timeout: Option<Duration>) -> Result<bool> {
+        self.wait_for_virtual_interrupt(timeout)?;
+        Ok(self.mcp23s17.get_mock_data(RegisterAddress::INTFB).0 != 0)
+    }
+}
+
+/// Reads the interrupt registers over a separate connection to the SPI bus so that
+/// interrupts can be serviced from a background thread.
+///
+/// The `Mcp23s17` can't be shared between threads, so this makes the raw transfers
+/// itself as burst reads, retrying according to the board's retry policy (there's no
+/// escalation as the main connection can't be used). The inputs read with each
+/// interrupt are passed to the input cache.
+#[cfg(not(any(test, feature = "mockspi")))]
+#[derive(Debug)]
+pub(crate) struct InterruptReader {
+    burst: crate::burst::BurstReader,
+    input_cache: crate::cache::InputCache,
+    pub(crate) retry_policy: crate::RetryPolicy,
+}
+
+#[cfg(not(any(test, feature = "mockspi")))]
+impl InterruptReader {
+    /// Open the SPI bus with the same settings as the device's main connection.
+    pub(crate) fn new(pfd_state: &PiFaceDigitalState) -> Result<Self> {
+        Ok(InterruptReader {
+            burst: crate::burst::BurstReader::open(
+                pfd_state.mcp23s17.get_hardware_address().try_into()?,
+                pfd_state.mcp23s17.get_spi_bus(),
+                pfd_state.chip_select,
+                pfd_state.spi_clock,
+                pfd_state.spi_mode,
+            )?,
+            input_cache: pfd_state.input_cache.clone(),
+            retry_policy: pfd_state.retry_policy,
+        })
+    }
+
+    /// Read the interrupt flags, the capture and the inputs in one burst, which clears
+    /// the interrupt.
+    pub(crate) fn read(&self) -> Result<PfdInterrupt> {
+        let mut data = [0u8; PfdInterrupt::BURST_LENGTH];
+        self.retry_policy
+            .attempt(|| self.burst.transfer(RegisterAddress::INTFB, &mut data))?;
+        let interrupt = PfdInterrupt::from_burst(data);
+        self.input_cache
+            .update(interrupt.inputs, interrupt.timestamp);
+        Ok(interrupt)
+    }
+}
@@ -0,0 +1,428 @@
+//! Supervision of the MCP23S17 to detect and recover from unexpected chip resets.
+//!
+//! A brown-out on the PiFace Digital's 5V supply resets the MCP23S17 to its power-on
+//! defaults: `IOCON` is cleared, every pin becomes an input and all interrupts are
+//! disabled. Without supervision the application carries on "blind" with none of its
+//! outputs driven and no interrupts being raised.
+//!
+//! The health monitor compares a handful of registers that can never legitimately hold
+//! their power-on values once the board has been initialised against the
+//! configuration the driver expects. If they disagree, the monitor reports the event
+//! and then restores the configuration and the last known output state.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+
+#[cfg(any(test, feature = "mockspi"))]
+use rppal_mcp23s17::IOCON;
+
+#[cfg(not(any(test, feature = "mockspi")))]
+use crate::Mcp23s17;
+use crate::{
+    PiFaceDigital, PiFaceDigitalError, PiFaceDigitalState, PinNumber, RegisterAddress, Result,
+    context::Context, default_iocon,
+};
+
+/// The configuration that the driver expects to find in the MCP23S17.
+///
+/// The registers are tracked as the driver changes them so that they remain accurate
+/// between health checks. `OLATA` is also refreshed whenever a health check finds the
+/// device in good order in case it has been written other than through an
+/// [`OutputPin`][crate::OutputPin].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ExpectedConfig {
+    pub(crate) iocon: u8,
+    pub(crate) iodirb: u8,
+    pub(crate) ipolb: u8,
+    pub(crate) gpintenb: u8,
+    pub(crate) defvalb: u8,
+    pub(crate) intconb: u8,
+    pub(crate) gppub: u8,
+    pub(crate) olata: u8,
+}
+
+impl Default for ExpectedConfig {
+    /// The configuration established by [`PiFaceDigital::init()`].
+    fn default() -> Self {
+        ExpectedConfig {
+            iocon: default_iocon(),
+            iodirb: 0xFF,
+            ipolb: 0x00,
+            gpintenb: 0x00,
+            defvalb: 0x00,
+            intconb: 0x00,
+            gppub: 0xFF,
+            olata: 0x00,
+        }
+    }
+}
+
+impl ExpectedConfig {
+    /// Port A is always wired as outputs on the PiFace Digital.
+    pub(crate) const IODIRA: u8 = 0x00;
+
+    /// Set or clear a pin's bit in one of the tracked registers.
+    pub(crate) fn update_bit(register: &mut u8, pin: PinNumber, set: bool) {
+        if set {
+            *register |= pin.mask();
+        } else {
+            *register &= !pin.mask();
+        }
+    }
+}
+
+/// Configuration and bookkeeping for the periodic health check.
+#[derive(Debug)]
+pub(crate) struct HealthMonitor {
+    interval: Duration,
+    last_check: Option<Instant>,
+}
+
+impl HealthMonitor {
+    pub(crate) fn new(interval: Duration) -> Self {
+        HealthMonitor {
+            interval,
+            last_check: None,
+        }
+    }
+
+    /// Is a health check now overdue?
+    fn is_due(&self) -> bool {
+        self.last_check
+            .is_none_or(|last_check| last_check.elapsed() >= self.interval)
+    }
+
+    /// How long until the next health check is due.
+    pub(crate) fn until_due(&self) -> Duration {
+        self.last_check.map_or(Duration::ZERO, |last_check| {
+            self.interval.saturating_sub(last_check.elapsed())
+        })
+    }
+}
+
+/// A register found to hold an unexpected value by a health check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterMismatch {
+    /// The register that was checked.
+    pub register: RegisterAddress,
+    /// The value the driver expected the register to hold.
+    pub expected: u8,
+    /// The value actually read from the device.
+    pub actual: u8,
+}
+
+impl fmt::Display for RegisterMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} expected 0x{:02x} found 0x{:02x}",
+            self.register, self.expected, self.actual
+        )
+    }
+}
+
+/// Details of a chip reset detected (and recovered from) by a health check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResetReport {
+    /// The supervised registers that did not hold their expected values.
+    pub mismatches: Vec<RegisterMismatch>,
+    /// The output state that was restored to `OLATA`.
+    pub restored_outputs: u8,
+}
+
+impl fmt::Display for ResetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MCP23S17 reset detected (")?;
+        for (i, mismatch) in self.mismatches.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{mismatch}")?;
+        }
+        write!(f, "); outputs restored to 0x{:02x}", self.restored_outputs)
+    }
+}
+
+/// Outcome of a health check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// The device holds the expected configuration.
+    Healthy,
+    /// The device had been reset and its configuration has now been restored.
+    Recovered(ResetReport),
+}
+
+impl PiFaceDigital {
+    /// Enable the health monitor.
+    ///
+    /// Once enabled, a health check (see [`PiFaceDigital::check_health()`]) is made
+    /// whenever [`PiFaceDigital::poll_health()`] is called and at least `interval` has
+    /// elapsed since the previous check. The interrupt polling functions
+    /// [`PiFaceDigital::poll_interrupts()`] and
+    /// [`InputPin::poll_interrupt()`][crate::InputPin::poll_interrupt()] also make a
+    /// check whenever one is due while they are waiting, waking up for it if need be.
+    /// A reset disables the interrupts, so without this a poll waiting indefinitely
+    /// would never return.
+    pub fn enable_health_monitor(&self, interval: Duration) {
+        info!("Enable health monitor every {interval:?}");
+        self.pfd_state.borrow_mut().health_monitor = Some(HealthMonitor::new(interval));
+    }
+
+    /// Disable the health monitor.
+    pub fn disable_health_monitor(&self) {
+        self.pfd_state.borrow_mut().health_monitor = None;
+    }
+
+    /// Make a health check if the health monitor is enabled and a check is due.
+    ///
+    /// Returns `Ok(None)` if no check was made.
+    pub fn poll_health(&self) -> Result<Option<HealthStatus>> {
+        self.pfd_state.borrow_mut().poll_health()
+    }
+
+    /// Immediately check that the MCP23S17 still holds the configuration expected by
+    /// the driver.
+    ///
+    /// The `IOCON`, `IODIRA` and `GPINTENB` registers are compared against their
+    /// expected values. A power-on reset of the MCP23S17 clears `IOCON` and `GPINTENB`
+    /// and sets `IODIRA` to all inputs so any of these disagreeing indicates that the
+    /// device has been reset. `OLATA` is also checked and included in the
+    /// [`ResetReport`] but, because it may legitimately be zero, a difference in
+    /// `OLATA` alone is not taken as a reset.
+    ///
+    /// If a reset is detected, it is logged and the configuration is re-applied,
+    /// including the input pin and interrupt settings and the outputs as they were at
+    /// the last successful check. Any pending interrupt is then cleared so that the
+    /// interrupt line is re-armed. If re-applying the configuration fails part way, the
+    /// error is returned and the next check re-applies it again, whatever the
+    /// registers it compares hold.
+    ///
+    /// ```no_run
+    /// use rppal_pfd::{ChipSelect, HardwareAddress, HealthStatus, PiFaceDigital, SpiBus, SpiMode};
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    /// pfd.init().expect("Failed to initialise!");
+    ///
+    /// match pfd.check_health().expect("Failed health check") {
+    ///     HealthStatus::Healthy => println!("All good"),
+    ///     HealthStatus::Recovered(report) => println!("Recovered from reset: {report}"),
+    /// }
+    /// ```
+    pub fn check_health(&self) -> Result<HealthStatus> {
+        self.pfd_state.borrow_mut().check_health()
+    }
+
+    /// The number of chip resets that health checks have detected.
+    pub fn resets_detected(&self) -> usize {
+        self.pfd_state.borrow().resets_detected
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Make a health check if one is due.
+    pub(crate) fn poll_health(&mut self) -> Result<Option<HealthStatus>> {
+        match &self.health_monitor {
+            Some(monitor) if monitor.is_due() => Ok(Some(self.check_health()?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Compare the supervised registers with the expected configuration and recover
+    /// from any reset.
+    pub(crate) fn check_health(&mut self) -> Result<HealthStatus> {
+        if let Some(monitor) = self.health_monitor.as_mut() {
+            monitor.last_check = Some(Instant::now());
+        }
+
+        // Read the configuration registers from IODIRA to IOCON in one burst. A full
+        // snapshot would also read INTCAPB, which would clear any pending interrupt.
+        let context = self.context("checking health");
+        let mut configuration = [0u8; RegisterAddress::IOCON as usize + 1];
+        self.burst
+            .read(&self.mcp23s17, RegisterAddress::IODIRA, &mut configuration)
+            .context(context.register(RegisterAddress::IODIRA))?;
+        let [iocon, iodira, gpintenb] = [
+            RegisterAddress::IOCON,
+            RegisterAddress::IODIRA,
+            RegisterAddress::GPINTENB,
+        ]
+        .map(|register| configuration[register as usize]);
+        let olata = self
+            .mcp23s17
+            .read(RegisterAddress::OLATA)
+            .map_err(PiFaceDigitalError::from)
+            .context(context.register(RegisterAddress::OLATA))?;
+
+        let mismatches: Vec<RegisterMismatch> = [
+            (RegisterAddress::IOCON, self.expected.iocon, iocon),
+            (RegisterAddress::IODIRA, ExpectedConfig::IODIRA, iodira),
+            (RegisterAddress::GPINTENB, self.expected.gpintenb, gpintenb),
+            (RegisterAddress::OLATA, self.expected.olata, olata),
+        ]
+        .into_iter()
+        .filter(|(_, expected, actual)| expected != actual)
+        .map(|(register, expected, actual)| RegisterMismatch {
+            register,
+            expected,
+            actual,
+        })
+        .collect();
+
+        let reset_detected = mismatches
+            .iter()
+            .any(|mismatch| mismatch.register != RegisterAddress::OLATA);
+        let restore_pending = self.restore_pending.get();
+        if !reset_detected && !restore_pending {
+            // Track the outputs so that they can be restored after any future reset.
+            self.expected.olata = olata;
+            debug!("Health check passed");
+            return Ok(HealthStatus::Healthy);
+        }
+
+        let report = ResetReport {
+            mismatches,
+            restored_outputs: self.expected.olata,
+        };
+        if restore_pending {
+            warn!("{report} - completing a restore that failed");
+        } else {
+            warn!("{report}");
+            self.resets_detected += 1;
+        }
+        self.restore_config()?;
+        Ok(HealthStatus::Recovered(report))
+    }
+
+    /// Write `IOCON`, re-enabling hardware addressing first if the device needs it.
+    ///
+    /// A reset clears `HAEN`, after which the device ignores its address pins and only
+    /// answers at address 0. So if `IOCON` doesn't read back as written at the board's
+    /// own address, it is written at address 0 and then at the board's own address
+    /// again. A board that really is at address 0 on the same chip select is only
+    /// written in that case, and then gets the same `IOCON`.
+    ///
+    /// Returns `IOCON` as read back, which only differs from `iocon` if there is no
+    /// device answering.
+    pub(crate) fn write_iocon(&self, iocon: u8) -> Result<u8> {
+        let context = self.board_context().register(RegisterAddress::IOCON);
+        let read_back = self.write_iocon_at_own_address(iocon).context(context)?;
+        if read_back == iocon || u8::from(self.mcp23s17.get_hardware_address()) == 0 {
+            return Ok(read_back);
+        }
+        info!("Re-enable hardware addressing through address 0");
+        self.write_iocon_at_address_zero(iocon).context(context)?;
+        self.write_iocon_at_own_address(iocon).context(context)
+    }
+
+    /// The error for an `IOCON` that doesn't read back as written.
+    pub(crate) fn no_hardware_detected(&self) -> PiFaceDigitalError {
+        match self.mcp23s17.get_hardware_address().try_into() {
+            Ok(hardware_address) => PiFaceDigitalError::NoHardwareDetected {
+                spi_bus: self.mcp23s17.get_spi_bus(),
+                hardware_address,
+            },
+            Err(e) => e,
+        }
+    }
+
+    /// Write `IOCON` at the board's own address and read it back.
+    fn write_iocon_at_own_address(&self, iocon: u8) -> Result<u8> {
+        // The mock answers whatever the address, so emulate the real device.
+        #[cfg(any(test, feature = "mockspi"))]
+        if !self.answers_at_own_address() {
+            return Ok(0x00);
+        }
+        self.attempt(|mcp23s17| {
+            mcp23s17.write(RegisterAddress::IOCON, iocon)?;
+            Ok(mcp23s17.read(RegisterAddress::IOCON)?)
+        })
+    }
+
+    /// Write `IOCON` at hardware address 0, over a connection of its own as
+    /// `rppal_mcp23s17` only addresses the board's own address.
+    #[cfg(not(any(test, feature = "mockspi")))]
+    fn write_iocon_at_address_zero(&self, iocon: u8) -> Result<()> {
+        self.retry_policy.attempt(|| {
+            let mcp23s17 = Mcp23s17::new(
+                rppal_mcp23s17::HardwareAddress::new(0)?,
+                self.mcp23s17.get_spi_bus(),
+                self.chip_select,
+                self.spi_clock,
+                self.spi_mode,
+            )?;
+            Ok(mcp23s17.write(RegisterAddress::IOCON, iocon)?)
+        })
+    }
+
+    /// Does the mock device answer at the board's own address? A real device with
+    /// `HAEN` clear only answers at address 0.
+    #[cfg(any(test, feature = "mockspi"))]
+    fn answers_at_own_address(&self) -> bool {
+        let (iocon, _, _) = self.mcp23s17.get_mock_data(RegisterAddress::IOCON);
+        u8::from(self.mcp23s17.get_hardware_address()) == 0 || iocon & IOCON::HAEN_ON.bits() != 0
+    }
+
+    /// Mock version of writing `IOCON` at hardware address 0, which reaches the mock
+    /// device if it is at address 0 or has `HAEN` clear.
+    #[cfg(any(test, feature = "mockspi"))]
+    fn write_iocon_at_address_zero(&self, iocon: u8) -> Result<()> {
+        let (current, _, _) = self.mcp23s17.get_mock_data(RegisterAddress::IOCON);
+        if current & IOCON::HAEN_ON.bits() == 0 {
+            self.mcp23s17.set_mock_data(RegisterAddress::IOCON, iocon);
+        }
+        #[cfg(test)]
+        self.address_zero_writes
+            .set(self.address_zero_writes.get() + 1);
+        Ok(())
+    }
+
+    /// Write the expected configuration back into the MCP23S17.
+    ///
+    /// `IOCON` goes first, re-enabling hardware addressing if need be, so that
+    /// register addressing is correct for the remaining writes and `GPINTENB` goes last
+    /// so that all the interrupt criteria are in place before interrupts are enabled.
+    /// Each access is retried according to the retry policy. Until the whole
+    /// configuration has been written, the next health check restores it again.
+    pub(crate) fn restore_config(&self) -> Result<()> {
+        info!("Restore MCP23S17 configuration");
+        self.restore_pending.set(true);
+        self.input_cache.invalidate();
+        let context = self.context("restoring configuration");
+        let expected = self.expected;
+        if self.write_iocon(expected.iocon).context(context)? != expected.iocon {
+            return Err(self.no_hardware_detected());
+        }
+        for (register_address, data) in [
+            (RegisterAddress::OLATA, expected.olata),
+            (RegisterAddress::IODIRA, ExpectedConfig::IODIRA),
+            (RegisterAddress::IODIRB, expected.iodirb),
+            (RegisterAddress::IPOLB, expected.ipolb),
+            (RegisterAddress::GPPUB, expected.gppub),
+            (RegisterAddress::DEFVALB, expected.defvalb),
+            (RegisterAddress::INTCONB, expected.intconb),
+        ] {
+            self.attempt(|mcp23s17| Ok(mcp23s17.write(register_address, data)?))
+                .context(context.register(register_address))?;
+        }
+
+        // Clear anything pending before enabling interrupts so that the interrupt line
+        // is released and the next interrupt generates a fresh edge.
+        let _ = self
+            .attempt(|mcp23s17| Ok(mcp23s17.read(RegisterAddress::INTCAPB)?))
+            .context(context.register(RegisterAddress::INTCAPB))?;
+        self.attempt(|mcp23s17| Ok(mcp23s17.write(RegisterAddress::GPINTENB, expected.gpintenb)?))
+            .context(context.register(RegisterAddress::GPINTENB))?;
+        self.restore_pending.set(false);
+        Ok(())
+    }
+}
@@ -10,27 +10,78 @@
 //! ```
 
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     fmt::{self, Display},
+    marker::PhantomData,
     rc::Rc,
     result,
+    sync::{Arc, atomic::AtomicUsize},
     time::Duration,
 };
 
+mod attach;
+pub use attach::AttachReport;
+mod board;
+pub use board::{Components, Led, Relay, Switch};
+mod builder;
+pub use builder::{InitPolicy, PiFaceDigitalBuilder};
+mod burst;
+use burst::BurstReader;
+mod cache;
+use cache::InputCache;
+mod calibrate;
+pub use calibrate::{ClockCalibration, ClockStep};
+mod cancel;
+pub use cancel::CancelToken;
+mod callbacks;
+mod context;
+use callbacks::CallbackDispatcher;
+pub use callbacks::{Edge, PinChange};
+use context::Context;
+pub use context::ErrorContext;
+mod dispatch;
+pub use dispatch::PfdInterrupt;
+use dispatch::{BackgroundInterrupts, InterruptQueues};
+mod health;
+mod lock;
+use health::{ExpectedConfig, HealthMonitor};
+pub use health::{HealthStatus, RegisterMismatch, ResetReport};
+use lock::BoardLock;
+mod registers;
+pub use registers::{ExpertToken, RegisterSnapshot, Registers};
+mod notify;
+pub use notify::InterruptFd;
+use notify::InterruptNotifier;
+mod pins;
+pub use pins::{PinLevels, PinLevelsIter, PinNumber, PinSet, PinSetIter};
+mod retry;
+pub use retry::{RetryEscalation, RetryPolicy};
+mod subscription;
+pub use subscription::{Backpressure, Subscription, SubscriptionOptions};
+mod typestate;
+pub use typestate::{Floating, InterruptState, InterruptsDisabled, InterruptsEnabled, PullUp};
+mod virtual_board;
+#[cfg(any(test, feature = "mockspi"))]
+use virtual_board::VirtualBoard;
+pub use virtual_board::VirtualMessage;
+mod watchdog;
 #[cfg(not(any(test, feature = "mockspi")))]
-use log::warn;
-use log::{debug, info};
+use watchdog::LineWatchdog;
+
+use log::{debug, info, warn};
 #[cfg(not(any(test, feature = "mockspi")))]
-use rppal::gpio::{self, Event as GpioEvent, Gpio, Trigger};
-#[cfg(not(feature = "mockspi"))]
-use rppal_mcp23s17::{IOCON, Mcp23s17, RegisterAddress};
+use rppal::gpio::{self, Gpio, Trigger};
 #[cfg(feature = "mockspi")]
-pub use rppal_mcp23s17::{IOCON, Mcp23s17, RegisterAddress};
+pub use rppal_mcp23s17::Mcp23s17;
+#[cfg(not(feature = "mockspi"))]
+use rppal_mcp23s17::Mcp23s17;
 
 use thiserror::Error;
 
 /// Re-export of `rppal_mcp23s17` crate APIs which we use on this crate's APIs.
-pub use rppal_mcp23s17::{ChipSelect, InterruptMode, Level, OutputPin, SpiBus, SpiMode};
+pub use rppal_mcp23s17::{
+    ChipSelect, IOCON, InterruptMode, Level, RegisterAddress, SpiBus, SpiMode,
+};
 
 //--------------------------------------------------------------------------------------
 /// The hardware address of the device - two bits.
@@ -95,11 +146,12 @@ impl fmt::Display for HardwareAddress {
 #[derive(Error, Debug)]
 pub enum PiFaceDigitalError {
     /// Errors from the `rppal_mcp23s17::Mcp23s17`.
-    #[error("MCP23S17 error")]
+    #[error("MCP23S17 error{context}")]
     Mcp23s17Error {
         /// Underlying error source.
-        #[from]
         source: rppal_mcp23s17::Mcp23s17Error,
+        /// Where the error happened.
+        context: ErrorContext,
     },
 
     /// Attempt to access a PiFace Digital beyond the hardware address range
@@ -107,6 +159,11 @@ pub enum PiFaceDigitalError {
     #[error("Hardware address out of range")]
     HardwareAddressBoundsError(u8),
 
+    /// Attempt to access a pin beyond the pin number range
+    /// (0 - [`PinNumber::MAX_PIN_NUMBER`]).
+    #[error("Pin number {0} out of range")]
+    PinNumberBoundsError(u8),
+
     /// Failed to detect the presence of any physical PiFace Digital device connected to
     /// the SPI bus.
     #[error("No hardware connected to {spi_bus} at hardware address={hardware_address})")]
@@ -118,14 +175,143 @@ pub enum PiFaceDigitalError {
     },
 
     /// Errors accessing the GPIO for the interrupt input.
-    #[error("GPIO error")]
+    #[error("GPIO error{context}")]
     GpioError {
         /// Underlying error source.
-        #[from]
         source: rppal::gpio::Error,
+        /// Where the error happened.
+        context: ErrorContext,
+    },
+
+    /// An SPI clock rate of zero or above [`PiFaceDigital::MAX_SPI_CLOCK`] was
+    /// requested.
+    #[error("SPI clock {0} Hz out of range")]
+    SpiClockOutOfRange(u32),
+
+    /// The board is locked by another process, or another instance in this process
+    /// (see [`PiFaceDigitalBuilder::exclusive()`]).
+    #[error(
+        "PiFace Digital on {spi_bus} {chip_select:?} at hardware address {hardware_address} in use{}",
+        .pid.map(|pid| format!(" by process {pid}")).unwrap_or_default()
+    )]
+    BoardInUse {
+        /// The SPI bus of the board.
+        spi_bus: SpiBus,
+        /// The chip select of the board.
+        chip_select: ChipSelect,
+        /// The hardware address of the board.
+        hardware_address: HardwareAddress,
+        /// The process ID of the lock's holder, if it could be told.
+        pid: Option<u32>,
+    },
+
+    /// The lock on the board could not be taken (see
+    /// [`PiFaceDigitalBuilder::exclusive()`]).
+    #[error("Failed to lock PiFace Digital")]
+    BoardLockFailed {
+        /// Underlying error source.
+        source: std::io::Error,
+    },
+
+    /// The connection to a virtual board failed (see
+    /// [`PiFaceDigitalBuilder::virtual_board()`]).
+    #[error("Failed to connect to virtual board")]
+    VirtualBoardFailed {
+        /// Underlying error source.
+        source: std::io::Error,
+    },
+
+    /// A line received from, or for, a virtual board isn't a [`VirtualMessage`].
+    #[error("Invalid virtual board message: {0:?}")]
+    InvalidVirtualMessage(String),
+
+    /// The GPIO given to [`PiFaceDigitalBuilder::interrupt_gpio()`] can't be used for
+    /// the interrupt line.
+    #[error("GPIO {gpio} can't be the interrupt line: {reason}")]
+    InvalidInterruptGpio {
+        /// The GPIO (BCM numbering).
+        gpio: u8,
+        /// Why it can't be used.
+        reason: &'static str,
+    },
+
+    /// The operation needs exclusive use of the device but [`InputPin`]s or
+    /// [`OutputPin`]s are still in use, or interrupts are handled in the background.
+    #[error("Operation not possible while pins are in use")]
+    PinsInUse,
+
+    /// Calibration of the SPI clock found the bus unreliable even at the lowest clock
+    /// rate tried.
+    #[error("SPI bus unreliable at all clock rates from {clock} Hz")]
+    ClockCalibrationFailed {
+        /// The lowest clock rate tried (Hz).
+        clock: u32,
+    },
+
+    /// A direct register write through [`Registers::write()`] was refused because it
+    /// is incompatible with the PiFace Digital hardware.
+    #[error("Write of 0x{data:02x} to {register} refused: {reason}")]
+    RegisterWriteRefused {
+        /// The register that was to be written.
+        register: RegisterAddress,
+        /// The data that was to be written.
+        data: u8,
+        /// Why the write was refused.
+        reason: &'static str,
+    },
+
+    /// Reading back an output pin after writing it showed that the write did not take
+    /// effect (see [`RetryPolicy::verify_writes`]).
+    #[error("Output pin {pin} did not take level {level} when written")]
+    WriteVerifyFailed {
+        /// The output pin that was written.
+        pin: PinNumber,
+        /// The level that was written to the pin.
+        level: Level,
+    },
+
+    /// The thread that runs the interrupt callbacks could not be started.
+    #[error("Failed to start callback dispatcher")]
+    CallbackDispatcherFailed {
+        /// Underlying error source.
+        source: std::io::Error,
+    },
+
+    /// Interrupts on the [`InputPin`] (with the given pin number) were to be enabled
+    /// with [`InterruptMode::None`].
+    #[error("Interrupts not enabled on pin {0}")]
+    InterruptsNotEnabled(PinNumber),
+
+    /// A blocking interrupt poll was cancelled through a [`CancelToken`].
+    #[error("Interrupt poll cancelled")]
+    Cancelled,
+
+    /// The file descriptor for interrupts could not be created.
+    #[error("Failed to create interrupt file descriptor")]
+    InterruptFdFailed {
+        /// Underlying error source.
+        source: std::io::Error,
     },
 }
 
+impl From<rppal_mcp23s17::Mcp23s17Error> for PiFaceDigitalError {
+    fn from(source: rppal_mcp23s17::Mcp23s17Error) -> Self {
+        PiFaceDigitalError::Mcp23s17Error {
+            source,
+            context: ErrorContext::default(),
+        }
+    }
+}
+
+impl From<rppal::gpio::Error> for PiFaceDigitalError {
+    fn from(source: rppal::gpio::Error) -> Self {
+        PiFaceDigitalError::GpioError {
+            source,
+            context: ErrorContext::default(),
+        }
+    }
+}
+
 /// Convenient alias for [`Result<_>`] types can have [`PiFaceDigitalError`]s.
 pub type Result<T> = result::Result<T, PiFaceDigitalError>;
 
@@ -134,6 +320,14 @@ pub type Result<T> = result::Result<T, PiFaceDigitalError>;
 /// The [`InputPin`] exposes the capabilities of the underlying `rppal_mcp23s17::InputPin`
 /// with the addition of interrupt handling.
 ///
+/// The pin's configuration is part of its type: `P` is whether the pull-up is
+/// enabled ([`Floating`] or [`PullUp`]) and `I` is whether interrupts are enabled
+/// ([`InterruptsDisabled`] or [`InterruptsEnabled`]). Methods that only make sense
+/// in one configuration, such as [`InputPin::poll_interrupt()`], are only available
+/// on pins of that type and the configuration is changed by converting the pin with
+/// methods such as [`InputPin::into_interrupts_enabled()`] and
+/// [`InputPin::into_pull_up()`].
+///
 /// # Example usage
 ///
 /// ```no_run
@@ -160,11 +354,84 @@ pub type Result<T> = result::Result<T, PiFaceDigitalError>;
 /// // Set the pin to logic-level low.
 /// pin.write(Level::Low).expect("Bad pin write");
 /// ```
+pub struct InputPin<P = Floating, I = InterruptsDisabled> {
+    inner: InputPinInner,
+    _state: PhantomData<(P, I)>,
+}
+
+impl<P, I> fmt::Debug for InputPin<P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InputPin")
+            .field("pull_up", &std::any::type_name::<P>())
+            .field("interrupts", &std::any::type_name::<I>())
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+/// The parts of an [`InputPin`] that don't depend on its configuration, which restore
+/// the pin's default configuration when dropped.
+#[derive(Debug)]
+struct InputPinInner {
+    /// Only taken when dropped, to be returned to the board.
+    pin: Option<rppal_mcp23s17::InputPin>,
+    interrupt_mode: InterruptMode,
+    active_level: ActiveLevel,
+    pfd_state: Rc<RefCell<PiFaceDigitalState>>,
+}
+
+/// The physical level on an [`InputPin`] that represents it being "active".
+///
+/// The four push-switches on the PiFace Digital pull their inputs to ground against
+/// the pull-up resistors so are [`ActiveLevel::Low`]: they read [`Level::Low`] when
+/// pressed. Rather than every application having to remember to invert these inputs,
+/// the inversion can be made by the MCP23S17 itself using the `IPOLB` register.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ActiveLevel {
+    /// The input is active when the pin is at [`Level::High`] (no inversion).
+    #[default]
+    High,
+    /// The input is active when the pin is at [`Level::Low`] (inverted by `IPOLB`).
+    Low,
+}
+
+impl fmt::Display for ActiveLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ActiveLevel::High => write!(f, "Active-High"),
+            ActiveLevel::Low => write!(f, "Active-Low"),
+        }
+    }
+}
+
+/// An output pin.
+///
+/// The [`OutputPin`] exposes the capabilities of the underlying
+/// `rppal_mcp23s17::OutputPin` with the addition of the [`PiFaceDigital`]'s
+/// [`RetryPolicy`].
+///
+/// # Example usage
+///
+/// ```no_run
+/// # use rppal_pfd::{ChipSelect, HardwareAddress, Level, PiFaceDigital, SpiBus, SpiMode};
+/// #
+/// # let pfd = PiFaceDigital::new(
+/// #     HardwareAddress::new(0).expect("Invalid hardware address"),
+/// #     SpiBus::Spi0,
+/// #     ChipSelect::Cs0,
+/// #     100_000,
+/// #     SpiMode::Mode0,
+/// # )
+/// # .expect("Failed to create PiFace Digital");
+/// #
+/// // Given an instance of a PiFaceDigital, take ownership of the output pin on bit 2
+/// // of the device and turn on its LED.
+/// let pin = pfd.get_output_pin(2).expect("Failed to get Pin");
+/// pin.set_high().expect("Bad pin write");
+/// ```
 #[derive(Debug)]
-pub struct InputPin {
-    pin: rppal_mcp23s17::InputPin,
-    interrupts_enabled: bool,
-    #[allow(dead_code)] // in test config no functionality accesses pfd_state.
+pub struct OutputPin {
+    pin: rppal_mcp23s17::OutputPin,
     pfd_state: Rc<RefCell<PiFaceDigitalState>>,
 }
 
@@ -172,10 +439,43 @@ pub struct InputPin {
 #[derive(Debug)]
 pub struct PiFaceDigitalState {
     mcp23s17: Mcp23s17,
+    burst: Rc<BurstReader>,
+    expected: ExpectedConfig,
+    health_monitor: Option<HealthMonitor>,
+    resets_detected: usize,
+    /// Set while restoring the configuration after a reset, so that a restore that
+    /// fails part way is made again by the next health check.
+    restore_pending: Cell<bool>,
+    retry_policy: RetryPolicy,
+    interrupt_queues: InterruptQueues,
+    callback_dispatcher: Option<CallbackDispatcher>,
+    background_interrupts: Option<Arc<BackgroundInterrupts>>,
+    /// Input pins that have been dropped, kept for reuse because `rppal_mcp23s17`
+    /// never frees a dropped input pin (and frees the output pin of the same number
+    /// instead).
+    returned_input_pins: [Option<rppal_mcp23s17::InputPin>; 8],
+    interrupt_recoveries: Arc<AtomicUsize>,
+    input_cache: InputCache,
+    safe_state: Option<PinSet>,
+    _lock: Option<BoardLock>,
+    #[cfg(any(test, feature = "mockspi"))]
+    virtual_board: Option<VirtualBoard>,
+    interrupt_notifier: Option<Arc<InterruptNotifier>>,
+    cancel_token: CancelToken,
+    chip_select: ChipSelect,
+    spi_clock: u32,
+    spi_mode: SpiMode,
+    #[cfg(not(any(test, feature = "mockspi")))]
+    interrupt_reader: Option<Arc<std::sync::Mutex<dispatch::InterruptReader>>>,
+    #[cfg(not(any(test, feature = "mockspi")))]
+    line_watchdog: Option<LineWatchdog>,
     #[cfg(not(any(test, feature = "mockspi")))]
     _gpio: Gpio,
+    /// Shared with the interrupt line watchdog, which samples its level.
     #[cfg(not(any(test, feature = "mockspi")))]
-    interrupt_pin: gpio::InputPin,
+    interrupt_pin: Arc<std::sync::Mutex<gpio::InputPin>>,
+    #[cfg(test)]
+    address_zero_writes: Cell<usize>,
 }
 
 /// Represents an instance of the PiFace Digital I/O expander for the Raspberry Pi.
@@ -227,20 +527,26 @@ pub struct PiFaceDigital {
 
 impl Display for PiFaceDigital {
     /// Generate a human readable display of the state.
+    ///
+    /// The registers are read in one SPI transaction with [`Registers::snapshot()`].
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for register in 0..RegisterAddress::LENGTH {
-            let register_address = RegisterAddress::try_from(register).unwrap();
-            match self.pfd_state.borrow().mcp23s17.read(register_address) {
-                Ok(data) => writeln!(f, "{:10} : 0x{:02x}", register_address, data)?,
-                Err(e) => writeln!(f, "{:10} : {}", register_address, e)?,
-            }
+        match self.pfd_state.borrow().snapshot() {
+            Ok(snapshot) => write!(f, "{snapshot}"),
+            Err(e) => writeln!(f, "Failed to read registers: {e}"),
         }
-        Ok(())
     }
 }
 
 impl PiFaceDigital {
     /// Create a PiFace Digital instance.
+    ///
+    /// The board still needs to be initialised with [`PiFaceDigital::init()`].
+    /// [`PiFaceDigital::builder()`] validates the settings and returns a board that's
+    /// ready to use.
+    ///
+    /// The board is locked against use by other processes, returning
+    /// `Err(`[`PiFaceDigitalError::BoardInUse`]`)` if it is already in use (see
+    /// [`PiFaceDigitalBuilder::exclusive()`] to share a board deliberately).
     pub fn new(
         address: HardwareAddress,
         spi_bus: SpiBus,
@@ -248,17 +554,100 @@ impl PiFaceDigital {
         spi_clock: u32,
         spi_mode: SpiMode,
     ) -> Result<Self> {
-        let mcp23s17 = Mcp23s17::new(address.into(), spi_bus, chip_select, spi_clock, spi_mode)?;
+        PiFaceDigital::open(
+            address,
+            spi_bus,
+            chip_select,
+            spi_clock,
+            spi_mode,
+            PiFaceDigital::DEFAULT_INTERRUPT_GPIO,
+            lock::EXCLUSIVE_BY_DEFAULT,
+        )
+    }
+
+    /// Lock the board, if `exclusive`, and open the SPI bus and the interrupt GPIO.
+    fn open(
+        address: HardwareAddress,
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+        interrupt_gpio: u8,
+        exclusive: bool,
+    ) -> Result<Self> {
+        let lock = exclusive
+            .then(|| BoardLock::acquire(spi_bus, chip_select, address))
+            .transpose()?;
+        let mcp23s17 = Mcp23s17::new(address.into(), spi_bus, chip_select, spi_clock, spi_mode)
+            .map_err(PiFaceDigitalError::from)
+            .context(ErrorContext::operation("opening SPI bus").board(address))?;
+        let burst = BurstReader::open(address, spi_bus, chip_select, spi_clock, spi_mode)
+            .context(ErrorContext::operation("opening SPI bus").board(address))?;
+        // There is no interrupt GPIO to open in testing environments.
         #[cfg(any(test, feature = "mockspi"))]
-        let pfd_state = PiFaceDigitalState { mcp23s17 };
+        let _ = interrupt_gpio;
+        #[cfg(any(test, feature = "mockspi"))]
+        let pfd_state = PiFaceDigitalState {
+            mcp23s17,
+            burst: Rc::new(burst),
+            expected: ExpectedConfig::default(),
+            health_monitor: None,
+            resets_detected: 0,
+            restore_pending: Cell::new(false),
+            retry_policy: RetryPolicy::default(),
+            interrupt_queues: InterruptQueues::default(),
+            callback_dispatcher: None,
+            background_interrupts: None,
+            returned_input_pins: Default::default(),
+            interrupt_recoveries: Arc::default(),
+            input_cache: InputCache::default(),
+            safe_state: None,
+            _lock: lock,
+            virtual_board: None,
+            interrupt_notifier: None,
+            cancel_token: CancelToken::default(),
+            chip_select,
+            spi_clock,
+            spi_mode,
+            #[cfg(test)]
+            address_zero_writes: Cell::new(0),
+        };
         #[cfg(not(any(test, feature = "mockspi")))]
         let pfd_state = {
-            let gpio = Gpio::new()?;
-            let interrupt_pin = gpio.get(25)?.into_input();
+            let opening_gpio = ErrorContext::operation("opening interrupt GPIO").board(address);
+            let gpio = Gpio::new()
+                .map_err(PiFaceDigitalError::from)
+                .context(opening_gpio)?;
+            let interrupt_pin = gpio
+                .get(interrupt_gpio)
+                .map_err(PiFaceDigitalError::from)
+                .context(opening_gpio)?
+                .into_input();
             PiFaceDigitalState {
                 mcp23s17,
+                burst: Rc::new(burst),
+                expected: ExpectedConfig::default(),
+                health_monitor: None,
+                resets_detected: 0,
+                restore_pending: Cell::new(false),
+                retry_policy: RetryPolicy::default(),
+                interrupt_queues: InterruptQueues::default(),
+                callback_dispatcher: None,
+                background_interrupts: None,
+                returned_input_pins: Default::default(),
+                interrupt_recoveries: Arc::default(),
+                input_cache: InputCache::default(),
+                safe_state: None,
+                _lock: lock,
+                interrupt_notifier: None,
+                cancel_token: CancelToken::default(),
+                chip_select,
+                spi_clock,
+                spi_mode,
+                interrupt_reader: None,
+                line_watchdog: None,
                 _gpio: gpio,
-                interrupt_pin,
+                interrupt_pin: Arc::new(std::sync::Mutex::new(interrupt_pin)),
             }
         };
         Ok(PiFaceDigital {
@@ -276,6 +665,10 @@ impl PiFaceDigital {
     /// your instance to overwrite what may now be non-default config (_e.g._ because
     /// pins have been constructed.)
     ///
+    /// Initialising turns all the outputs off. To take over a board that is already
+    /// running while keeping its outputs as they are, use [`PiFaceDigital::attach()`]
+    /// instead.
+    ///
     /// ## Default settings
     ///
     /// | Register  | Purpose              | GPIO-A side<br>(Output) | GPIO-B side<br>(Input) |
@@ -285,7 +678,7 @@ impl PiFaceDigital {
     /// |  GPINTEN  | GPIO interrupt enable| 0x00        | 0x00        |
     /// |  DEFVAL   | Default value        | 0x00        | 0x00        |
     /// |  INTCON   | Interrupt control    | 0x00        | 0x00        |
-    /// |  IOCON    | I/O control          | 0x28        | (Note 1)    |
+    /// |  IOCON    | I/O control          | 0x08        | (Note 1)    |
     /// |  GPPU     | GPIO pull-up         | 0x00        | 0xFF<br>(Note 3) |
     /// |  INTF     | Interrupt Flag       |             |             |
     /// |  INTCAP   | Interrupt capture    |             |             |
@@ -299,7 +692,8 @@ impl PiFaceDigital {
     ///
     ///     - `BANK` off
     ///     - `MIRROR` off
-    ///     - `SEQOP` off
+    ///     - `SEQOP` on (sequential operation, so that consecutive registers can be
+    ///       read in one SPI transaction)
     ///     - `DISSLW` enabled (this is an I<sup>2</sup>C function so effectively "don't care")
     ///     - `HAEN` on
     ///     - `ODR` off
@@ -322,74 +716,26 @@ impl PiFaceDigital {
 
         // First ensure IOCON is correct so that register addressing is set appropriately.
         // It can't be done in the table below because the bits() function isn't const.
-        let iocon = (IOCON::BANK_OFF
-            | IOCON::MIRROR_OFF
-            | IOCON::SEQOP_OFF
-            | IOCON::DISSLW_SLEW_RATE_CONTROLLED
-            | IOCON::HAEN_ON
-            | IOCON::ODR_OFF
-            | IOCON::INTPOL_LOW)
-            .bits();
-        self.pfd_state
-            .borrow()
-            .mcp23s17
-            .write(RegisterAddress::IOCON, iocon)?;
+        // After power-on the device only answers at address 0 until HAEN is set.
+        let initialising = ErrorContext::operation("initialising");
+        let iocon = default_iocon();
 
-        // There are no acknowledgements in the SPI protocol so read-back the value to
+        // There are no acknowledgements in the SPI protocol so IOCON is read back to
         // assess whether there's actually anything connected.
-        if self
-            .pfd_state
-            .borrow()
-            .mcp23s17
-            .read(RegisterAddress::IOCON)?
-            != iocon
         {
-            return Err(PiFaceDigitalError::NoHardwareDetected {
-                spi_bus: self.pfd_state.borrow().mcp23s17.get_spi_bus(),
-                hardware_address: self
-                    .pfd_state
-                    .borrow()
-                    .mcp23s17
-                    .get_hardware_address()
-                    .try_into()
-                    .expect("MCP23S17 hardware address limited to PiFace Digital range"),
-            });
+            let pfd_state = self.pfd_state.borrow();
+            if pfd_state.write_iocon(iocon).context(initialising)? != iocon {
+                return Err(pfd_state.no_hardware_detected());
+            }
         }
 
         // Log debug info about the current register state.
         debug!("Uninitialised MCP23S17 state:\n{self}");
 
-        const RESET_REGISTER_STATES: [(RegisterAddress, Option<u8>); RegisterAddress::LENGTH] = [
-            (RegisterAddress::IODIRA, Some(0x00)),
-            (RegisterAddress::IODIRB, Some(0xFF)),
-            (RegisterAddress::IPOLA, Some(0x00)),
-            (RegisterAddress::IPOLB, Some(0x00)),
-            (RegisterAddress::GPINTENA, Some(0x00)),
-            (RegisterAddress::GPINTENB, Some(0x00)),
-            (RegisterAddress::DEFVALA, Some(0x00)),
-            (RegisterAddress::DEFVALB, Some(0x00)),
-            (RegisterAddress::INTCONA, Some(0x00)),
-            (RegisterAddress::INTCONB, Some(0x00)),
-            (RegisterAddress::IOCON, None),
-            (RegisterAddress::IOCON2, None),
-            (RegisterAddress::GPPUA, Some(0x00)),
-            (RegisterAddress::GPPUB, Some(0xFF)),
-            (RegisterAddress::INTFA, None),
-            (RegisterAddress::INTFB, None),
-            (RegisterAddress::INTCAPA, None),
-            (RegisterAddress::INTCAPB, None),
-            (RegisterAddress::GPIOA, Some(0x00)),
-            (RegisterAddress::GPIOB, None),
-            (RegisterAddress::OLATA, None),
-            (RegisterAddress::OLATB, None),
-        ];
-
         for (register_address, default_value) in RESET_REGISTER_STATES {
             if let Some(data) = default_value {
-                self.pfd_state
-                    .borrow()
-                    .mcp23s17
-                    .write(register_address, data)?;
+                self.write_register(register_address, data)
+                    .context(initialising)?;
                 debug!("New {register_address:?} register state: 0x{data:02x}");
             }
         }
@@ -397,13 +743,18 @@ impl PiFaceDigital {
         // Log debug info about the updated register state.
         debug!("Initialised MCP23S17 state:\n{self}");
 
+        // The health monitor now has a known-good configuration to check against.
+        self.pfd_state.borrow_mut().expected = ExpectedConfig::default();
+
         // Enable the GPIO interrupts. The MCP23S17 should be in a state where all
         // interrupts are disabled so there shouldn't be an immediate trigger.
         #[cfg(not(any(test, feature = "mockspi")))]
         self.pfd_state
-            .borrow_mut()
-            .interrupt_pin
-            .set_interrupt(Trigger::FallingEdge, None)?;
+            .borrow()
+            .lock_interrupt_pin()
+            .set_interrupt(Trigger::FallingEdge, None)
+            .map_err(PiFaceDigitalError::from)
+            .context(initialising)?;
 
         Ok(())
     }
@@ -411,249 +762,359 @@ impl PiFaceDigital {
     /// Returns an [`InputPin`] for the specified pin number configured as a
     /// high-impedance input.
     ///
-    /// If the pin is already in use, or the pin number `pin` is greater than 7 then
-    /// `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
+    /// The pin can be given as a [`PinNumber`] or as a `u8`, in which case a pin
+    /// number greater than 7 returns
+    /// `Err(`[`PiFaceDigitalError::PinNumberBoundsError`]`)`. If the pin is already in
+    /// use then `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
     /// with the source error type of `rppal_mcp23s17::Mcp23s17Error::PinNotAvailable`.
     ///
     /// After the [`InputPin`] goes out of scope, it can be retrieved again through
     /// another `get_input_pin()` call.
     ///
     /// When constructed, the pin has interrupts disabled.
-    pub fn get_input_pin(&self, pin: u8) -> Result<InputPin> {
+    pub fn get_input_pin<N>(&self, pin: N) -> Result<InputPin<Floating>>
+    where
+        N: TryInto<PinNumber>,
+        PiFaceDigitalError: From<N::Error>,
+    {
         // Get the unconfigured Pin (assuming it's available) and then convert it to
         // an InputPin (i.e. high-impedance input).
-        Ok(InputPin {
-            pin: self
-                .pfd_state
-                .borrow()
-                .mcp23s17
-                .get(rppal_mcp23s17::Port::GpioB, pin)?
-                .into_input_pin()?,
-            interrupts_enabled: false,
-            pfd_state: self.pfd_state.clone(),
-        })
+        self.claim_input_pin(pin.try_into()?, false, |pin| pin.into_input_pin())
     }
 
     /// Returns an [`InputPin`] for the specified pin number configured with a pull-up
     /// resistor.
     ///
-    /// If the pin is already in use, or the pin number `pin` is greater than 7 then
-    /// `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
+    /// The pin can be given as a [`PinNumber`] or as a `u8`, in which case a pin
+    /// number greater than 7 returns
+    /// `Err(`[`PiFaceDigitalError::PinNumberBoundsError`]`)`. If the pin is already in
+    /// use then `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
     /// with the source error type of `rppal_mcp23s17::Mcp23s17Error::PinNotAvailable`.
     ///
     /// After the [`InputPin`] goes out of scope, it can be retrieved again through
     /// another `get_input_pin()` call.
     ///
     /// When constructed, the pin has interrupts disabled.
-    pub fn get_pull_up_input_pin(&self, pin: u8) -> Result<InputPin> {
+    pub fn get_pull_up_input_pin<N>(&self, pin: N) -> Result<InputPin<PullUp>>
+    where
+        N: TryInto<PinNumber>,
+        PiFaceDigitalError: From<N::Error>,
+    {
         // Get the unconfigured Pin (assuming it's available) and then convert it to
-        // an InputPin (i.e. high-impedance input).
-        Ok(InputPin {
-            pin: self
-                .pfd_state
-                .borrow()
-                .mcp23s17
-                .get(rppal_mcp23s17::Port::GpioB, pin)?
-                .into_pullup_input_pin()?,
-            interrupts_enabled: false,
-            pfd_state: self.pfd_state.clone(),
-        })
+        // an InputPin with the pull-up enabled.
+        self.claim_input_pin(pin.try_into()?, true, |pin| pin.into_pullup_input_pin())
     }
 
     /// Returns an [`OutputPin`] for the specified pin number.
     ///
-    /// If the pin is already in use, or the pin number `pin` is greater than 7 then
-    /// `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
+    /// The pin can be given as a [`PinNumber`] or as a `u8`, in which case a pin
+    /// number greater than 7 returns
+    /// `Err(`[`PiFaceDigitalError::PinNumberBoundsError`]`)`. If the pin is already in
+    /// use then `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
     /// with the source error type of `rppal_mcp23s17::Mcp23s17Error::PinNotAvailable`.
     ///
     /// After the [`OutputPin`] goes out of scope, it can be retrieved again through
     /// another `get_output_pin()` call.
-    pub fn get_output_pin(&self, pin: u8) -> Result<OutputPin> {
+    pub fn get_output_pin<N>(&self, pin: N) -> Result<OutputPin>
+    where
+        N: TryInto<PinNumber>,
+        PiFaceDigitalError: From<N::Error>,
+    {
+        let pin: PinNumber = pin.try_into()?;
         // Get the unconfigured Pin (assuming it's available) and then convert it to
-        // an InputPin (i.e. high-impedance input).
-        Ok(self
-            .pfd_state
-            .borrow()
-            .mcp23s17
-            .get(rppal_mcp23s17::Port::GpioA, pin)?
-            .into_output_pin()?)
+        // an OutputPin.
+        Ok(OutputPin {
+            pin: self.claim_output_pin(pin, |pin| pin.into_output_pin())?,
+            pfd_state: self.pfd_state.clone(),
+        })
     }
 
     /// Returns an [`OutputPin`] for the specified pin number already set high.
     ///
-    /// If the pin is already in use, or the pin number `pin` is greater than 7 then
-    /// `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
+    /// The pin can be given as a [`PinNumber`] or as a `u8`, in which case a pin
+    /// number greater than 7 returns
+    /// `Err(`[`PiFaceDigitalError::PinNumberBoundsError`]`)`. If the pin is already in
+    /// use then `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
     /// with the source error type of `rppal_mcp23s17::Mcp23s17Error::PinNotAvailable`.
     ///
     /// After the [`OutputPin`] goes out of scope, it can be retrieved again through
     /// another `get_output_pin()` call.
-    pub fn get_output_pin_high(&self, pin: u8) -> Result<OutputPin> {
+    pub fn get_output_pin_high<N>(&self, pin: N) -> Result<OutputPin>
+    where
+        N: TryInto<PinNumber>,
+        PiFaceDigitalError: From<N::Error>,
+    {
+        let pin: PinNumber = pin.try_into()?;
         // Get the unconfigured Pin (assuming it's available) and then convert it to
-        // an InputPin (i.e. high-impedance input).
-        Ok(self
-            .pfd_state
-            .borrow()
-            .mcp23s17
-            .get(rppal_mcp23s17::Port::GpioA, pin)?
-            .into_output_pin_high()?)
+        // an OutputPin.
+        Ok(OutputPin {
+            pin: self.claim_output_pin(pin, |pin| pin.into_output_pin_high())?,
+            pfd_state: self.pfd_state.clone(),
+        })
     }
 
     /// Returns an [`OutputPin`] for the specified pin number already set low.
     ///
-    /// If the pin is already in use, or the pin number `pin` is greater than 7 then
-    /// `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
+    /// The pin can be given as a [`PinNumber`] or as a `u8`, in which case a pin
+    /// number greater than 7 returns
+    /// `Err(`[`PiFaceDigitalError::PinNumberBoundsError`]`)`. If the pin is already in
+    /// use then `get_input_pin()` returns `Err(`[`PiFaceDigitalError::Mcp23s17Error`]`)`
     /// with the source error type of `rppal_mcp23s17::Mcp23s17Error::PinNotAvailable`.
     ///
     /// After the [`OutputPin`] goes out of scope, it can be retrieved again through
     /// another `get_output_pin()` call.
-    pub fn get_output_pin_low(&self, pin: u8) -> Result<OutputPin> {
+    pub fn get_output_pin_low<N>(&self, pin: N) -> Result<OutputPin>
+    where
+        N: TryInto<PinNumber>,
+        PiFaceDigitalError: From<N::Error>,
+    {
+        let pin: PinNumber = pin.try_into()?;
         // Get the unconfigured Pin (assuming it's available) and then convert it to
-        // an InputPin (i.e. high-impedance input).
-        Ok(self
-            .pfd_state
-            .borrow()
+        // an OutputPin.
+        Ok(OutputPin {
+            pin: self.claim_output_pin(pin, |pin| pin.into_output_pin_low())?,
+            pfd_state: self.pfd_state.clone(),
+        })
+    }
+
+    /// Get an input pin from the MCP23S17 and convert it with `convert`, which
+    /// enables the pull-up or not according to `pull_up`.
+    fn claim_input_pin<P>(
+        &self,
+        pin: PinNumber,
+        pull_up: bool,
+        convert: impl FnOnce(rppal_mcp23s17::Pin) -> rppal_mcp23s17::Result<rppal_mcp23s17::InputPin>,
+    ) -> Result<InputPin<P>> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        let claiming = pfd_state.context("claiming input pin").pin(pin);
+        let index = usize::from(u8::from(pin));
+        let input_pin = if pfd_state.returned_input_pins[index].is_some() {
+            // A pin returned to the board only needs its pull-up setting.
+            pfd_state
+                .retry(|mcp23s17| {
+                    if pull_up {
+                        Ok(mcp23s17.set_bit(RegisterAddress::GPPUB, pin.into())?)
+                    } else {
+                        Ok(mcp23s17.clear_bit(RegisterAddress::GPPUB, pin.into())?)
+                    }
+                })
+                .context(claiming.register(RegisterAddress::GPPUB))?;
+            pfd_state.returned_input_pins[index]
+                .take()
+                .expect("Returned pin present")
+        } else {
+            pfd_state
+                .mcp23s17
+                .get(rppal_mcp23s17::Port::GpioB, pin.into())
+                .and_then(convert)
+                .map_err(PiFaceDigitalError::from)
+                .context(claiming)?
+        };
+        drop(pfd_state);
+        ExpectedConfig::update_bit(
+            &mut self.pfd_state.borrow_mut().expected.gppub,
+            pin,
+            pull_up,
+        );
+        Ok(InputPin {
+            inner: InputPinInner {
+                pin: Some(input_pin),
+                interrupt_mode: InterruptMode::None,
+                active_level: ActiveLevel::High,
+                pfd_state: self.pfd_state.clone(),
+            },
+            _state: PhantomData,
+        })
+    }
+
+    /// Get an output pin from the MCP23S17 and convert it with `convert`.
+    fn claim_output_pin(
+        &self,
+        pin: PinNumber,
+        convert: impl FnOnce(rppal_mcp23s17::Pin) -> rppal_mcp23s17::Result<rppal_mcp23s17::OutputPin>,
+    ) -> Result<rppal_mcp23s17::OutputPin> {
+        let pfd_state = self.pfd_state.borrow();
+        pfd_state
             .mcp23s17
-            .get(rppal_mcp23s17::Port::GpioA, pin)?
-            .into_output_pin_high()?)
+            .get(rppal_mcp23s17::Port::GpioA, pin.into())
+            .and_then(convert)
+            .map_err(PiFaceDigitalError::from)
+            .context(pfd_state.context("claiming output pin").pin(pin))
     }
 
-    // Waits for interrupts across a set of InputPins (actual docs are included because
-    // two flavours of this function exist)
+    // Waits for interrupts across a set of InputPins (actual docs are included from a
+    // separate file because they are extensive).
     #[doc = include_str!("sync-interrupts.md")]
-    #[cfg(not(any(test, feature = "mockspi")))]
-    pub fn poll_interrupts<'a>(
+    pub fn poll_interrupts<P>(
         &self,
-        pins: &[&'a InputPin],
+        pins: &[&InputPin<P, InterruptsEnabled>],
         reset: bool,
         timeout: Option<Duration>,
-    ) -> Result<Option<Vec<(&'a InputPin, Level)>>> {
-        // Including a pin that can't raise interrupts is considered a coding error.
-        for pin in pins {
-            assert!(
-                pin.interrupts_enabled(),
-                "InputPin({}) included in poll() does not have interrupts enabled!",
-                pin.get_pin_number()
-            );
-        }
-
+    ) -> Result<Option<PinLevels>> {
         let mut pfd_state = self.pfd_state.borrow_mut();
+        let polling = pfd_state.context("polling interrupts");
+        pfd_state.cancel_token.check()?;
 
-        match pfd_state.interrupt_pin.poll_interrupt(reset, timeout)? {
-            Some(_level) => {
-                // There was an interrupt so work out what pin/pins registered it and
-                // get the input capture so we can report the levels on the pins.
-                let interrupt_flags = pfd_state.mcp23s17.read(RegisterAddress::INTFB)?;
-                let input_port = pfd_state.mcp23s17.read(RegisterAddress::INTCAPB)?;
-                let mut interrupting_pins = Vec::new();
-
-                for pin in pins {
-                    let pin_no = pin.get_pin_number();
-                    if (interrupt_flags & (0x01 << pin_no)) != 0 {
-                        let level: Level = (input_port & (0x01 << pin_no)).into();
-                        debug!("Active interrupt on pin {pin_no} level {level}");
-                        interrupting_pins.push((*pin, level));
-                    }
-                }
+        // Interrupts already serviced by an earlier poll are reported without waiting.
+        if pins
+            .iter()
+            .all(|pin| pfd_state.interrupt_queues.len(pin.get_pin_number()) == 0)
+        {
+            if !pfd_state
+                .wait_for_interrupt(reset, timeout)
+                .context(polling)?
+            {
+                // Poll timed out.
+                return Ok(None);
+            }
+            pfd_state.service_interrupt().context(polling)?;
+        }
 
-                // Finding no active interrupts may be intentional but most likely
-                // indicates a misconfiguration, so log a warning.
-                if interrupting_pins.is_empty() {
-                    warn!(
-                        "No interrupts on any of pins {pins:?} - will poll again but interrupt will have been lost!"
-                    );
-                }
-                Ok(Some(interrupting_pins))
+        let mut interrupting_pins = PinLevels::default();
+        for pin in pins {
+            let pin_no = pin.get_pin_number();
+            if let Some(level) = pfd_state.interrupt_queues.pop(pin_no) {
+                debug!("Active interrupt on pin {pin_no} level {level}");
+                interrupting_pins.insert(pin_no, level);
             }
+        }
 
-            // Poll timed out.
-            None => Ok(None),
+        // Finding no active interrupts may be intentional but most likely indicates a
+        // misconfiguration, so log a warning.
+        if interrupting_pins.is_empty() {
+            warn!(
+                "No interrupts on any of pins {pins:?} - any interrupts on other pins have been queued for them"
+            );
         }
+        Ok(Some(interrupting_pins))
     }
 
-    // Dummy ("mockspi") version of synchronous interrupt poll (actual docs are included
-    // because two flavours of this function exist).
-    #[doc = include_str!("sync-interrupts.md")]
-    #[cfg(any(test, feature = "mockspi"))]
-    pub fn poll_interrupts<'a>(
+    /// Waits for the next interrupt on the board and returns it whole.
+    ///
+    /// This is the lightweight alternative to [`PiFaceDigital::poll_interrupts()`] for
+    /// high interrupt rates: the interrupt flags and captured levels of all the pins
+    /// are read in one SPI transaction and returned on the stack as a
+    /// [`PfdInterrupt`], with nothing allocated or queued. Any pin that raised the
+    /// interrupt is in [`PfdInterrupt::flags`], and the flags may be empty if the
+    /// interrupt line was asserted by another device sharing it.
+    ///
+    /// As the interrupt isn't queued for the individual pins, avoid mixing this with
+    /// [`InputPin::poll_interrupt()`], [`PiFaceDigital::poll_interrupts()`] or
+    /// callbacks, which would each miss the interrupts taken by the other.
+    ///
+    /// If the timeout expires the function returns [`None`]. If the poll is cancelled
+    /// through a [`CancelToken`] it returns [`PiFaceDigitalError::Cancelled`].
+    ///
+    /// ```no_run
+    /// use rppal_pfd::{
+    ///     ChipSelect, HardwareAddress, InterruptMode, PiFaceDigital, PinSet, SpiBus, SpiMode,
+    /// };
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    /// pfd.init().expect("Failed to initialise!");
+    ///
+    /// let counter = pfd
+    ///     .get_input_pin(7)
+    ///     .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+    ///     .expect("Bad pin");
+    /// let mut edges = 0u64;
+    /// while let Some(interrupt) = pfd.poll_board_interrupt(false, None).expect("Bad poll") {
+    ///     if interrupt.is_flagged(counter.get_pin_number()) {
+    ///         edges += 1;
+    ///     }
+    /// }
+    /// ```
+    pub fn poll_board_interrupt(
         &self,
-        pins: &[&'a InputPin],
-        _reset: bool,
-        _timeout: Option<Duration>,
-    ) -> Result<Option<Vec<(&'a InputPin, Level)>>> {
-        for pin in pins {
-            assert!(
-                pin.interrupts_enabled(),
-                "InputPin({}) included in poll() does not have interrupts enabled!",
-                pin.get_pin_number()
-            );
+        reset: bool,
+        timeout: Option<Duration>,
+    ) -> Result<Option<PfdInterrupt>> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        let polling = pfd_state.context("polling board interrupt");
+        pfd_state.cancel_token.check()?;
+        if !pfd_state
+            .wait_for_interrupt(reset, timeout)
+            .context(polling)?
+        {
+            return Ok(None);
+        }
+        if let Some(interrupt) = pfd_state.background_interrupt() {
+            return Ok(Some(interrupt));
         }
-        let mut _pfd_state = self.pfd_state.borrow_mut();
+        pfd_state.read_interrupt().context(polling).map(Some)
+    }
 
-        Ok(None)
+    /// Access the Interrupt Capture register for the input port.
+    ///
+    /// Reading the capture clears the interrupt for every pin without queueing it, so
+    /// avoid mixing this with [`PiFaceDigital::poll_interrupts()`] or
+    /// [`InputPin::poll_interrupt()`]. It also discards the input cache (see
+    /// [`PiFaceDigital::enable_input_cache()`]).
+    pub fn get_interrupt_capture(&self) -> Result<PinSet> {
+        let capture = self.read_register(RegisterAddress::INTCAPB)?;
+        self.pfd_state.borrow().input_cache.invalidate();
+        Ok(PinSet::from_bits(capture))
     }
 
-    // Asynchronous interrupt poll (actual docs are included because two flavours of this
-    // function exist).
-    #[doc = include_str!("async-interrupts.md")]
-    #[cfg(not(any(test, feature = "mockspi")))]
-    pub fn subscribe_async_interrupts<C: FnMut(GpioEvent) + Send + 'static>(
-        &mut self,
-        callback: C,
-    ) -> Result<()> {
-        self.pfd_state
-            .borrow_mut()
-            .interrupt_pin
-            .set_async_interrupt(Trigger::FallingEdge, None, callback)?;
-        Ok(())
+    /// Access the Interrupt Flag register for the input port.
+    pub fn get_interrupt_flags(&self) -> Result<PinSet> {
+        self.read_register(RegisterAddress::INTFB)
+            .map(PinSet::from_bits)
     }
 
-    // Dummy ("mockspi") version of asynchronous interrupt poll (actual docs are included
-    // because two flavours of this function exist).
-    #[doc = include_str!("async-interrupts.md")]
-    #[cfg(any(test, feature = "mockspi"))]
-    pub fn subscribe_async_interrupts<C: FnMut(bool) + Send + 'static>(
-        &mut self,
-        _callback: C,
-    ) -> Result<()> {
-        let mut _pfd_state = self.pfd_state.borrow_mut();
-        Ok(())
+    /// Read all the input pins at once, returning the set of pins that are at
+    /// [`Level::High`].
+    ///
+    /// The levels are as read by the MCP23S17 so, unlike [`InputPin::read()`], take no
+    /// account of any [`ActiveLevel`] set on the individual pins. Pins that haven't
+    /// been claimed as [`InputPin`]s are read too.
+    ///
+    /// If the input cache is enabled (see [`PiFaceDigital::enable_input_cache()`]) and
+    /// interrupts are enabled on all the pins, the levels are taken from the cache.
+    pub fn read_inputs(&self) -> Result<PinSet> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        let context = pfd_state.context("reading inputs");
+        pfd_state.read_inputs(PinSet::ALL).context(context)
     }
 
-    /// Clear the registered callback for interrupt notifications.
-    #[cfg(not(any(test, feature = "mockspi")))]
-    pub fn clear_async_interrupts(&mut self) -> Result<()> {
-        self.pfd_state
-            .borrow_mut()
-            .interrupt_pin
-            .clear_async_interrupt()?;
-        Ok(())
+    /// Read back all the output pins at once, returning the set of pins that are set
+    /// to [`Level::High`].
+    pub fn read_outputs(&self) -> Result<PinSet> {
+        self.read_register(RegisterAddress::OLATA)
+            .map(PinSet::from_bits)
     }
 
-    /// Register a callback for interrupt notifications.
-    #[cfg(any(test, feature = "mockspi"))]
-    pub fn clear_async_interrupts(&mut self) -> Result<()> {
-        let _pfd_state = self.pfd_state.borrow_mut();
-        Ok(())
+    /// Read an MCP23S17 register applying the [`RetryPolicy`].
+    fn read_register(&self, register: RegisterAddress) -> Result<u8> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        pfd_state
+            .retry(|mcp23s17| Ok(mcp23s17.read(register)?))
+            .context(pfd_state.board_context().register(register))
     }
 
-    /// Access the Interrupt Capture register for the input port.
-    pub fn get_interrupt_capture(&self) -> Result<u8> {
-        let data = self
-            .pfd_state
-            .borrow()
-            .mcp23s17
-            .read(RegisterAddress::INTCAPB)?;
-        Ok(data)
+    /// Read an MCP23S17 register applying the [`RetryPolicy`] but without escalation
+    /// (which would be meaningless while the device is being initialised, and would
+    /// overwrite the outputs while it is being attached).
+    fn read_register_without_escalation(&self, register: RegisterAddress) -> Result<u8> {
+        let pfd_state = self.pfd_state.borrow();
+        pfd_state
+            .attempt(|mcp23s17| Ok(mcp23s17.read(register)?))
+            .context(pfd_state.board_context().register(register))
     }
 
-    /// Access the Interrupt Capture register for the input port.
-    pub fn get_interrupt_flags(&self) -> Result<u8> {
-        let data = self
-            .pfd_state
-            .borrow()
-            .mcp23s17
-            .read(RegisterAddress::INTFB)?;
-        Ok(data)
+    /// Write an MCP23S17 register applying the [`RetryPolicy`] but without escalation
+    /// (which would be meaningless while the device is being initialised).
+    fn write_register(&self, register: RegisterAddress, data: u8) -> Result<()> {
+        let pfd_state = self.pfd_state.borrow();
+        pfd_state
+            .attempt(|mcp23s17| Ok(mcp23s17.write(register, data)?))
+            .context(pfd_state.board_context().register(register))
     }
 
     /// Generate a debug log containing the state of the MCP23S17.
@@ -691,17 +1152,53 @@ impl PiFaceDigital {
     /// pfd.init().expect("Failed to initialise!");
     ///
     /// // The IOCON register gets set once and then read back by the initialise to
-    /// // test that there's actually some hardware connected. The 0x28 represents the
+    /// // test that there's actually some hardware connected. The 0x08 represents the
     /// // default configuration.
     /// assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON),
-    ///     (0x28, 1, 1));
+    ///     (0x08, 1, 1));
     /// ```
-
     #[cfg(any(test, feature = "mockspi"))]
     pub fn get_mock_data(&self, register: RegisterAddress) -> (u8, usize, usize) {
         self.pfd_state.borrow().mcp23s17.get_mock_data(register)
     }
 
+    /// In testing environments provide an API to count the SPI transactions made to the
+    /// mock SPI, so that the cost of operations can be measured.
+    ///
+    /// Each access to a single register counts as one transaction, as does each burst
+    /// read of several registers (which isn't counted against the individual registers
+    /// by [`PiFaceDigital::get_mock_data()`]).
+    ///
+    /// ```
+    /// use rppal_pfd::{ChipSelect, HardwareAddress, PiFaceDigital, SpiBus, SpiMode};
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    /// pfd.init().expect("Failed to initialise!");
+    ///
+    /// // Reading all 22 registers takes a single burst.
+    /// let before = pfd.get_mock_transactions();
+    /// let _ = pfd.to_string();
+    /// assert_eq!(pfd.get_mock_transactions() - before, 1);
+    /// ```
+    #[cfg(any(test, feature = "mockspi"))]
+    pub fn get_mock_transactions(&self) -> usize {
+        let pfd_state = self.pfd_state.borrow();
+        let single_register_transactions: usize = (0..RegisterAddress::LENGTH)
+            .map(|register| {
+                let register = RegisterAddress::try_from(register).unwrap();
+                let (_, reads, writes) = pfd_state.mcp23s17.get_mock_data(register);
+                reads + writes
+            })
+            .sum();
+        single_register_transactions + pfd_state.burst.transactions()
+    }
+
     /// In testing environments provide an API to get access to the mock SPI that
     /// allows unit tests to be run without a real Raspberry Pi.
     ///
@@ -723,10 +1220,10 @@ impl PiFaceDigital {
     /// pfd.init().expect("Failed to initialise!");
     ///
     /// // The IOCON register gets set once and then read back by the initialise to
-    /// // test that there's actually some hardware connected. The 0x28 represents the
+    /// // test that there's actually some hardware connected. The 0x08 represents the
     /// // default configuration.
     /// assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON),
-    ///     (0x28, 1, 1));
+    ///     (0x08, 1, 1));
     /// ```
     #[cfg(any(test, feature = "mockspi"))]
     pub fn set_mock_data(&self, register: RegisterAddress, data: u8) {
@@ -737,7 +1234,48 @@ impl PiFaceDigital {
     }
 }
 
-impl Default for PiFaceDigital {
+/// The state of each register after [`PiFaceDigital::init()`], or `None` if it isn't
+/// written.
+const RESET_REGISTER_STATES: [(RegisterAddress, Option<u8>); RegisterAddress::LENGTH] = [
+    (RegisterAddress::IODIRA, Some(0x00)),
+    (RegisterAddress::IODIRB, Some(0xFF)),
+    (RegisterAddress::IPOLA, Some(0x00)),
+    (RegisterAddress::IPOLB, Some(0x00)),
+    (RegisterAddress::GPINTENA, Some(0x00)),
+    (RegisterAddress::GPINTENB, Some(0x00)),
+    (RegisterAddress::DEFVALA, Some(0x00)),
+    (RegisterAddress::DEFVALB, Some(0x00)),
+    (RegisterAddress::INTCONA, Some(0x00)),
+    (RegisterAddress::INTCONB, Some(0x00)),
+    (RegisterAddress::IOCON, None),
+    (RegisterAddress::IOCON2, None),
+    (RegisterAddress::GPPUA, Some(0x00)),
+    (RegisterAddress::GPPUB, Some(0xFF)),
+    (RegisterAddress::INTFA, None),
+    (RegisterAddress::INTFB, None),
+    (RegisterAddress::INTCAPA, None),
+    (RegisterAddress::INTCAPB, None),
+    (RegisterAddress::GPIOA, Some(0x00)),
+    (RegisterAddress::GPIOB, None),
+    (RegisterAddress::OLATA, None),
+    (RegisterAddress::OLATB, None),
+];
+
+/// The `IOCON` configuration required by the PiFace Digital.
+///
+/// See [`PiFaceDigital::init()`] for the meaning of the individual bits.
+fn default_iocon() -> u8 {
+    (IOCON::BANK_OFF
+        | IOCON::MIRROR_OFF
+        | IOCON::SEQOP_ON
+        | IOCON::DISSLW_SLEW_RATE_CONTROLLED
+        | IOCON::HAEN_ON
+        | IOCON::ODR_OFF
+        | IOCON::INTPOL_LOW)
+        .bits()
+}
+
+impl PiFaceDigital {
     /// Creates a default PiFaceDigital that:
     ///
     /// - Is at hardware address `0`
@@ -745,80 +1283,256 @@ impl Default for PiFaceDigital {
     /// - Uses chip-select `Cs0`
     /// - Is clocked at 100kHz
     /// - Uses SPI mode 0
-    fn default() -> Self {
+    ///
+    /// This replaces the `Default` implementation, which had to panic if the SPI bus or
+    /// GPIO couldn't be opened.
+    pub fn with_defaults() -> Result<Self> {
         PiFaceDigital::new(
-            HardwareAddress::new(0).unwrap(),
+            HardwareAddress(0),
             SpiBus::Spi0,
             ChipSelect::Cs0,
             100_000,
             SpiMode::Mode0,
         )
-        .expect("Failed to create default PiFaceDigital")
     }
 }
 
-impl InputPin {
+impl<P, I: InterruptState> InputPin<P, I> {
     /// Reads the pin's logic level.
+    ///
+    /// This is the logical level, so is inverted if the pin is [`ActiveLevel::Low`].
+    ///
+    /// If the input cache is enabled (see [`PiFaceDigital::enable_input_cache()`]) and
+    /// interrupts are enabled on the pin, the level is taken from the cache.
     #[inline]
     pub fn read(&self) -> Result<Level> {
-        Ok(self.pin.read()?)
+        let inner = &self.inner;
+        let mut pfd_state = inner.pfd_state.borrow_mut();
+        let pin_no = inner.pin_number();
+        let context = pfd_state.context("reading input pin").pin(pin_no);
+        #[cfg(any(test, feature = "mockspi"))]
+        pfd_state.pump_virtual_board();
+        if pfd_state.input_cache.is_enabled() {
+            return pfd_state
+                .read_inputs(pin_no.into())
+                .map(|inputs| Level::from(inputs.contains(pin_no)))
+                .context(context);
+        }
+        pfd_state
+            .retry(|_| Ok(inner.mcp23s17_pin().read()?))
+            .context(context.register(RegisterAddress::GPIOB))
     }
 
     /// Reads the pin's logic level, and returns [`true`] if it is set to
     /// [`Level::Low`].
     #[inline]
     pub fn is_low(&self) -> Result<bool> {
-        Ok(self.pin.read()? == Level::Low)
+        Ok(self.read()? == Level::Low)
     }
 
     /// Reads the pin's logic level, and returns [`true`] if it is set to
     /// [`Level::High`].
     #[inline]
     pub fn is_high(&self) -> Result<bool> {
-        Ok(self.pin.read()? == Level::High)
+        Ok(self.read()? == Level::High)
     }
 
-    /// Enable synchronous interrupts.
+    /// Set which physical level on the pin is considered to be "active".
     ///
-    /// Synchronous interrupts can be polled once enabled by either:
+    /// Setting [`ActiveLevel::Low`] sets the pin's bit in the MCP23S17's `IPOLB`
+    /// register so that the device itself inverts the input. Thereafter, all the
+    /// levels reported for the pin are _logical_ levels in which [`Level::High`]
+    /// means active. That includes [`InputPin::read()`], the levels returned by
+    /// [`InputPin::poll_interrupt()`] and [`PiFaceDigital::poll_interrupts()`] and the
+    /// pin's bit in [`PiFaceDigital::get_interrupt_capture()`].
     ///
-    /// - Calling [`InputPin::poll_interrupt()`] in the case where just one `InputPin`
-    ///   is configured to raise interrupts.
-    /// - Calling [`PiFaceDigital::poll_interrupts()`] in the case where more than one
-    ///   InputPin is configured to raise interrupts.
+    /// Pins are [`ActiveLevel::High`] when constructed and revert to it when dropped.
     ///
-    /// Interrupts can be disabled by a call to [`InputPin::clear_interrupt()`] and
-    /// will also be automatically disabled when the `InputPin` is dropped.
-    pub fn set_interrupt(&mut self, mode: InterruptMode) -> Result<()> {
-        self.interrupts_enabled = true;
-        self.pin.set_interrupt_mode(mode).map_err(|e| e.into())
-    }
-
-    /// Disable synchronous interrupts on the pin.
+    /// ```no_run
+    /// # use rppal_pfd::{ActiveLevel, ChipSelect, HardwareAddress, PiFaceDigital, SpiBus, SpiMode};
+    /// #
+    /// # let mut pfd = PiFaceDigital::new(
+    /// #     HardwareAddress::new(0).expect("Invalid hardware address"),
+    /// #     SpiBus::Spi0,
+    /// #     ChipSelect::Cs0,
+    /// #     100_000,
+    /// #     SpiMode::Mode0,
+    /// # )
+    /// # .expect("Failed to create PiFace Digital");
+    /// # pfd.init().expect("Failed to initialise PiFace Digital");
+    /// #
+    /// // The first push-switch on the PiFace Digital.
+    /// let mut switch = pfd.get_pull_up_input_pin(0).expect("Failed to get pin");
+    /// switch.set_active_level(ActiveLevel::Low).expect("Failed to set polarity");
+    ///
+    /// if switch.is_active().expect("Bad pin read") {
+    ///     println!("Switch is pressed");
+    /// }
+    /// ```
+    pub fn set_active_level(&mut self, active_level: ActiveLevel) -> Result<()> {
+        self.inner.set_active_level(active_level)
+    }
+
+    /// Get which physical level on the pin is considered to be "active".
+    pub fn active_level(&self) -> ActiveLevel {
+        self.inner.active_level
+    }
+
+    /// Reads the pin, and returns [`true`] if it is active according to its
+    /// [`ActiveLevel`].
+    pub fn is_active(&self) -> Result<bool> {
+        self.is_high()
+    }
+
+    /// Reads the pin, and returns [`true`] if it is inactive according to its
+    /// [`ActiveLevel`].
+    pub fn is_inactive(&self) -> Result<bool> {
+        self.is_low()
+    }
+
+    /// Get the interrupt mode that the pin is configured with.
+    pub fn interrupt_mode(&self) -> InterruptMode {
+        self.inner.interrupt_mode
+    }
+
+    /// Get the pin number that this pin is connected to.
+    pub fn get_pin_number(&self) -> PinNumber {
+        self.inner.pin_number()
+    }
+
+    /// Get the interrupt state.
+    pub fn interrupts_enabled(&self) -> bool {
+        I::ENABLED
+    }
+}
+
+impl<P> InputPin<P, InterruptsDisabled> {
+    /// Enable synchronous interrupts.
     ///
-    /// Note that:
+    /// Synchronous interrupts can be polled once enabled by either:
     ///
-    /// - Multiple calls to `clear_interrupt()` are permitted (though don't do anything
-    ///   useful).
-    /// - If not explicitly disabled, the interrupts will be disabled when the pin
-    ///   is dropped.
-    pub fn clear_interrupt(&mut self) -> Result<()> {
-        self.interrupts_enabled = false;
-        self.pin
-            .set_interrupt_mode(InterruptMode::None)
-            .map_err(|e| e.into())
+    /// - Calling [`InputPin::poll_interrupt()`] in the case where just one `InputPin`
+    ///   is configured to raise interrupts.
+    /// - Calling [`PiFaceDigital::poll_interrupts()`] in the case where more than one
+    ///   InputPin is configured to raise interrupts.
+    ///
+    /// Neither can be called on a pin that doesn't have interrupts enabled. A `mode`
+    /// of [`InterruptMode::None`] wouldn't enable interrupts, so returns
+    /// [`PiFaceDigitalError::InterruptsNotEnabled`].
+    ///
+    /// Interrupts can be disabled again with [`InputPin::into_interrupts_disabled()`]
+    /// and will also be automatically disabled when the `InputPin` is dropped.
+    pub fn into_interrupts_enabled(
+        mut self,
+        mode: InterruptMode,
+    ) -> Result<InputPin<P, InterruptsEnabled>> {
+        if mode == InterruptMode::None {
+            return Err(PiFaceDigitalError::InterruptsNotEnabled(
+                self.get_pin_number(),
+            ));
+        }
+        self.inner.set_interrupt_mode(mode)?;
+        Ok(self.retype())
     }
 
-    /// Wait for an interrupt (or timeout) on this pin.
+    /// Enable interrupts whenever the input differs from a reference level.
     ///
-    /// Must only be called if interrupts have been enabled - calling with interrupts
-    /// disabled is considered a coding error and will panic.
+    /// Rather than interrupting on a change of the input, the MCP23S17 compares the
+    /// input with the pin's bit in `DEFVALB` and holds an interrupt for as long as
+    /// they differ. This suits inputs such as alarm zones where the interesting
+    /// condition is "the door is not closed" rather than "the door has just opened".
+    /// The comparison is made on the same logical level as reported by
+    /// [`InputPin::read()`] so takes account of the pin's [`ActiveLevel`].
+    ///
+    /// This configures the same registers as [`InterruptMode::ActiveHigh`] (for a
+    /// `reference` of [`Level::Low`]) or [`InterruptMode::ActiveLow`] (for a
+    /// `reference` of [`Level::High`]) but expresses the intent directly.
+    ///
+    /// ## Interaction with interrupt handling
+    ///
+    /// Reading the interrupt capture clears the interrupt but, if the input still
+    /// differs from `reference`, the MCP23S17 immediately raises it again. So:
+    ///
+    /// - [`InputPin::poll_interrupt()`] and [`PiFaceDigital::poll_interrupts()`] return
+    ///   the pin again on every call for as long as the condition persists. Poll with
+    ///   a timeout and treat repeated reports as a reminder rather than a new event,
+    ///   or disable interrupts once the condition has been noted.
+    /// - The interrupt line pulses high briefly each time the capture is read, so
+    ///   callbacks registered with [`PiFaceDigital::subscribe_async_interrupts()`]
+    ///   are called repeatedly, at a rate set by how quickly each call reads the
+    ///   capture, until the condition clears.
+    ///
+    /// ```no_run
+    /// # use rppal_pfd::{ChipSelect, HardwareAddress, Level, PiFaceDigital, SpiBus, SpiMode};
+    /// # use std::time::Duration;
+    /// #
+    /// # let mut pfd = PiFaceDigital::new(
+    /// #     HardwareAddress::new(0).expect("Invalid hardware address"),
+    /// #     SpiBus::Spi0,
+    /// #     ChipSelect::Cs0,
+    /// #     100_000,
+    /// #     SpiMode::Mode0,
+    /// # )
+    /// # .expect("Failed to create PiFace Digital");
+    /// # pfd.init().expect("Failed to initialise PiFace Digital");
+    /// #
+    /// // A door contact on input 5 that pulls the input low when the door is closed.
+    /// let mut door = pfd
+    ///     .get_pull_up_input_pin(5)
+    ///     .and_then(|pin| pin.into_compare_interrupt(Level::Low))
+    ///     .expect("Failed to enable interrupts");
+    ///
+    /// while let Ok(Some(_)) = door.poll_interrupt(false, Some(Duration::from_secs(10))) {
+    ///     println!("Door is open!");
+    /// }
+    /// ```
+    pub fn into_compare_interrupt(
+        self,
+        reference: Level,
+    ) -> Result<InputPin<P, InterruptsEnabled>> {
+        self.into_interrupts_enabled(match reference {
+            Level::Low => InterruptMode::ActiveHigh,
+            Level::High => InterruptMode::ActiveLow,
+        })
+    }
+}
+
+impl<P> InputPin<P, InterruptsEnabled> {
+    /// Get the reference level if the pin has interrupts enabled in compare mode (see
+    /// [`InputPin::into_compare_interrupt()`]).
+    pub fn compare_reference(&self) -> Option<Level> {
+        match self.inner.interrupt_mode {
+            InterruptMode::ActiveHigh => Some(Level::Low),
+            InterruptMode::ActiveLow => Some(Level::High),
+            InterruptMode::None | InterruptMode::BothEdges => None,
+        }
+    }
+
+    /// Disable interrupts on the pin.
+    ///
+    /// Any interrupts serviced for the pin but not yet polled are discarded and any
+    /// callbacks registered for the pin are removed. If not explicitly disabled, the
+    /// interrupts will be disabled when the pin is dropped.
+    pub fn into_interrupts_disabled(mut self) -> Result<InputPin<P, InterruptsDisabled>> {
+        self.inner.unregister_callbacks();
+        self.inner.set_interrupt_mode(InterruptMode::None)?;
+        Ok(self.retype())
+    }
+
+    /// Wait for an interrupt (or timeout) on this pin.
     ///
     /// If `reset` is `true` it will cause the GPIO interrupts to be flushed before
     /// starting the poll.
     ///
+    /// Returns the level on the pin captured by the MCP23S17 at the time of the
+    /// interrupt. If an interrupt on this pin has already been serviced (by a poll on
+    /// another pin or by [`PiFaceDigital::poll_interrupts()`]) it is returned
+    /// immediately without waiting. Interrupts on other pins that are serviced while
+    /// waiting are queued for those pins rather than being lost.
+    ///
     /// If no interrupts have happened after `timeout`, the function will exit returning
-    /// `Ok(None))`.
+    /// `Ok(None))`. If the poll is cancelled through a [`CancelToken`], it returns
+    /// [`PiFaceDigitalError::Cancelled`].
     ///
     /// Note that interrupts will have been re-enabled by the time that the poll returns
     /// so there may be repeated interrupts.
@@ -839,8 +1553,9 @@ impl InputPin {
     ///     .expect("Failed to create PFD");
     /// pfd.init().expect("Failed to initialise PFD");
     ///
-    /// let mut pin = pfd.get_input_pin(0).expect("Failed to get pin");
-    /// pin.set_interrupt(InterruptMode::BothEdges)
+    /// let mut pin = pfd
+    ///     .get_input_pin(0)
+    ///     .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
     ///     .expect("Failed to enable interrupts");
     ///
     /// match pin.poll_interrupt(false, Some(Duration::from_secs(60))) {
@@ -856,10 +1571,32 @@ impl InputPin {
     ///
     ///     Err(e) => {
     ///         eprintln!("Poll failed with {e}");
-    ///     }     
+    ///     }
     /// }
+    /// ```
     ///
-    #[cfg(not(any(test, feature = "mockspi")))]
+    /// Polling a pin without interrupts enabled doesn't compile:
+    ///
+    /// ```compile_fail
+    /// # use rppal_pfd::{ChipSelect, HardwareAddress, PiFaceDigital, SpiBus, SpiMode};
+    /// # let pfd = PiFaceDigital::new(
+    /// #     HardwareAddress::new(0).unwrap(),
+    /// #     SpiBus::Spi0,
+    /// #     ChipSelect::Cs0,
+    /// #     100_000,
+    /// #     SpiMode::Mode0,
+    /// # )
+    /// # .unwrap();
+    /// let mut pin = pfd.get_input_pin(0).unwrap();
+    /// pin.poll_interrupt(false, None);
+    /// ```
+    ///
+    /// ## Testing
+    ///
+    /// Note that in testing environments or with the `mockspi` feature enabled, there
+    /// is no GPIO to wait on so the poll never blocks. Instead, the interrupt line is
+    /// treated as asserted whenever the mock `INTFB` register has any flags set and,
+    /// if none are, the poll returns immediately as if the timeout had expired.
     pub fn poll_interrupt(
         &mut self,
         reset: bool,
@@ -867,79 +1604,235 @@ impl InputPin {
     ) -> Result<Option<Level>> {
         use std::time::Instant;
 
-        assert!(
-            self.interrupts_enabled,
-            "InputPin({}): No interrupts enabled before trying to poll()",
-            self.get_pin_number()
-        );
-
+        let pin_no = self.get_pin_number();
+        let mut pfd_state = self.inner.pfd_state.borrow_mut();
+        let polling = pfd_state.context("polling interrupt").pin(pin_no);
+        pfd_state.cancel_token.check()?;
         let wait_until = timeout.map(|delay| Instant::now() + delay);
 
         // The interrupt line may be asserted by any pin on this device or, potentially,
-        // other PiFace Digital devices on the same SPI bus.  Check the relevant INTFB
-        // bit to see if this pin caused the interrupt.
+        // other PiFace Digital devices on the same SPI bus. Servicing the interrupt
+        // queues it for whichever pins raised it so keep waiting until this one has.
         loop {
-            let timeout = wait_until.map(|end_time| end_time - Instant::now());
-            let mut pfd_state = self.pfd_state.borrow_mut();
-            match pfd_state.interrupt_pin.poll_interrupt(reset, timeout)? {
-                Some(_level) => {
-                    if pfd_state
-                        .mcp23s17
-                        .get_bit(RegisterAddress::INTFB, self.pin.get_pin_number())?
-                        .into()
-                    {
-                        // We did raise the interrupt condition.
-                        info!("Received interrupt on pin {}", self.pin.get_pin_number());
-                        return Ok(Some(self.read()?));
-                    } else {
-                        // Wasn't this pin. We have to read the port to clear the
-                        // interrupt but this probably wasn't what was intended so raise
-                        // a warning.
-                        warn!(
-                            "Interrupt was not on pin {} - will poll again but interrupt will have been lost!",
-                            self.pin.get_pin_number()
-                        );
-                        let _ = self.read()?;
-                    }
+            if let Some(level) = pfd_state.interrupt_queues.pop(pin_no) {
+                info!("Received interrupt on pin {pin_no}");
+                return Ok(Some(level));
+            }
+
+            let timeout =
+                wait_until.map(|end_time| end_time.saturating_duration_since(Instant::now()));
+            if !pfd_state
+                .wait_for_interrupt(reset, timeout)
+                .context(polling)?
+            {
+                return Ok(None);
+            }
+            if !pfd_state
+                .service_interrupt()
+                .context(polling)?
+                .is_flagged(pin_no)
+            {
+                debug!("Interrupt was not on pin {pin_no} - queued for other pins");
+            }
+        }
+    }
+
+    /// Get the number of interrupts on this pin that have been serviced but are
+    /// waiting to be polled.
+    pub fn pending_interrupts(&self) -> usize {
+        self.inner
+            .pfd_state
+            .borrow()
+            .interrupt_queues
+            .len(self.get_pin_number())
+    }
+}
+
+impl InputPinInner {
+    /// Get the pin number that this pin is connected to.
+    pub(crate) fn pin_number(&self) -> PinNumber {
+        PinNumber(self.mcp23s17_pin().get_pin_number())
+    }
+
+    /// The underlying MCP23S17 pin, which is only missing once dropped.
+    fn mcp23s17_pin(&self) -> &rppal_mcp23s17::InputPin {
+        self.pin.as_ref().expect("Input pin already returned")
+    }
+
+    fn set_active_level(&mut self, active_level: ActiveLevel) -> Result<()> {
+        let pin_no = self.pin_number();
+        let invert = active_level == ActiveLevel::Low;
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        pfd_state
+            .retry(|mcp23s17| {
+                if invert {
+                    Ok(mcp23s17.set_bit(RegisterAddress::IPOLB, pin_no.into())?)
+                } else {
+                    Ok(mcp23s17.clear_bit(RegisterAddress::IPOLB, pin_no.into())?)
                 }
-                None => return Ok(None),
+            })
+            .context(
+                pfd_state
+                    .context("setting active level")
+                    .pin(pin_no)
+                    .register(RegisterAddress::IPOLB),
+            )?;
+        ExpectedConfig::update_bit(&mut pfd_state.expected.ipolb, pin_no, invert);
+        pfd_state.input_cache.invalidate();
+        self.active_level = active_level;
+        Ok(())
+    }
+
+    /// Set the interrupt mode, discarding any interrupts waiting to be polled if
+    /// interrupts are being disabled.
+    fn set_interrupt_mode(&mut self, mode: InterruptMode) -> Result<()> {
+        let operation = match mode {
+            InterruptMode::None => "clearing interrupt mode",
+            _ => "setting interrupt mode",
+        };
+        self.pin
+            .as_mut()
+            .expect("Input pin already returned")
+            .set_interrupt_mode(mode)
+            .map_err(PiFaceDigitalError::from)
+            .context(self.context(operation))?;
+        self.interrupt_mode = mode;
+        self.track_interrupt_mode(mode);
+        // The pin may have changed since the inputs were cached without raising an
+        // interrupt.
+        self.pfd_state.borrow().input_cache.invalidate();
+        if mode == InterruptMode::None {
+            self.pfd_state
+                .borrow_mut()
+                .interrupt_queues
+                .clear(self.pin_number());
+        }
+        Ok(())
+    }
+
+    /// Keep the health monitor's view of the interrupt registers in step with the
+    /// changes `rppal_mcp23s17` makes when setting the interrupt mode.
+    fn track_interrupt_mode(&self, mode: InterruptMode) {
+        let bit = self.pin_number();
+        let expected = &mut self.pfd_state.borrow_mut().expected;
+        match mode {
+            InterruptMode::None => {
+                ExpectedConfig::update_bit(&mut expected.gpintenb, bit, false);
+            }
+            InterruptMode::ActiveHigh => {
+                ExpectedConfig::update_bit(&mut expected.intconb, bit, true);
+                ExpectedConfig::update_bit(&mut expected.defvalb, bit, false);
+                ExpectedConfig::update_bit(&mut expected.gpintenb, bit, true);
+            }
+            InterruptMode::ActiveLow => {
+                ExpectedConfig::update_bit(&mut expected.intconb, bit, true);
+                ExpectedConfig::update_bit(&mut expected.defvalb, bit, true);
+                ExpectedConfig::update_bit(&mut expected.gpintenb, bit, true);
+            }
+            InterruptMode::BothEdges => {
+                ExpectedConfig::update_bit(&mut expected.intconb, bit, false);
+                ExpectedConfig::update_bit(&mut expected.gpintenb, bit, true);
             }
         }
     }
 
-    /// Dummy version of interrupt poll routine for use in testing environments.
+    /// Context for an operation on this pin.
+    fn context(&self, operation: &'static str) -> ErrorContext {
+        self.pfd_state
+            .borrow()
+            .context(operation)
+            .pin(self.pin_number())
+    }
+}
+
+impl OutputPin {
+    /// Set the state of the pin.
     ///
-    /// Immediately returns as if a timeout occurred.
-    #[cfg(any(test, feature = "mockspi"))]
-    pub fn poll_interrupt(
-        &mut self,
-        _reset: bool,
-        _timeout: Option<Duration>,
-    ) -> Result<Option<Level>> {
-        assert!(
-            self.interrupts_enabled,
-            "InputPin({}): No interrupts enabled before trying to poll()",
-            self.get_pin_number()
-        );
-        Ok(None)
+    /// If the [`RetryPolicy`] requires writes to be verified, the pin is read back
+    /// after writing and a mismatch is treated as a failed write.
+    pub fn write(&self, level: Level) -> Result<()> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        let verify_writes = pfd_state.retry_policy.verify_writes;
+        let pin_no = self.get_pin_number();
+        pfd_state
+            .retry(|_| {
+                self.pin.write(level)?;
+                if verify_writes && self.pin.read()? != level {
+                    return Err(PiFaceDigitalError::WriteVerifyFailed { pin: pin_no, level });
+                }
+                Ok(())
+            })
+            .context(
+                pfd_state
+                    .context("writing output pin")
+                    .pin(pin_no)
+                    .register(RegisterAddress::OLATA),
+            )?;
+        ExpectedConfig::update_bit(&mut pfd_state.expected.olata, pin_no, level == Level::High);
+        #[cfg(any(test, feature = "mockspi"))]
+        pfd_state.pump_virtual_board();
+        Ok(())
     }
 
-    /// Get the pin number (0-7) that this pin is connected to.
-    pub fn get_pin_number(&self) -> u8 {
-        self.pin.get_pin_number()
+    /// Set the output to `Level::High`.
+    pub fn set_high(&self) -> Result<()> {
+        self.write(Level::High)
     }
 
-    /// Get the interrupt state.
-    pub fn interrupts_enabled(&self) -> bool {
-        self.interrupts_enabled
+    /// Set the output to `Level::Low`.
+    pub fn set_low(&self) -> Result<()> {
+        self.write(Level::Low)
+    }
+
+    /// Reads the pin's logic level.
+    pub fn read(&self) -> Result<Level> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        let context = pfd_state
+            .context("reading output pin")
+            .pin(self.get_pin_number());
+        pfd_state.retry(|_| Ok(self.pin.read()?)).context(context)
+    }
+
+    /// Reads the pin's logic level, and returns [`true`] if it is set to
+    /// [`Level::Low`].
+    pub fn is_low(&self) -> Result<bool> {
+        Ok(self.read()? == Level::Low)
+    }
+
+    /// Reads the pin's logic level, and returns [`true`] if it is set to
+    /// [`Level::High`].
+    pub fn is_high(&self) -> Result<bool> {
+        Ok(self.read()? == Level::High)
+    }
+
+    /// Get the pin number that this pin is connected to.
+    pub fn get_pin_number(&self) -> PinNumber {
+        PinNumber(self.pin.get_pin_number())
     }
 }
 
-impl Drop for InputPin {
+impl Drop for InputPinInner {
     fn drop(&mut self) {
-        if self.interrupts_enabled {
-            self.clear_interrupt()
-                .expect("InputPin failed to clear interrupts on Drop");
+        self.unregister_callbacks();
+        if self.interrupt_mode != InterruptMode::None {
+            if let Err(e) = self.set_interrupt_mode(InterruptMode::None) {
+                warn!(
+                    "InputPin({}) failed to clear interrupts on Drop: {e}",
+                    self.pin_number()
+                );
+            }
+        }
+        if self.active_level != ActiveLevel::High {
+            if let Err(e) = self.set_active_level(ActiveLevel::High) {
+                warn!(
+                    "InputPin({}) failed to restore polarity on Drop: {e}",
+                    self.pin_number()
+                );
+            }
+        }
+        let pin_no = usize::from(u8::from(self.pin_number()));
+        if let Ok(mut pfd_state) = self.pfd_state.try_borrow_mut() {
+            pfd_state.returned_input_pins[pin_no] = self.pin.take();
         }
     }
 }
@@ -972,7 +1865,7 @@ DEFVALA    : 0x00
 DEFVALB    : 0x00
 INTCONA    : 0x00
 INTCONB    : 0x00
-IOCON      : 0x28
+IOCON      : 0x08
 IOCON (2)  : 0x00
 GPPUA      : 0x00
 GPPUB      : 0xff
@@ -1002,15 +1895,15 @@ OLATB      : 0x00
         .expect("Failed to create PFD");
         pfd.init().expect("Failed to initialise PFD");
 
-        let mut pin = pfd.get_input_pin(0).expect("Failed to get pin");
-        pin.set_interrupt(InterruptMode::BothEdges)
-            .expect("Failed to enable interrupts");
+        let mut pin = pfd
+            .get_input_pin(0)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
 
         assert_eq!(pin.poll_interrupt(false, None).expect("Bad poll"), None);
     }
 
     #[test]
-    #[should_panic]
     fn pfd_input_pin_poll_interrupt_bad_config() {
         let mut pfd = PiFaceDigital::new(
             HardwareAddress::new(0).unwrap(),
@@ -1022,9 +1915,47 @@ OLATB      : 0x00
         .expect("Failed to create PFD");
         pfd.init().expect("Failed to initialise PFD");
 
-        let mut pin = pfd.get_input_pin(0).expect("Failed to get pin");
+        // Polling a pin without interrupts is a compile error, so the only way to get
+        // it wrong is to ask for no interrupts.
+        assert!(matches!(
+            pfd.get_input_pin(0)
+                .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::None)),
+            Err(PiFaceDigitalError::InterruptsNotEnabled(PinNumber(0)))
+        ));
+    }
+
+    #[test]
+    fn pfd_input_pin_conversions() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
 
-        let _ = pin.poll_interrupt(false, None).expect("Bad poll");
+        let pin: InputPin<Floating> = pfd.get_input_pin(2).expect("Failed to get pin");
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0b1111_1011);
+        let pin: InputPin<PullUp> = pin.into_pull_up().expect("Failed to convert");
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0b1111_1111);
+        assert!(!pin.interrupts_enabled());
+
+        let pin: InputPin<PullUp, InterruptsEnabled> = pin
+            .into_interrupts_enabled(InterruptMode::BothEdges)
+            .expect("Failed to convert");
+        assert!(pin.interrupts_enabled());
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPINTENB).0, 0b0000_0100);
+
+        let pin: InputPin<Floating, InterruptsEnabled> =
+            pin.into_floating().expect("Failed to convert");
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0b1111_1011);
+        assert_eq!(pin.interrupt_mode(), InterruptMode::BothEdges);
+
+        let pin: InputPin = pin.into_interrupts_disabled().expect("Failed to convert");
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPINTENB).0, 0b0000_0000);
+        assert_eq!(pin.interrupt_mode(), InterruptMode::None);
     }
 
     #[test]
@@ -1039,12 +1970,14 @@ OLATB      : 0x00
         .expect("Failed to create PFD");
         pfd.init().expect("Failed to initialise PFD");
 
-        let mut pin1 = pfd.get_input_pin(0).expect("Failed to get pin");
-        pin1.set_interrupt(InterruptMode::BothEdges)
-            .expect("Failed to enable interrupts");
-        let mut pin2 = pfd.get_input_pin(1).expect("Failed to get pin");
-        pin2.set_interrupt(InterruptMode::BothEdges)
-            .expect("Failed to enable interrupts");
+        let pin1 = pfd
+            .get_input_pin(0)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        let pin2 = pfd
+            .get_input_pin(1)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
 
         let interrupt_pins = [&pin1, &pin2];
         if let Some(interrupting_pins) = pfd
@@ -1056,8 +1989,13 @@ OLATB      : 0x00
     }
 
     #[test]
-    #[should_panic]
-    fn pfd_input_pins_poll_interrupts_bad_config() {
+    fn pfd_with_defaults() {
+        let pfd = PiFaceDigital::with_defaults().expect("Failed to create PFD");
+        assert_eq!(pfd.spi_clock(), 100_000);
+    }
+
+    #[test]
+    fn pfd_split() {
         let mut pfd = PiFaceDigital::new(
             HardwareAddress::new(0).unwrap(),
             SpiBus::Spi0,
@@ -1068,13 +2006,135 @@ OLATB      : 0x00
         .expect("Failed to create PFD");
         pfd.init().expect("Failed to initialise PFD");
 
-        let mut pin1 = pfd.get_input_pin(0).expect("Failed to get pin");
-        pin1.set_interrupt(InterruptMode::BothEdges)
-            .expect("Failed to enable interrupts");
-        let pin2 = pfd.get_input_pin(1).expect("Failed to get pin");
+        {
+            let Components {
+                relays,
+                leds,
+                switches,
+                inputs,
+            } = pfd.split().expect("Failed to split PFD");
+            assert_eq!(relays[1].number(), 1);
+            assert_eq!(leds[0].number(), 2);
+            assert_eq!(inputs[3].get_pin_number(), 7);
+            assert!(matches!(
+                pfd.get_output_pin(0),
+                Err(PiFaceDigitalError::Mcp23s17Error { .. })
+            ));
+            assert!(pfd.split().is_err());
+
+            relays[0].energise().expect("Bad write");
+            leds[5].on().expect("Bad write");
+            assert_eq!(pfd.get_mock_data(RegisterAddress::GPIOA).0, 0b1000_0001);
+            assert!(relays[0].is_energised().expect("Bad read"));
+            relays[0].de_energise().expect("Bad write");
+            leds[5].toggle().expect("Bad write");
+            assert_eq!(pfd.get_mock_data(RegisterAddress::GPIOA).0, 0b0000_0000);
+
+            // Switches are active-low with pull-ups.
+            assert_eq!(pfd.get_mock_data(RegisterAddress::IPOLB).0, 0b0000_1111);
+            assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0b1111_1111);
+            pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0100);
+            assert!(switches[2].is_pressed().expect("Bad read"));
+            assert!(switches[0].is_released().expect("Bad read"));
+        }
 
-        let interrupt_pins = [&pin1, &pin2];
-        let _ = pfd.poll_interrupts(&interrupt_pins, false, None);
+        // The pins are available again once the components are dropped.
+        pfd.get_output_pin(0).expect("Failed to get pin");
+        pfd.get_input_pin(0).expect("Failed to get pin");
+    }
+
+    #[test]
+    fn pfd_split_leds() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let components = pfd.split().expect("Failed to split PFD");
+        for output in 0..8 {
+            assert_eq!(components.led(output).expect("Bad LED").number(), output);
+        }
+        assert!(matches!(
+            components.led(8),
+            Err(PiFaceDigitalError::PinNumberBoundsError { .. })
+        ));
+
+        // The LEDs on outputs 0 and 1 are the relays'.
+        components.led(1).expect("Bad LED").on().expect("Bad write");
+        assert!(components.relays[1].is_energised().expect("Bad read"));
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPIOA).0, 0b0000_0010);
+    }
+
+    #[test]
+    fn pfd_split_again() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let Components {
+            relays,
+            leds,
+            switches,
+            inputs,
+        } = pfd.split().expect("Failed to split PFD");
+
+        // Dropping a switch frees its input pin, but not the output of the same number
+        // that its relay still holds.
+        let [switch0, other_switches @ ..] = switches;
+        drop(switch0);
+        assert!(matches!(
+            pfd.get_output_pin(0),
+            Err(PiFaceDigitalError::Mcp23s17Error { .. })
+        ));
+        let input0 = pfd.get_input_pin(0).expect("Failed to get pin");
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0b1111_1110);
+        drop(input0);
+
+        drop((relays, leds, other_switches, inputs));
+        let components = pfd.split().expect("Failed to split PFD again");
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0b1111_1111);
+        assert_eq!(components.switches[0].number(), 0);
+    }
+
+    #[test]
+    fn pfd_error_context() {
+        let pfd = PiFaceDigital::new(
+            HardwareAddress::new(2).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        let _pin = pfd.get_input_pin(3).expect("Failed to get pin");
+        let error = pfd.get_input_pin(3).expect_err("Pin claimed twice");
+        assert_eq!(
+            error.context(),
+            Some(&ErrorContext {
+                operation: Some("claiming input pin"),
+                board: Some(HardwareAddress::new(2).unwrap()),
+                pin: Some(PinNumber(3)),
+                register: None,
+            })
+        );
+        // The underlying error is left to the source chain rather than repeated.
+        assert_eq!(
+            error.to_string(),
+            "MCP23S17 error while claiming input pin (board 2, pin 3)"
+        );
+        assert!(std::error::Error::source(&error).is_some());
+        assert!(PiFaceDigitalError::Cancelled.context().is_none());
     }
 
     #[test]
@@ -1094,9 +2154,10 @@ OLATB      : 0x00
         );
 
         {
-            let mut pin = pfd.get_input_pin(0).expect("Failed to get pin");
-            pin.set_interrupt(InterruptMode::BothEdges)
-                .expect("Failed to enable interrupts");
+            let _pin = pfd
+                .get_input_pin(0)
+                .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+                .expect("Failed to get pin");
             assert_eq!(
                 pfd.get_mock_data(RegisterAddress::GPINTENB),
                 (0b0000_0001, 1, 2)
@@ -1109,7 +2170,7 @@ OLATB      : 0x00
     }
 
     #[test]
-    fn pfd_input_pin_read_levels() {
+    fn pfd_input_pin_compare_interrupt() {
         let mut pfd = PiFaceDigital::new(
             HardwareAddress::new(0).unwrap(),
             SpiBus::Spi0,
@@ -1120,15 +2181,32 @@ OLATB      : 0x00
         .expect("Failed to create PFD");
         pfd.init().expect("Failed to initialise PFD");
 
-        let pin = pfd.get_input_pin(0).expect("Failed to get pin");
-        assert!(pin.is_low().expect("Bad pin access"));
-
-        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0001);
-        assert!(pin.is_high().expect("Bad pin access"));
+        let pin = pfd
+            .get_pull_up_input_pin(5)
+            .and_then(|pin| pin.into_compare_interrupt(Level::High))
+            .expect("Failed to enable interrupts");
+        assert_eq!(pin.compare_reference(), Some(Level::High));
+        assert_eq!(pin.interrupt_mode(), InterruptMode::ActiveLow);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::INTCONB).0, 0b0010_0000);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::DEFVALB).0, 0b0010_0000);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPINTENB).0, 0b0010_0000);
+
+        let pin = pin
+            .into_interrupts_disabled()
+            .and_then(|pin| pin.into_compare_interrupt(Level::Low))
+            .expect("Failed to enable interrupts");
+        assert_eq!(pin.compare_reference(), Some(Level::Low));
+        assert_eq!(pfd.get_mock_data(RegisterAddress::DEFVALB).0, 0b0000_0000);
+
+        let pin = pin
+            .into_interrupts_disabled()
+            .expect("Failed to disable interrupts");
+        assert_eq!(pin.interrupt_mode(), InterruptMode::None);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPINTENB).0, 0b0000_0000);
     }
 
     #[test]
-    fn pfd_init() {
+    fn pfd_interrupts_queued_for_other_pins() {
         let mut pfd = PiFaceDigital::new(
             HardwareAddress::new(0).unwrap(),
             SpiBus::Spi0,
@@ -1139,68 +2217,1763 @@ OLATB      : 0x00
         .expect("Failed to create PFD");
         pfd.init().expect("Failed to initialise PFD");
 
-        // Sample a few of the registers for correct values.
-        assert_eq!(
-            pfd.get_mock_data(RegisterAddress::IODIRA),
-            (0x00, 0, 1),
-            "Bad IODIRA"
-        );
-        assert_eq!(
-            pfd.get_mock_data(RegisterAddress::IODIRB),
-            (0xFF, 0, 1),
-            "Bad IODIRB"
-        );
-        assert_eq!(
-            pfd.get_mock_data(RegisterAddress::IOCON),
-            (0x28, 1, 1),
-            "Bad IOCON"
-        );
-        assert_eq!(
-            pfd.get_mock_data(RegisterAddress::GPPUB),
-            (0xFF, 0, 1),
-            "Bad GPPUB"
+        let [mut pin0, mut pin1, pin2] = [0, 1, 2].map(|pin| {
+            pfd.get_pull_up_input_pin(pin)
+                .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+                .expect("Failed to get pin")
+        });
+
+        // One interrupt flagged on pins 0 and 1 with pin 1 captured high.
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0011);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0010);
+
+        assert_eq!(pin0.poll_interrupt(false, None).unwrap(), Some(Level::Low));
+        assert_eq!(pfd.get_mock_data(RegisterAddress::INTFB).0, 0);
+        assert_eq!(pin0.pending_interrupts(), 0);
+        assert_eq!(pin1.pending_interrupts(), 1);
+
+        // Pin 1's interrupt is still delivered without the line being asserted.
+        let interrupts = pfd
+            .poll_interrupts(&[&pin1, &pin2], false, None)
+            .expect("Poll failed")
+            .expect("Poll timed out");
+        assert_eq!(interrupts.len(), 1);
+        assert_eq!(interrupts.level(pin1.get_pin_number()), Some(Level::High));
+        assert_eq!(interrupts.level(pin2.get_pin_number()), None);
+
+        assert_eq!(pin1.poll_interrupt(false, None).unwrap(), None);
+        assert!(
+            pfd.poll_interrupts(&[&pin1, &pin2], false, None)
+                .unwrap()
+                .is_none()
         );
     }
 
     #[test]
-    fn pfd_init_no_hardware() {
+    fn pfd_poll_board_interrupt() {
         let mut pfd = PiFaceDigital::new(
             HardwareAddress::new(0).unwrap(),
-            SpiBus::Spi6, // Magic value that makes mock simulate no hardware.
+            SpiBus::Spi0,
             ChipSelect::Cs0,
             100_000,
             SpiMode::Mode0,
         )
         .expect("Failed to create PFD");
-        let init_result = pfd.init();
+        pfd.init().expect("Failed to initialise PFD");
 
-        // Check we get the expected error.
-        println!("{init_result:?}");
-        match init_result {
-            Err(PiFaceDigitalError::NoHardwareDetected {
-                spi_bus: bus,
-                hardware_address: address,
-            }) => {
-                assert_eq!(bus, SpiBus::Spi6);
-                assert_eq!(address, 0.try_into().unwrap())
-            }
-            _ => panic!("Unexpected return result: {init_result:?}"),
-        }
-    }
+        let pin = pfd
+            .get_input_pin(5)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        assert_eq!(pfd.poll_board_interrupt(false, None).unwrap(), None);
 
-    #[test]
-    fn pfd_address_to_mcp23s17_addr() {
-        let pfd_addr = HardwareAddress::new(3).expect("valid HardwareAddress");
-        let mcp_addr: rppal_mcp23s17::HardwareAddress = pfd_addr.into();
-        assert_eq!(
-            mcp_addr,
-            rppal_mcp23s17::HardwareAddress::new(3).expect("valid HardwareAddress")
-        );
-    }
-    #[test]
-    fn good_hardware_address() {
-        let addr = HardwareAddress::new(2).expect("Bad address");
-        assert_eq!(2u8, addr.into(), "Unexpected address value");
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0010_0001);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0010_0000);
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0001);
+        let interrupt = pfd
+            .poll_board_interrupt(false, None)
+            .expect("Poll failed")
+            .expect("Poll timed out");
+        assert_eq!(interrupt.flags, PinSet::from_bits(0b0010_0001));
+        assert_eq!(interrupt.level(pin.get_pin_number()), Level::High);
+
+        // Read in one burst from INTFB through GPIOB, clearing the interrupt without
+        // queueing it.
+        assert_eq!(interrupt.inputs, PinSet::from_bits(0b0000_0001));
+        let before = pfd.get_mock_transactions();
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0001);
+        pfd.poll_board_interrupt(false, None)
+            .expect("Poll failed")
+            .expect("Poll timed out");
+        assert_eq!(pfd.get_mock_transactions() - before, 1);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::INTFB).0, 0);
+        assert_eq!(pin.pending_interrupts(), 0);
+    }
+
+    #[test]
+    fn pfd_input_pin_clear_interrupt_discards_queue() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let mut pin3 = pfd
+            .get_pull_up_input_pin(3)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        let pin4 = pfd
+            .get_pull_up_input_pin(4)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0001_1000);
+        assert!(pin3.poll_interrupt(false, None).unwrap().is_some());
+        assert_eq!(pin4.pending_interrupts(), 1);
+
+        let pin4 = pin4
+            .into_interrupts_disabled()
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to disable interrupts");
+        assert_eq!(pin4.pending_interrupts(), 0);
+    }
+
+    #[test]
+    fn pfd_input_pin_callbacks() {
+        use std::sync::mpsc::{RecvTimeoutError, channel};
+
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let [mut pin0, mut pin1] = [0, 1].map(|pin| {
+            pfd.get_pull_up_input_pin(pin)
+                .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+                .expect("Failed to get pin")
+        });
+        let (change_tx, change_rx) = channel();
+        pin0.on_change(move |change| change_tx.send(change).unwrap())
+            .expect("Failed to register callback");
+        let (falling_tx, falling_rx) = channel();
+        pin1.on_falling(move |change| falling_tx.send(change).unwrap())
+            .expect("Failed to register callback");
+        assert_eq!(pin0.interrupt_mode(), InterruptMode::BothEdges);
+
+        // Pin 0 rises and pin 1 rises, which only pin 0's callback wants.
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0011);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0011);
+        assert_eq!(pin1.poll_interrupt(false, None).unwrap(), Some(Level::High));
+        assert_eq!(
+            change_rx.recv_timeout(Duration::from_secs(1)),
+            Ok(PinChange {
+                pin: PinNumber(0),
+                edge: Edge::Rising,
+                level: Level::High
+            })
+        );
+
+        // Pin 1 falls.
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0010);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0000);
+        assert_eq!(pin1.poll_interrupt(false, None).unwrap(), Some(Level::Low));
+        assert_eq!(
+            falling_rx.recv_timeout(Duration::from_secs(1)),
+            Ok(PinChange {
+                pin: PinNumber(1),
+                edge: Edge::Falling,
+                level: Level::Low
+            })
+        );
+        assert_eq!(falling_rx.try_recv().ok(), None);
+
+        // Dropping the pin unregisters (and so drops) its callback.
+        drop(pin0);
+        assert_eq!(
+            change_rx.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn pfd_callback_panics() {
+        use std::sync::mpsc::channel;
+
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let [mut pin0, mut pin1] = [0, 1].map(|pin| {
+            pfd.get_pull_up_input_pin(pin)
+                .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+                .expect("Failed to get pin")
+        });
+        pin0.on_change(|_| panic!("Callback panicked"))
+            .expect("Failed to register callback");
+        let (tx, rx) = channel();
+        pin1.on_change(move |change| tx.send(change.level).unwrap())
+            .expect("Failed to register callback");
+
+        // The panic is contained, so the other callback is still run, and so is the
+        // panicking one for the next interrupt.
+        for capture in [0b0000_0011, 0b0000_0000] {
+            pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0011);
+            pfd.set_mock_data(RegisterAddress::INTCAPB, capture);
+            assert!(pin1.poll_interrupt(false, None).unwrap().is_some());
+        }
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(Level::High));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(Level::Low));
+    }
+
+    #[test]
+    fn pfd_callback_registration_while_running() {
+        use std::sync::mpsc::channel;
+
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let [mut pin0, mut pin1, pin2] = [0, 1, 2].map(|pin| {
+            pfd.get_pull_up_input_pin(pin)
+                .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+                .expect("Failed to get pin")
+        });
+        let (started_tx, started_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+        pin0.on_change(move |_| {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        })
+        .expect("Failed to register callback");
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0001);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0001);
+        assert!(pin0.poll_interrupt(false, None).unwrap().is_some());
+        started_rx
+            .recv_timeout(Duration::from_secs(1))
+            .expect("Callback not run");
+
+        // Callbacks can be registered and dropped while the first is still running.
+        let (tx, rx) = channel();
+        pin1.on_change(move |change| tx.send(change.pin).unwrap())
+            .expect("Failed to register callback");
+        drop(pin2);
+        release_tx.send(()).unwrap();
+
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0010);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0010);
+        assert!(pin1.poll_interrupt(false, None).unwrap().is_some());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(PinNumber(1)));
+    }
+
+    #[test]
+    fn pfd_compare_interrupt_callbacks() {
+        use std::sync::mpsc::channel;
+
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let mut pin = pfd
+            .get_pull_up_input_pin(3)
+            .and_then(|pin| pin.into_compare_interrupt(Level::Low))
+            .expect("Failed to get pin");
+        let (rising_tx, rising_rx) = channel();
+        pin.on_rising(move |change| rising_tx.send(change.edge).unwrap())
+            .expect("Failed to register callback");
+        let (falling_tx, falling_rx) = channel();
+        pin.on_falling(move |change| falling_tx.send(change.edge).unwrap())
+            .expect("Failed to register callback");
+
+        // The interrupt repeats while the pin is high, each time reported as rising.
+        for _ in 0..2 {
+            pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_1000);
+            pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_1000);
+            assert_eq!(pin.poll_interrupt(false, None).unwrap(), Some(Level::High));
+            assert_eq!(
+                rising_rx.recv_timeout(Duration::from_secs(1)),
+                Ok(Edge::Rising)
+            );
+        }
+
+        // Returning to the reference level raises no interrupt, so nothing falls.
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0000);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0000);
+        assert_eq!(pin.poll_interrupt(false, None).unwrap(), None);
+        assert_eq!(
+            falling_rx.recv_timeout(Duration::from_millis(100)),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn pfd_subscribe_async_interrupts() {
+        use std::sync::mpsc::channel;
+
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let (tx, rx) = channel();
+        pfd.subscribe_async_interrupts(move |interrupt| tx.send(interrupt).unwrap())
+            .expect("Failed to subscribe");
+
+        let mut pin = pfd
+            .get_pull_up_input_pin(6)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0100_0000);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0100_0001);
+        assert_eq!(pin.poll_interrupt(false, None).unwrap(), Some(Level::High));
+
+        let interrupt = rx
+            .recv_timeout(Duration::from_secs(1))
+            .expect("No interrupt");
+        assert_eq!(
+            (interrupt.flags, interrupt.capture),
+            (
+                PinSet::from_bits(0b0100_0000),
+                PinSet::from_bits(0b0100_0001)
+            )
+        );
+        assert!(interrupt.is_flagged(PinNumber(6)));
+        assert!(!interrupt.is_flagged(PinNumber(0)));
+        assert_eq!(
+            interrupt.changes().collect::<Vec<_>>(),
+            vec![PinChange {
+                pin: PinNumber(6),
+                edge: Edge::Rising,
+                level: Level::High
+            }]
+        );
+
+        pfd.clear_async_interrupts()
+            .expect("Failed to clear subscription");
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn pfd_subscribe_clears_pending_interrupt() {
+        use std::sync::mpsc::channel;
+
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        // Nothing pending so nothing to recover.
+        pfd.subscribe_async_interrupts(|_| ())
+            .expect("Failed to subscribe");
+        assert_eq!(pfd.interrupt_recoveries(), 0);
+
+        // An interrupt that was already asserted is serviced and passed to the callback.
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_1000);
+        let (tx, rx) = channel();
+        pfd.subscribe_async_interrupts(move |interrupt| tx.send(interrupt).unwrap())
+            .expect("Failed to subscribe");
+        assert_eq!(pfd.interrupt_recoveries(), 1);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::INTFB).0, 0);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(1))
+                .expect("No interrupt")
+                .flags,
+            PinSet::from_bits(0b0000_1000)
+        );
+    }
+
+    #[test]
+    fn pfd_background_interrupts() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        let mut pin = pfd
+            .get_pull_up_input_pin(2)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+
+        // Stand in for the interrupt line watcher, which is compiled out of the tests.
+        let background: Arc<BackgroundInterrupts> = Arc::default();
+        pfd.pfd_state.borrow_mut().background_interrupts = Some(Arc::clone(&background));
+        let reader = {
+            let background = Arc::clone(&background);
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                background.push(PfdInterrupt::new(0b0000_0100, 0b0000_0000, 0b1111_1011));
+                background.push(PfdInterrupt::new(0b0000_0100, 0b0000_0100, 0b1111_1111));
+                background.push(PfdInterrupt::new(0b0000_1000, 0b0000_1000, 0b1111_1111));
+            })
+        };
+
+        // The polls wait for the interrupts read in the background and take them rather
+        // than reading the interrupt registers.
+        let intfb_reads = pfd.get_mock_data(RegisterAddress::INTFB).1;
+        let levels = pfd
+            .poll_interrupts(&[&pin], false, Some(Duration::from_secs(5)))
+            .expect("Poll failed")
+            .expect("Poll timed out");
+        assert_eq!(levels.level(PinNumber(2)), Some(Level::Low));
+        reader.join().unwrap();
+        assert_eq!(
+            pin.poll_interrupt(false, Some(Duration::from_secs(5)))
+                .unwrap(),
+            Some(Level::High)
+        );
+        let interrupt = pfd
+            .poll_board_interrupt(false, Some(Duration::from_secs(5)))
+            .expect("Poll failed")
+            .expect("Poll timed out");
+        assert_eq!(interrupt.flags, PinSet::from_bits(0b0000_1000));
+        assert_eq!(pfd.get_mock_data(RegisterAddress::INTFB).1, intfb_reads);
+
+        // Nothing more was read in the background.
+        assert_eq!(
+            pin.poll_interrupt(false, Some(Duration::from_millis(10)))
+                .unwrap(),
+            None
+        );
+        assert!(pfd.service_interrupt().unwrap().is_none());
+        background.push(PfdInterrupt::new(0b0000_0100, 0b0000_0000, 0b1111_1011));
+        let interrupt = pfd
+            .service_interrupt()
+            .expect("Service failed")
+            .expect("No interrupt");
+        assert!(interrupt.is_flagged(PinNumber(2)));
+        assert_eq!(pin.pending_interrupts(), 1);
+    }
+
+    #[test]
+    fn stuck_line_detector() {
+        let mut detector = watchdog::StuckLineDetector::default();
+        assert!(!detector.check(false, 0));
+        // Asserted for the first time may just not have been serviced yet.
+        assert!(!detector.check(true, 0));
+        // Still asserted and nothing delivered - stuck.
+        assert!(detector.check(true, 0));
+        // Having recovered, needs to be seen twice again.
+        assert!(!detector.check(true, 0));
+        // Delivered in between so not stuck.
+        assert!(!detector.check(true, 1));
+        assert!(!detector.check(false, 1));
+    }
+
+    #[test]
+    fn pfd_subscriptions() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let mut pin0 = pfd
+            .get_pull_up_input_pin(0)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        let pin1 = pfd
+            .get_pull_up_input_pin(1)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+
+        let rising = pfd
+            .subscribe(SubscriptionOptions {
+                pins: PinSet::from_bits(0b0000_0010),
+                edge: Some(Edge::Rising),
+                capacity: 1,
+                backpressure: Backpressure::DropNewest,
+            })
+            .expect("Failed to subscribe");
+        let latest = pfd
+            .subscribe(SubscriptionOptions {
+                capacity: 2,
+                ..Default::default()
+            })
+            .expect("Failed to subscribe");
+        let all = pfd
+            .subscribe(SubscriptionOptions::default())
+            .expect("Failed to subscribe");
+
+        for capture in [0b0000_0010, 0b0000_0000, 0b0000_0011] {
+            pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0011);
+            pfd.set_mock_data(RegisterAddress::INTCAPB, capture);
+            assert!(pin0.poll_interrupt(false, None).unwrap().is_some());
+        }
+
+        // The last subscriber is offered each change last so once it has them all,
+        // so have the others.
+        for _ in 0..6 {
+            all.recv_timeout(Duration::from_secs(1))
+                .expect("Missing event");
+        }
+        assert_eq!(all.overflows(), 0);
+
+        assert_eq!(rising.len(), 1);
+        assert_eq!(rising.overflows(), 1);
+        assert_eq!(
+            rising.try_recv(),
+            Ok(PinChange {
+                pin: PinNumber(1),
+                edge: Edge::Rising,
+                level: Level::High
+            })
+        );
+
+        assert_eq!(latest.overflows(), 4);
+        let levels: Vec<_> = std::iter::from_fn(|| latest.try_recv().ok())
+            .map(|change| (change.pin, change.level))
+            .collect();
+        assert_eq!(
+            levels,
+            vec![(PinNumber(0), Level::High), (PinNumber(1), Level::High)]
+        );
+
+        // Dropping the board disconnects the subscribers.
+        drop(pin0);
+        drop(pin1);
+        drop(pfd);
+        assert_eq!(all.recv(), Err(std::sync::mpsc::RecvError));
+    }
+
+    #[test]
+    fn pfd_drop_with_blocked_subscriber() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let mut pin = pfd
+            .get_pull_up_input_pin(0)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        let blocked = pfd
+            .subscribe(SubscriptionOptions {
+                capacity: 1,
+                backpressure: Backpressure::Block,
+                ..Default::default()
+            })
+            .expect("Failed to subscribe");
+        let all = pfd
+            .subscribe(SubscriptionOptions::default())
+            .expect("Failed to subscribe");
+
+        // The first event fills the blocking subscriber's queue and the dispatcher
+        // waits on it with the second, which the other subscriber never sees.
+        for capture in [0b0000_0000, 0b0000_0001] {
+            pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0001);
+            pfd.set_mock_data(RegisterAddress::INTCAPB, capture);
+            assert!(pin.poll_interrupt(false, None).unwrap().is_some());
+        }
+        all.recv_timeout(Duration::from_secs(1))
+            .expect("Missing event");
+        assert_eq!(
+            all.recv_timeout(Duration::from_millis(100)),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+        );
+
+        // Neither unregistering the pin's callbacks nor dropping the board waits for
+        // the subscriber.
+        drop(pin);
+        drop(pfd);
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked.overflows(), 1);
+    }
+
+    #[test]
+    fn pfd_service_interrupt() {
+        use std::os::fd::AsRawFd;
+
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let fd = pfd.interrupt_fd().expect("Failed to get interrupt fd");
+        assert!(fd.as_raw_fd() >= 0);
+        assert!(pfd.service_interrupt().unwrap().is_none());
+
+        let mut pin = pfd
+            .get_pull_up_input_pin(7)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b1000_0000);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b1000_0000);
+        let interrupt = pfd
+            .service_interrupt()
+            .expect("Service failed")
+            .expect("No interrupt");
+        assert_eq!(interrupt.flags, PinSet::from_bits(0b1000_0000));
+        assert!(pfd.service_interrupt().unwrap().is_none());
+
+        // The interrupt is also queued for the pin.
+        assert_eq!(pin.pending_interrupts(), 1);
+        assert_eq!(pin.poll_interrupt(false, None).unwrap(), Some(Level::High));
+    }
+
+    #[cfg(feature = "mio")]
+    #[test]
+    fn pfd_interrupt_fd_mio() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let mut fd = pfd.interrupt_fd().expect("Failed to get interrupt fd");
+        let mut poll = mio::Poll::new().unwrap();
+        let mut events = mio::Events::with_capacity(4);
+        poll.registry()
+            .register(&mut fd, mio::Token(7), mio::Interest::READABLE)
+            .expect("Failed to register");
+        poll.poll(&mut events, Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(events.is_empty());
+
+        // Stand in for the interrupt line watcher, which is compiled out of the tests.
+        let notifier = pfd.pfd_state.borrow().interrupt_notifier.clone().unwrap();
+        notifier.wake();
+        poll.poll(&mut events, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(
+            events.iter().map(|event| event.token()).collect::<Vec<_>>(),
+            [mio::Token(7)]
+        );
+        poll.registry().deregister(&mut fd).unwrap();
+    }
+
+    #[cfg(feature = "calloop")]
+    #[test]
+    fn pfd_interrupt_fd_calloop() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let fd = pfd.interrupt_fd().expect("Failed to get interrupt fd");
+        let mut event_loop: calloop::EventLoop<usize> = calloop::EventLoop::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(fd, |(), &mut (), readable| *readable += 1)
+            .expect("Failed to insert source");
+        let mut readable = 0;
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut readable)
+            .unwrap();
+        assert_eq!(readable, 0);
+
+        // Stand in for the interrupt line watcher, which is compiled out of the tests.
+        let notifier = pfd.pfd_state.borrow().interrupt_notifier.clone().unwrap();
+        notifier.wake();
+        event_loop
+            .dispatch(Duration::from_secs(5), &mut readable)
+            .unwrap();
+        assert_eq!(readable, 1);
+
+        // Servicing the interrupt makes the file descriptor unreadable again.
+        assert!(pfd.service_interrupt().unwrap().is_none());
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut readable)
+            .unwrap();
+        assert_eq!(readable, 1);
+    }
+
+    #[test]
+    fn pfd_cancel_polls() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let mut pin = pfd
+            .get_pull_up_input_pin(0)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0001);
+
+        let cancel = pfd.cancel_token();
+        std::thread::spawn(move || cancel.cancel()).join().unwrap();
+        assert!(pfd.cancel_token().is_cancelled());
+        assert!(matches!(
+            pin.poll_interrupt(false, None),
+            Err(PiFaceDigitalError::Cancelled)
+        ));
+        assert!(matches!(
+            pfd.poll_interrupts(&[&pin], false, None),
+            Err(PiFaceDigitalError::Cancelled)
+        ));
+
+        pfd.cancel_token().reset();
+        assert_eq!(pin.poll_interrupt(false, None).unwrap(), Some(Level::Low));
+    }
+
+    #[test]
+    fn pfd_input_pin_read_levels() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let pin = pfd.get_input_pin(0).expect("Failed to get pin");
+        assert!(pin.is_low().expect("Bad pin access"));
+
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0001);
+        assert!(pin.is_high().expect("Bad pin access"));
+    }
+
+    #[test]
+    fn pfd_input_pin_active_low() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        {
+            let mut pin = pfd.get_pull_up_input_pin(2).expect("Failed to get pin");
+            assert_eq!(pin.active_level(), ActiveLevel::High);
+            pin.set_active_level(ActiveLevel::Low)
+                .expect("Failed to set active level");
+            assert_eq!(pin.active_level(), ActiveLevel::Low);
+            assert_eq!(pfd.get_mock_data(RegisterAddress::IPOLB).0, 0b0000_0100);
+
+            // The mock doesn't invert so simulate the MCP23S17 doing so for a pressed
+            // switch.
+            pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0100);
+            assert!(pin.is_active().expect("Bad pin access"));
+            pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0000);
+            assert!(pin.is_inactive().expect("Bad pin access"));
+        }
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IPOLB).0, 0b0000_0000);
+    }
+
+    #[test]
+    fn pfd_output_pin_initial_levels() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        pfd.set_mock_data(RegisterAddress::GPIOA, 0b0000_1000);
+        let low = pfd.get_output_pin_low(3).expect("Failed to get pin");
+        assert!(low.is_low().expect("Bad read"));
+        let high = pfd.get_output_pin_high(4).expect("Failed to get pin");
+        assert!(high.is_high().expect("Bad read"));
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPIOA).0, 0b0001_0000);
+    }
+
+    #[test]
+    fn pfd_init() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        // Sample a few of the registers for correct values.
+        assert_eq!(
+            pfd.get_mock_data(RegisterAddress::IODIRA),
+            (0x00, 0, 1),
+            "Bad IODIRA"
+        );
+        assert_eq!(
+            pfd.get_mock_data(RegisterAddress::IODIRB),
+            (0xFF, 0, 1),
+            "Bad IODIRB"
+        );
+        assert_eq!(
+            pfd.get_mock_data(RegisterAddress::IOCON),
+            (0x08, 1, 1),
+            "Bad IOCON"
+        );
+        assert_eq!(
+            pfd.get_mock_data(RegisterAddress::GPPUB),
+            (0xFF, 0, 1),
+            "Bad GPPUB"
+        );
+    }
+
+    #[test]
+    fn pfd_init_no_hardware() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi6, // Magic value that makes mock simulate no hardware.
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        let init_result = pfd.init();
+
+        // Check we get the expected error.
+        println!("{init_result:?}");
+        match init_result {
+            Err(PiFaceDigitalError::NoHardwareDetected {
+                spi_bus: bus,
+                hardware_address: address,
+            }) => {
+                assert_eq!(bus, SpiBus::Spi6);
+                assert_eq!(address, 0.try_into().unwrap())
+            }
+            _ => panic!("Unexpected return result: {init_result:?}"),
+        }
+    }
+
+    #[test]
+    fn pfd_health_check_healthy() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        // Outputs changing between checks is not a reset.
+        pfd.set_mock_data(RegisterAddress::OLATA, 0b0000_0101);
+        assert_eq!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Healthy
+        );
+        assert_eq!(pfd.resets_detected(), 0);
+
+        // Interrupts enabled through an InputPin are expected.
+        let _pin = pfd
+            .get_pull_up_input_pin(3)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        assert_eq!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Healthy
+        );
+    }
+
+    #[test]
+    fn pfd_health_check_recovers_reset() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let _pin = pfd
+            .get_input_pin(1)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::ActiveLow))
+            .expect("Failed to get pin");
+        pfd.set_mock_data(RegisterAddress::OLATA, 0b0000_0011);
+        assert_eq!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Healthy
+        );
+
+        // Simulate a power-on reset of the MCP23S17.
+        for register in 0..RegisterAddress::LENGTH {
+            pfd.set_mock_data(RegisterAddress::try_from(register).unwrap(), 0x00);
+        }
+        pfd.set_mock_data(RegisterAddress::IODIRA, 0xFF);
+        pfd.set_mock_data(RegisterAddress::IODIRB, 0xFF);
+
+        match pfd.check_health().expect("Bad check") {
+            HealthStatus::Recovered(report) => {
+                assert_eq!(report.restored_outputs, 0b0000_0011);
+                assert_eq!(
+                    report.mismatches[0],
+                    RegisterMismatch {
+                        register: RegisterAddress::IOCON,
+                        expected: 0x08,
+                        actual: 0x00
+                    }
+                );
+                assert_eq!(report.mismatches.len(), 4);
+            }
+            status => panic!("Unexpected health status: {status:?}"),
+        }
+        assert_eq!(pfd.resets_detected(), 1);
+
+        // Configuration and outputs are back as they were.
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON).0, 0x08);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IODIRA).0, 0x00);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0b1111_1101);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPINTENB).0, 0b0000_0010);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::INTCONB).0, 0b0000_0010);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::DEFVALB).0, 0b0000_0010);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::OLATA).0, 0b0000_0011);
+        assert_eq!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Healthy
+        );
+    }
+
+    #[test]
+    fn pfd_health_check_recovers_reset_at_address() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(2).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        let pfd_state = Rc::clone(&pfd.pfd_state);
+        let address_zero_writes = || pfd_state.borrow().address_zero_writes.get();
+
+        // Straight after power-on HAEN is clear, so the device only answers at address
+        // 0 until IOCON has been written there.
+        pfd.init().expect("Failed to initialise PFD");
+        assert_eq!(address_zero_writes(), 1);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON).0, 0x08);
+
+        // Re-initialising, or restoring a board that hasn't been reset, leaves address 0
+        // alone.
+        pfd.init().expect("Failed to initialise PFD");
+        pfd.pfd_state
+            .borrow()
+            .restore_config()
+            .expect("Failed to restore");
+        assert_eq!(address_zero_writes(), 1);
+
+        // After a power-on reset HAEN is clear again.
+        pfd.set_mock_data(RegisterAddress::IOCON, 0x00);
+        pfd.set_mock_data(RegisterAddress::IODIRA, 0xFF);
+        assert!(matches!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Recovered(_)
+        ));
+        assert_eq!(address_zero_writes(), 2);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON).0, 0x08);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IODIRA).0, 0x00);
+    }
+
+    #[test]
+    fn pfd_health_check_while_waiting() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        let mut pin = pfd
+            .get_pull_up_input_pin(2)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+
+        // Stand in for the interrupt line watcher so that the poll really waits.
+        pfd.pfd_state.borrow_mut().background_interrupts = Some(Arc::default());
+        pfd.enable_health_monitor(Duration::from_millis(20));
+        assert_eq!(
+            pfd.poll_health().expect("Bad poll"),
+            Some(HealthStatus::Healthy)
+        );
+
+        // A reset disables the interrupts, so the waiting poll has to wake for the next
+        // check to recover from it.
+        pfd.set_mock_data(RegisterAddress::IOCON, 0x00);
+        pfd.set_mock_data(RegisterAddress::IODIRA, 0xFF);
+        pfd.set_mock_data(RegisterAddress::GPINTENB, 0x00);
+        assert_eq!(
+            pin.poll_interrupt(false, Some(Duration::from_millis(200)))
+                .expect("Bad poll"),
+            None
+        );
+        assert_eq!(pfd.resets_detected(), 1);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPINTENB).0, 0b0000_0100);
+    }
+
+    #[test]
+    fn pfd_health_check_completes_failed_restore() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        // A restore that failed after writing the registers that the check compares
+        // but before GPPUB.
+        pfd.pfd_state.borrow().restore_pending.set(true);
+        pfd.set_mock_data(RegisterAddress::GPPUB, 0x00);
+        assert!(matches!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Recovered(_)
+        ));
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0xFF);
+        assert_eq!(pfd.resets_detected(), 0);
+        assert_eq!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Healthy
+        );
+    }
+
+    #[test]
+    fn pfd_poll_health_interval() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        assert_eq!(pfd.poll_health().expect("Bad poll"), None);
+
+        pfd.enable_health_monitor(Duration::from_secs(3600));
+        assert_eq!(
+            pfd.poll_health().expect("Bad poll"),
+            Some(HealthStatus::Healthy)
+        );
+        assert_eq!(pfd.poll_health().expect("Bad poll"), None);
+
+        pfd.disable_health_monitor();
+        assert_eq!(pfd.poll_health().expect("Bad poll"), None);
+    }
+
+    #[test]
+    fn pfd_output_pin_write_verified() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        pfd.set_retry_policy(RetryPolicy {
+            retries: 2,
+            verify_writes: true,
+            ..Default::default()
+        });
+
+        let pin = pfd.get_output_pin(2).expect("Failed to get pin");
+        pin.set_high().expect("Bad pin write");
+        assert_eq!(
+            pfd.get_mock_data(RegisterAddress::GPIOA),
+            (0b0000_0100, 2, 2)
+        );
+    }
+
+    #[test]
+    fn pfd_output_pin_write_retries_exhausted() {
+        let pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi6, // Magic value that makes mock simulate no hardware.
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.set_retry_policy(RetryPolicy {
+            retries: 2,
+            verify_writes: true,
+            ..Default::default()
+        });
+
+        let pin = pfd.get_output_pin(0).expect("Failed to get pin");
+        match pin.write(Level::High) {
+            Err(PiFaceDigitalError::WriteVerifyFailed {
+                pin: PinNumber(0),
+                level: Level::High,
+            }) => (),
+            result => panic!("Unexpected return result: {result:?}"),
+        }
+
+        // One attempt plus two retries, each a read-modify-write and a read back.
+        assert_eq!(
+            pfd.get_mock_data(RegisterAddress::GPIOA),
+            (0b0000_0001, 6, 3)
+        );
+    }
+
+    #[test]
+    fn pfd_calibrate_clock() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        let calibration = pfd
+            .calibrate_clock(100_000..=2_000_000)
+            .expect("Failed to calibrate");
+        assert_eq!(calibration.steps.len(), 20);
+        assert!(calibration.steps.iter().all(|step| step.good == 100));
+        assert_eq!(calibration.highest_reliable_clock, 2_000_000);
+        assert_eq!(calibration.selected_clock, 1_500_000);
+        assert_eq!(pfd.spi_clock(), 1_500_000);
+
+        // The device state survives re-opening the bus.
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON).0, 0x08);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::DEFVALA).0, 0x00);
+    }
+
+    #[test]
+    fn pfd_calibrate_clock_failures() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi6, // Magic value that makes mock simulate no hardware.
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+
+        match pfd.calibrate_clock(100_000..=20_000_000) {
+            Err(PiFaceDigitalError::SpiClockOutOfRange(20_000_000)) => (),
+            result => panic!("Unexpected return result: {result:?}"),
+        }
+        match pfd.calibrate_clock(100_000..=1_000_000) {
+            Err(PiFaceDigitalError::ClockCalibrationFailed { clock: 100_000 }) => (),
+            result => panic!("Unexpected return result: {result:?}"),
+        }
+        assert_eq!(pfd.spi_clock(), 100_000);
+
+        let _pin = pfd.get_output_pin(0).expect("Failed to get pin");
+        match pfd.calibrate_clock(100_000..=1_000_000) {
+            Err(PiFaceDigitalError::PinsInUse) => (),
+            result => panic!("Unexpected return result: {result:?}"),
+        }
+
+        // Callbacks keep their own connection, which can't be re-opened.
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        let mut pin = pfd
+            .get_pull_up_input_pin(0)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        pin.on_change(|_| ()).expect("Failed to register callback");
+        drop(pin);
+        match pfd.calibrate_clock(100_000..=1_000_000) {
+            Err(PiFaceDigitalError::PinsInUse) => (),
+            result => panic!("Unexpected return result: {result:?}"),
+        }
+        assert_eq!(pfd.spi_clock(), 100_000);
+    }
+
+    #[test]
+    fn pfd_registers_guarded_writes() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        let registers = pfd.registers();
+
+        for (register, data) in [
+            (RegisterAddress::IODIRA, 0x01),
+            (RegisterAddress::IODIRB, 0x7F),
+            (RegisterAddress::IOCON, 0x08 | IOCON::BANK.bits()),
+            (RegisterAddress::IOCON, 0x20),
+            (RegisterAddress::IOCON, 0x08 | IOCON::SEQOP_OFF.bits()),
+            (RegisterAddress::INTCAPB, 0x00),
+        ] {
+            match registers.write(register, data) {
+                Err(PiFaceDigitalError::RegisterWriteRefused { .. }) => (),
+                result => panic!("Unexpected result writing {register}: {result:?}"),
+            }
+        }
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IODIRA), (0x00, 0, 1));
+
+        // Open-drain interrupt output and maximum slew rate are harmless.
+        let iocon = 0x08 | (IOCON::ODR_ON | IOCON::DISSLW_SLEW_RATE_MAX).bits();
+        registers
+            .write(RegisterAddress::IOCON, iocon)
+            .expect("Bad write");
+        assert_eq!(
+            registers.read(RegisterAddress::IOCON).expect("Bad read"),
+            iocon
+        );
+
+        // The health monitor now expects the new configuration.
+        assert_eq!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Healthy
+        );
+
+        registers
+            .write_unchecked(RegisterAddress::IODIRB, 0x7F, ExpertToken::accept_risk())
+            .expect("Bad write");
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IODIRB).0, 0x7F);
+    }
+
+    #[test]
+    fn pfd_register_snapshot() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0xA5);
+
+        let before = pfd.get_mock_transactions();
+        let snapshot = pfd.registers().snapshot().expect("Bad snapshot");
+        assert_eq!(pfd.get_mock_transactions() - before, 1);
+        assert_eq!(snapshot.iter().count(), RegisterAddress::LENGTH);
+        for (register, data) in snapshot.iter() {
+            assert_eq!(data, pfd.get_mock_data(register).0, "{register}");
+        }
+        assert_eq!(snapshot.get(RegisterAddress::IOCON), 0x08);
+        assert_eq!(snapshot.get(RegisterAddress::GPIOB), 0xA5);
+
+        // Display also takes a single transaction.
+        let before = pfd.get_mock_transactions();
+        let display = pfd.to_string();
+        assert_eq!(pfd.get_mock_transactions() - before, 1);
+        assert_eq!(display, snapshot.to_string());
+        assert!(display.contains("IOCON      : 0x08"));
+    }
+
+    #[test]
+    fn pfd_input_cache() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        pfd.enable_input_cache(Duration::from_secs(60));
+
+        let pin0 = pfd
+            .get_input_pin(0)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        let pin1 = pfd.get_input_pin(1).expect("Failed to get pin");
+
+        // The first read fills the cache and the next comes from it.
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0011);
+        let before = pfd.get_mock_transactions();
+        assert_eq!(pin0.read().expect("Bad read"), Level::High);
+        assert_eq!(pfd.get_mock_transactions() - before, 1);
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0000);
+        assert_eq!(pin0.read().expect("Bad read"), Level::High);
+        assert_eq!(pfd.get_mock_transactions() - before, 1);
+
+        // Pin 1 doesn't have interrupts enabled so is always read from the device.
+        assert_eq!(pin1.read().expect("Bad read"), Level::Low);
+        assert_eq!(pfd.get_mock_transactions() - before, 2);
+        assert!(pfd.read_inputs().expect("Bad read").is_empty());
+        assert_eq!(pfd.get_mock_transactions() - before, 3);
+
+        // A pending interrupt is serviced, and queued, before reading.
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0001);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0001);
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0001);
+        assert_eq!(pin0.read().expect("Bad read"), Level::High);
+        assert_eq!(pin0.pending_interrupts(), 1);
+        let before = pfd.get_mock_transactions();
+        assert_eq!(pin0.read().expect("Bad read"), Level::High);
+        assert_eq!(pfd.get_mock_transactions() - before, 0);
+
+        // Once disabled every read goes to the device.
+        pfd.disable_input_cache();
+        pin0.read().expect("Bad read");
+        assert_eq!(pfd.get_mock_transactions() - before, 1);
+    }
+
+    #[test]
+    fn pfd_input_cache_staleness() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        pfd.enable_input_cache(Duration::from_millis(20));
+
+        let pin = pfd
+            .get_input_pin(2)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        assert_eq!(pin.read().expect("Bad read"), Level::Low);
+
+        // A change that raised no interrupt is picked up once the cache is stale.
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0100);
+        assert_eq!(pin.read().expect("Bad read"), Level::Low);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(pin.read().expect("Bad read"), Level::High);
+
+        // Changing the pin's configuration discards the cache.
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0000);
+        let pin = pin
+            .into_interrupts_disabled()
+            .expect("Bad disable")
+            .into_interrupts_enabled(InterruptMode::BothEdges)
+            .expect("Bad enable");
+        assert_eq!(pin.read().expect("Bad read"), Level::Low);
+    }
+
+    #[test]
+    fn pfd_input_cache_compare_mode() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+        pfd.enable_input_cache(Duration::from_secs(60));
+
+        let mut pin = pfd
+            .get_input_pin(3)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::ActiveHigh))
+            .expect("Failed to get pin");
+
+        // The pin goes high, raising an interrupt that fills the cache.
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_1000);
+        pfd.set_mock_data(RegisterAddress::INTCAPB, 0b0000_1000);
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_1000);
+        assert_eq!(pin.poll_interrupt(false, None).unwrap(), Some(Level::High));
+        pfd.set_mock_data(RegisterAddress::INTFB, 0b0000_0000);
+
+        // Returning to the reference level raises no interrupt, so the read has to go
+        // to the device.
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0000_0000);
+        let before = pfd.get_mock_transactions();
+        assert_eq!(pin.read().expect("Bad read"), Level::Low);
+        assert_eq!(pfd.get_mock_transactions() - before, 1);
+    }
+
+    #[test]
+    fn pfd_builder_validation() {
+        for (builder, expected) in [
+            (PiFaceDigital::builder().address(4), "Hardware address"),
+            (PiFaceDigital::builder().spi_clock(0), "SPI clock 0"),
+            (
+                PiFaceDigital::builder().spi_clock(20_000_000),
+                "SPI clock 20000000",
+            ),
+            (PiFaceDigital::builder().interrupt_gpio(28), "GPIO 28"),
+            (PiFaceDigital::builder().interrupt_gpio(10), "GPIO 10"),
+            // SPI4's second chip select is the PiFace Digital's interrupt GPIO.
+            (PiFaceDigital::builder().spi_bus(SpiBus::Spi4), "GPIO 25"),
+        ] {
+            match builder.clone().build() {
+                Err(e) => assert!(e.to_string().starts_with(expected), "{e}"),
+                Ok(_) => panic!("Built with bad settings {builder:?}"),
+            }
+        }
+        assert!(
+            PiFaceDigital::builder()
+                .spi_bus(SpiBus::Spi1)
+                .interrupt_gpio(10)
+                .spi_clock(PiFaceDigital::MAX_SPI_CLOCK)
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn pfd_builder_build() {
+        let pfd = PiFaceDigital::builder()
+            .address(HardwareAddress::new(2).unwrap())
+            .spi_clock(1_000_000)
+            .safe_state(PinSet::from_bits(0b1000_0001))
+            .build()
+            .expect("Failed to build PFD");
+        assert_eq!(pfd.spi_clock(), 1_000_000);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON).0, 0x08);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IODIRB).0, 0xFF);
+        assert_eq!(
+            pfd.read_outputs().expect("Bad read"),
+            PinSet::from_bits(0b1000_0001)
+        );
+        // The safe state is what the health monitor expects.
+        assert_eq!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Healthy
+        );
+
+        // Attaching without initialising leaves the registers alone.
+        let pfd = PiFaceDigital::builder()
+            .init_policy(InitPolicy::Skip)
+            .build()
+            .expect("Failed to build PFD");
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON), (0x00, 0, 0));
+    }
+
+    #[test]
+    fn pfd_attach_preserves_outputs() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+
+        // As left by a previous instance with interrupts on pins 0 and 1.
+        for (register, data) in [
+            (RegisterAddress::IOCON, 0x08),
+            (RegisterAddress::IODIRA, 0x00),
+            (RegisterAddress::IODIRB, 0xFF),
+            (RegisterAddress::GPPUB, 0xFF),
+            (RegisterAddress::GPINTENB, 0x03),
+            (RegisterAddress::GPIOA, 0b0000_0101),
+            (RegisterAddress::OLATA, 0b0000_0101),
+        ] {
+            pfd.set_mock_data(register, data);
+        }
+
+        let report = pfd.attach().expect("Failed to attach");
+        assert_eq!(
+            report.corrections,
+            vec![RegisterMismatch {
+                register: RegisterAddress::GPINTENB,
+                expected: 0x00,
+                actual: 0x03,
+            }]
+        );
+        assert_eq!(report.outputs, PinSet::from_bits(0b0000_0101));
+        for register in [
+            RegisterAddress::IOCON,
+            RegisterAddress::IODIRA,
+            RegisterAddress::GPIOA,
+            RegisterAddress::OLATA,
+        ] {
+            assert_eq!(pfd.get_mock_data(register).2, 0, "{register} written");
+        }
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPINTENB), (0x00, 0, 1));
+
+        // The health monitor expects the outputs as they were.
+        assert_eq!(
+            pfd.check_health().expect("Bad check"),
+            HealthStatus::Healthy
+        );
+        assert!(pfd.attach().expect("Failed to attach").was_configured());
+    }
+
+    #[test]
+    fn pfd_attach_without_escalation() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.set_retry_policy(RetryPolicy {
+            retries: 0,
+            escalation: RetryEscalation::Reset,
+            ..Default::default()
+        });
+        for (register, data) in [
+            (RegisterAddress::IOCON, 0x08),
+            (RegisterAddress::IODIRA, 0x00),
+            (RegisterAddress::IODIRB, 0xFF),
+            (RegisterAddress::GPPUB, 0xFF),
+            (RegisterAddress::GPIOA, 0b0000_0101),
+            (RegisterAddress::OLATA, 0b0000_0101),
+        ] {
+            pfd.set_mock_data(register, data);
+        }
+
+        // A failed read isn't escalated to a reset, which would turn the outputs off.
+        pfd.pfd_state.borrow().burst.fail_reads(1);
+        assert!(matches!(
+            pfd.attach(),
+            Err(PiFaceDigitalError::Mcp23s17Error { .. })
+        ));
+        for register in [RegisterAddress::GPIOA, RegisterAddress::OLATA] {
+            assert_eq!(pfd.get_mock_data(register).2, 0, "{register} written");
+        }
+
+        let report = pfd.attach().expect("Failed to attach");
+        assert_eq!(report.outputs, PinSet::from_bits(0b0000_0101));
+        assert_eq!(
+            pfd.get_mock_data(RegisterAddress::OLATA),
+            (0b0000_0101, 1, 0)
+        );
+    }
+
+    #[test]
+    fn pfd_attach_after_power_on() {
+        let pfd = PiFaceDigital::builder()
+            .init_policy(InitPolicy::WarmAttach)
+            .safe_state(PinSet::from_bits(0b0000_0001))
+            .build()
+            .expect("Failed to build PFD");
+
+        // All the power-on defaults that differ from init() get corrected but the
+        // outputs (and safe state) aren't written.
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IOCON).0, 0x08);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IODIRA).0, 0x00);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::IODIRB).0, 0xFF);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPPUB).0, 0xFF);
+        assert_eq!(pfd.get_mock_data(RegisterAddress::GPIOA), (0x00, 0, 0));
+        assert_eq!(pfd.get_mock_data(RegisterAddress::OLATA).2, 0);
+    }
+
+    #[test]
+    fn pfd_board_lock() {
+        // Somewhere that no other test uses.
+        let builder = PiFaceDigital::builder()
+            .spi_bus(SpiBus::Spi6)
+            .chip_select(ChipSelect::Cs1)
+            .address(3)
+            .init_policy(InitPolicy::Skip)
+            .exclusive(true);
+        let pfd = builder.clone().build().expect("Failed to build PFD");
+        match builder.clone().build() {
+            Err(PiFaceDigitalError::BoardInUse { pid, .. }) => {
+                assert_eq!(pid, Some(std::process::id()))
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+
+        // Deliberate sharing is still possible.
+        let shared = builder
+            .clone()
+            .exclusive(false)
+            .build()
+            .expect("Failed to build shared PFD");
+        drop(shared);
+
+        // Dropping the board releases the lock.
+        drop(pfd);
+        let pfd = builder.build().expect("Failed to build PFD");
+        drop(pfd);
+    }
+
+    #[test]
+    fn pfd_board_lock_stale() {
+        // A lock file left by a process that has gone away, with a longer process ID.
+        let path = BoardLock::path(SpiBus::Spi5, ChipSelect::Cs1, HardwareAddress(3));
+        std::fs::write(&path, u32::MAX.to_string()).expect("Failed to write lock");
+
+        // Nothing holds the lock on the file so it is simply taken.
+        let builder = PiFaceDigital::builder()
+            .spi_bus(SpiBus::Spi5)
+            .chip_select(ChipSelect::Cs1)
+            .address(3)
+            .init_policy(InitPolicy::Skip)
+            .exclusive(true);
+        let pfd = builder.clone().build().expect("Failed to build PFD");
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("No lock"),
+            std::process::id().to_string()
+        );
+
+        // The lock file stays in place for the next holder.
+        drop(pfd);
+        assert!(path.exists());
+        let pfd = builder.build().expect("Failed to build PFD");
+        drop(pfd);
+    }
+
+    #[test]
+    fn virtual_message_format() {
+        let messages = [
+            (
+                "outputs 0x81",
+                VirtualMessage::Outputs(PinSet::from_bits(0x81)),
+            ),
+            ("input 3 0", VirtualMessage::Input(PinNumber(3), Level::Low)),
+            (
+                "input 7 1",
+                VirtualMessage::Input(PinNumber(7), Level::High),
+            ),
+        ];
+        for (text, message) in messages {
+            assert_eq!(message.to_string(), text);
+            assert_eq!(
+                text.parse::<VirtualMessage>().expect("Bad message"),
+                message
+            );
+        }
+        for text in ["", "outputs 81", "input 8 0", "input 3 high", "reset"] {
+            assert!(matches!(
+                text.parse::<VirtualMessage>(),
+                Err(PiFaceDigitalError::InvalidVirtualMessage(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn pfd_virtual_board() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!(
+            "rppal-pfd-virtual-test-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).expect("Failed to listen");
+
+        let pfd = PiFaceDigital::builder()
+            .virtual_board(&path)
+            .build()
+            .expect("Failed to build PFD");
+        let (mut ui, _) = listener.accept().expect("Failed to accept");
+        ui.set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("Failed to set timeout");
+        let mut from_pfd = BufReader::new(ui.try_clone().expect("Failed to clone"));
+        let mut receive = || {
+            let mut line = String::new();
+            from_pfd.read_line(&mut line).expect("Failed to receive");
+            line.trim().parse::<VirtualMessage>().expect("Bad message")
+        };
+
+        // Output writes are sent to the user interface.
+        assert_eq!(receive(), VirtualMessage::Outputs(PinSet::EMPTY));
+        let led = pfd.get_output_pin_low(1).expect("Failed to get pin");
+        assert!(led.is_low().expect("Bad read"));
+        led.set_high().expect("Bad write");
+        assert_eq!(
+            receive(),
+            VirtualMessage::Outputs(PinSet::from(PinNumber(1)))
+        );
+        assert_eq!(
+            pfd.read_outputs().expect("Bad read"),
+            PinSet::from(PinNumber(1))
+        );
+
+        // Pressing a switch raises an interrupt.
+        let switch = pfd
+            .get_pull_up_input_pin(2)
+            .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+            .expect("Failed to get pin");
+        writeln!(ui, "{}", VirtualMessage::Input(PinNumber(2), Level::Low)).expect("Bad send");
+        let levels = pfd
+            .poll_interrupts(&[&switch], false, Some(Duration::from_secs(5)))
+            .expect("Bad poll")
+            .expect("Poll timed out");
+        assert_eq!(levels.level(PinNumber(2)), Some(Level::Low));
+        assert_eq!(switch.read().expect("Bad read"), Level::Low);
+
+        // Without further changes the wait times out.
+        assert!(
+            pfd.poll_interrupts(&[&switch], false, Some(Duration::from_millis(100)))
+                .expect("Bad poll")
+                .is_none()
+        );
+
+        drop(switch);
+        drop(led);
+        drop(pfd);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn pfd_address_to_mcp23s17_addr() {
+        let pfd_addr = HardwareAddress::new(3).expect("valid HardwareAddress");
+        let mcp_addr: rppal_mcp23s17::HardwareAddress = pfd_addr.into();
+        assert_eq!(
+            mcp_addr,
+            rppal_mcp23s17::HardwareAddress::new(3).expect("valid HardwareAddress")
+        );
+    }
+    #[test]
+    fn good_hardware_address() {
+        let addr = HardwareAddress::new(2).expect("Bad address");
+        assert_eq!(2u8, addr.into(), "Unexpected address value");
     }
 
     #[test]
@@ -1226,4 +3999,71 @@ OLATB      : 0x00
             _ => panic!("Unexpected return value: {addr:?}"),
         }
     }
+
+    #[test]
+    fn pin_number_bounds() {
+        let pin = PinNumber::new(7).expect("Bad pin");
+        assert_eq!(7u8, pin.into(), "Unexpected pin value");
+        let pin: Result<PinNumber> = 8u8.try_into();
+        match pin {
+            Err(PiFaceDigitalError::PinNumberBoundsError(8)) => (),
+            _ => panic!("Unexpected return value: {pin:?}"),
+        }
+    }
+
+    #[test]
+    fn pin_set_operations() {
+        let mut set: PinSet = [PinNumber(1), PinNumber(3)].into_iter().collect();
+        assert_eq!(set.bits(), 0b0000_1010);
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(PinNumber(3)));
+        set.insert(PinNumber(7));
+        set.remove(PinNumber(1));
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![PinNumber(3), PinNumber(7)]
+        );
+        assert_eq!(format!("{set}"), "{3, 7}");
+
+        let other = PinSet::from_bits(0b0000_1100);
+        assert_eq!(set | other, PinSet::from_bits(0b1000_1100));
+        assert_eq!(set & other, PinSet::from(PinNumber(3)));
+        assert_eq!(set - other, PinSet::from(PinNumber(7)));
+        assert_eq!(set ^ other, PinSet::from_bits(0b1000_0100));
+        assert_eq!(!PinSet::ALL, PinSet::EMPTY);
+        assert!((set & other).is_subset(set));
+    }
+
+    #[test]
+    fn pfd_pin_numbers() {
+        let mut pfd = PiFaceDigital::new(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Failed to create PFD");
+        pfd.init().expect("Failed to initialise PFD");
+
+        assert!(matches!(
+            pfd.get_input_pin(8),
+            Err(PiFaceDigitalError::PinNumberBoundsError(8))
+        ));
+        let pin = pfd
+            .get_output_pin(PinNumber::new(5).unwrap())
+            .expect("Failed to get pin");
+        assert_eq!(pin.get_pin_number(), 5);
+
+        pfd.set_mock_data(RegisterAddress::GPIOB, 0b0100_0001);
+        assert_eq!(
+            pfd.read_inputs().expect("Bad read"),
+            PinSet::from_bits(0b0100_0001)
+        );
+        pfd.set_mock_data(RegisterAddress::OLATA, 0b0010_0000);
+        assert_eq!(
+            pfd.read_outputs().expect("Bad read"),
+            PinSet::from(pin.get_pin_number())
+        );
+    }
 }
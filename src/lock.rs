@@ -0,0 +1,115 @@
+//! Advisory locking of a board against use by more than one process.
+//!
+//! Two processes driving the same PiFace Digital overwrite each other's output and
+//! interrupt configuration and each services the other's interrupts. So, by default,
+//! a [`PiFaceDigital`][crate::PiFaceDigital] takes a lock on its board, keyed by SPI
+//! bus, chip select and hardware address, before touching the hardware.
+//!
+//! The lock is an exclusive `flock` on a file in `/run/lock` (or the temporary
+//! directory if there is no `/run/lock`). The lock file is never removed, so every
+//! process locks the same file, and the kernel releases the lock when the board is
+//! dropped or the holder dies, so there are no stale locks to take over. The holder
+//! writes its process ID into the file, but only so that it can be reported to
+//! anyone else who tries to take the lock. The lock is advisory: it only protects
+//! against other users of this driver that also take it.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+use log::debug;
+
+use crate::{ChipSelect, HardwareAddress, PiFaceDigitalError, Result, SpiBus};
+
+/// Are boards locked unless asked not to be?
+///
+/// There's no hardware to protect when using the mock SPI, so boards are only locked
+/// on request (which lets the tests share boards freely).
+pub(crate) const EXCLUSIVE_BY_DEFAULT: bool = cfg!(not(any(test, feature = "mockspi")));
+
+/// A held lock on a board, released when dropped.
+#[derive(Debug)]
+pub(crate) struct BoardLock {
+    path: PathBuf,
+    /// The lock is held for as long as the file is open.
+    _file: File,
+}
+
+impl BoardLock {
+    /// Take the lock for the board, failing with [`PiFaceDigitalError::BoardInUse`] if
+    /// another process (or another instance in this process) holds it.
+    pub(crate) fn acquire(
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        hardware_address: HardwareAddress,
+    ) -> Result<Self> {
+        let path = Self::path(spi_bus, chip_select, hardware_address);
+        let lock_failed = |source| PiFaceDigitalError::BoardLockFailed { source };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(lock_failed)?;
+
+        // Each open of the file is locked separately, so this also fails if another
+        // instance in this process holds the lock.
+        // SAFETY: flock() only operates on the file descriptor, which is open.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != ErrorKind::WouldBlock {
+                return Err(lock_failed(error));
+            }
+            return Err(PiFaceDigitalError::BoardInUse {
+                spi_bus,
+                chip_select,
+                hardware_address,
+                pid: Self::holder(&mut file),
+            });
+        }
+
+        file.set_len(0)
+            .and_then(|()| file.write_all(process::id().to_string().as_bytes()))
+            .map_err(lock_failed)?;
+        debug!("Locked {}", path.display());
+        Ok(BoardLock { path, _file: file })
+    }
+
+    /// The path of the lock file for a board.
+    pub(crate) fn path(
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        hardware_address: HardwareAddress,
+    ) -> PathBuf {
+        let lock_dir = Path::new("/run/lock");
+        let lock_dir = if lock_dir.is_dir() {
+            lock_dir.to_path_buf()
+        } else {
+            std::env::temp_dir()
+        };
+        lock_dir.join(format!(
+            "rppal-pfd-{spi_bus}-{chip_select:?}-{hardware_address}.lock"
+        ))
+    }
+
+    /// The process ID written into the lock file by its holder, if it has been
+    /// written yet.
+    fn holder(file: &mut File) -> Option<u32> {
+        let mut contents = String::new();
+        file.rewind().ok()?;
+        file.read_to_string(&mut contents).ok()?;
+        contents.trim().parse().ok()
+    }
+}
+
+impl Drop for BoardLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock.
+        debug!("Unlocked {}", self.path.display());
+    }
+}
@@ -0,0 +1,274 @@
+//! Integration of interrupts with external event loops.
+//!
+//! Applications built around an event loop (`epoll`, `mio`, `calloop` and the like)
+//! can't spare a thread blocked in [`PiFaceDigital::poll_interrupts()`]. Instead,
+//! [`PiFaceDigital::interrupt_fd()`] provides a file descriptor that becomes readable
+//! whenever the PiFace Digital raises an interrupt, and the event loop calls
+//! [`PiFaceDigital::service_interrupt()`] when it does.
+//!
+//! `rppal` doesn't expose the file descriptor of the GPIO line itself, so the driver
+//! watches the line in the background, reading each interrupt as it happens, and
+//! signals interrupts over a socket pair. The interrupts read are held for
+//! [`PiFaceDigital::service_interrupt()`] and the synchronous polls alike.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    sync::Arc,
+};
+
+use log::{debug, warn};
+
+use crate::{
+    PfdInterrupt, PiFaceDigital, PiFaceDigitalError, PiFaceDigitalState, RegisterAddress, Result,
+    context::Context,
+};
+
+/// Signals interrupts over a socket pair.
+#[derive(Debug)]
+pub(crate) struct InterruptNotifier {
+    reader: UnixStream,
+    // There is no interrupt line to watch for the mock SPI so nothing signals.
+    #[cfg_attr(any(test, feature = "mockspi"), allow(dead_code))]
+    writer: UnixStream,
+}
+
+impl InterruptNotifier {
+    fn new() -> std::io::Result<Self> {
+        let (reader, writer) = UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        Ok(InterruptNotifier { reader, writer })
+    }
+
+    /// Make the file descriptor readable.
+    #[cfg_attr(any(test, feature = "mockspi"), allow(dead_code))]
+    pub(crate) fn wake(&self) {
+        // If the socket is full it is already readable, so nothing is lost.
+        match (&self.writer).write(&[0x01]) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => warn!("Failed to signal interrupt: {e}"),
+        }
+    }
+
+    /// Make the file descriptor unreadable again.
+    fn drain(&self) {
+        let mut buffer = [0u8; 64];
+        while matches!((&self.reader).read(&mut buffer), Ok(n) if n > 0) {}
+    }
+}
+
+/// A file descriptor that becomes readable when the PiFace Digital raises an interrupt,
+/// returned by [`PiFaceDigital::interrupt_fd()`].
+///
+/// The file descriptor is only for polling for readability: the interrupt is collected
+/// with [`PiFaceDigital::service_interrupt()`], which also makes the file descriptor
+/// unreadable again.
+///
+/// With the `mio` feature it is a `mio` event source and with the `calloop` feature a
+/// `calloop` one, whose event says only that the file descriptor is readable.
+#[derive(Debug)]
+pub struct InterruptFd {
+    stream: UnixStream,
+    #[cfg(feature = "calloop")]
+    source: calloop::generic::Generic<UnixStream>,
+}
+
+impl InterruptFd {
+    fn new(stream: UnixStream) -> std::io::Result<Self> {
+        Ok(InterruptFd {
+            #[cfg(feature = "calloop")]
+            source: calloop::generic::Generic::new(
+                stream.try_clone()?,
+                calloop::Interest::READ,
+                calloop::Mode::Level,
+            ),
+            stream,
+        })
+    }
+}
+
+impl AsRawFd for InterruptFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl AsFd for InterruptFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+#[cfg(feature = "mio")]
+impl mio::event::Source for InterruptFd {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
+
+#[cfg(feature = "calloop")]
+impl calloop::EventSource for InterruptFd {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+    type Error = std::io::Error;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: calloop::Readiness,
+        token: calloop::Token,
+        mut callback: F,
+    ) -> std::result::Result<calloop::PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.source.process_events(readiness, token, |_, _| {
+            callback((), &mut ());
+            Ok(calloop::PostAction::Continue)
+        })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut calloop::Poll,
+        token_factory: &mut calloop::TokenFactory,
+    ) -> calloop::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut calloop::Poll,
+        token_factory: &mut calloop::TokenFactory,
+    ) -> calloop::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut calloop::Poll) -> calloop::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
+impl PiFaceDigital {
+    /// Get a file descriptor that becomes readable whenever the PiFace Digital raises
+    /// an interrupt, for use with an external event loop.
+    ///
+    /// When the file descriptor is readable, call [`PiFaceDigital::service_interrupt()`]
+    /// until it returns `Ok(None)`.
+    ///
+    /// On the Raspberry Pi this starts watching the interrupt line in the background.
+    /// The synchronous polls keep working alongside, taking the interrupts read in the
+    /// background.
+    ///
+    /// The file descriptor can be used with any event loop. With the `mio` feature it
+    /// can be registered directly:
+    ///
+    /// ```ignore
+    /// let mut fd = pfd.interrupt_fd()?;
+    /// poll.registry()
+    ///     .register(&mut fd, PFD_TOKEN, Interest::READABLE)?;
+    /// ```
+    ///
+    /// and with the `calloop` feature it can be inserted directly:
+    ///
+    /// ```ignore
+    /// let fd = pfd.interrupt_fd()?;
+    /// handle.insert_source(fd, |(), &mut (), state| {
+    ///     while let Ok(Some(interrupt)) = state.pfd.service_interrupt() {
+    ///         // ...
+    ///     }
+    /// })?;
+    /// ```
+    ///
+    /// ## Testing
+    ///
+    /// Note that in testing environments or with the `mockspi` feature enabled, there
+    /// is no interrupt line to watch so the file descriptor never becomes readable.
+    pub fn interrupt_fd(&mut self) -> Result<InterruptFd> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        if pfd_state.interrupt_notifier.is_none() {
+            let notifier = InterruptNotifier::new()
+                .map_err(|source| PiFaceDigitalError::InterruptFdFailed { source })?;
+            pfd_state.interrupt_notifier = Some(Arc::new(notifier));
+            #[cfg(not(any(test, feature = "mockspi")))]
+            pfd_state.watch_interrupt_line()?;
+            pfd_state.clear_pending_interrupt()?;
+        }
+        pfd_state
+            .interrupt_notifier
+            .as_ref()
+            .unwrap()
+            .reader
+            .try_clone()
+            .and_then(InterruptFd::new)
+            .map_err(|source| PiFaceDigitalError::InterruptFdFailed { source })
+    }
+
+    /// Service an interrupt, returning the decoded interrupt or `None` if there isn't
+    /// one waiting.
+    ///
+    /// Intended to be called from an external event loop when the file descriptor from
+    /// [`PiFaceDigital::interrupt_fd()`] is readable; call it until it returns
+    /// `Ok(None)` as several interrupts may be waiting. The interrupt is also queued for
+    /// the pins that raised it, just as if it had been serviced by
+    /// [`PiFaceDigital::poll_interrupts()`], and passed to any callbacks.
+    pub fn service_interrupt(&self) -> Result<Option<PfdInterrupt>> {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        if let Some(notifier) = &pfd_state.interrupt_notifier {
+            notifier.drain();
+        }
+        if pfd_state.reads_interrupts_in_background() {
+            let interrupt = pfd_state.background_interrupt();
+            if let Some(interrupt) = &interrupt {
+                debug!("Collected interrupt {interrupt:?}");
+                pfd_state.interrupt_queues.push(interrupt);
+            }
+            return Ok(interrupt);
+        }
+        let flags = pfd_state
+            .retry(|mcp23s17| Ok(mcp23s17.read(RegisterAddress::INTFB)?))
+            .context(
+                pfd_state
+                    .context("checking for interrupt")
+                    .register(RegisterAddress::INTFB),
+            )?;
+        if flags == 0 {
+            return Ok(None);
+        }
+        pfd_state.service_interrupt().map(Some)
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Are the interrupt registers read in the background when interrupts happen?
+    ///
+    /// On the Raspberry Pi this is the case once callbacks or the interrupt file
+    /// descriptor are in use. If so, they mustn't also be read when collecting
+    /// interrupts or the two would compete.
+    pub(crate) fn reads_interrupts_in_background(&self) -> bool {
+        self.background_interrupts.is_some()
+    }
+}
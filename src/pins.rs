@@ -0,0 +1,393 @@
+//! Pin numbers and sets of pins.
+//!
+//! Each port of the PiFace Digital has eight pins, so a [`PinNumber`] is bounds-checked
+//! to 0-7 when it is created, in the same way as a [`HardwareAddress`][crate::HardwareAddress],
+//! rather than the error surfacing deep inside the MCP23S17 driver. Anything that
+//! concerns several pins at once (interrupt flags, captured levels, subscription filters
+//! and so on) is a [`PinSet`], which is a bitmask with bit 0 for pin 0 and so on, just
+//! like the MCP23S17's registers.
+
+use std::{
+    convert::Infallible,
+    fmt,
+    iter::FusedIterator,
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, Not, Sub},
+};
+
+use crate::{Level, PiFaceDigitalError, Result};
+
+//--------------------------------------------------------------------------------------
+/// The number of a pin on one of the PiFace Digital's ports - three bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PinNumber(pub(crate) u8);
+
+impl PinNumber {
+    /// Each port has eight pins so 0-7 are valid.
+    pub const MAX_PIN_NUMBER: u8 = 7;
+
+    /// All the pin numbers in order.
+    pub const ALL: [PinNumber; 8] = [
+        PinNumber(0),
+        PinNumber(1),
+        PinNumber(2),
+        PinNumber(3),
+        PinNumber(4),
+        PinNumber(5),
+        PinNumber(6),
+        PinNumber(7),
+    ];
+
+    /// Create a PinNumber bounds-checking that it is valid.
+    pub fn new(pin: u8) -> Result<Self> {
+        if pin <= Self::MAX_PIN_NUMBER {
+            Ok(Self(pin))
+        } else {
+            Err(PiFaceDigitalError::PinNumberBoundsError(pin))
+        }
+    }
+
+    /// The pin's bit in a register.
+    pub(crate) fn mask(self) -> u8 {
+        0x01 << self.0
+    }
+}
+
+impl TryFrom<u8> for PinNumber {
+    type Error = PiFaceDigitalError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        PinNumber::new(value)
+    }
+}
+
+impl From<PinNumber> for u8 {
+    fn from(pin: PinNumber) -> Self {
+        pin.0
+    }
+}
+
+impl PartialEq<u8> for PinNumber {
+    fn eq(&self, other: &u8) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for PinNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl From<Infallible> for PiFaceDigitalError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
+//--------------------------------------------------------------------------------------
+/// A set of pins on one port, held as a bitmask with bit 0 for pin 0 and so on.
+///
+/// ```
+/// use rppal_pfd::{PinNumber, PinSet};
+///
+/// let switches = PinSet::from_bits(0b0000_1111);
+/// let flags = PinSet::from_bits(0b0001_0010);
+///
+/// let pressed = flags & switches;
+/// assert_eq!(pressed, PinSet::from(PinNumber::new(1).unwrap()));
+/// assert_eq!(
+///     (flags - switches).iter().map(u8::from).collect::<Vec<_>>(),
+///     vec![4]
+/// );
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PinSet(u8);
+
+impl PinSet {
+    /// The set of no pins.
+    pub const EMPTY: PinSet = PinSet(0x00);
+
+    /// The set of all eight pins.
+    pub const ALL: PinSet = PinSet(0xFF);
+
+    /// Create a set from a bitmask (bit 0 for pin 0 and so on).
+    pub const fn from_bits(bits: u8) -> Self {
+        PinSet(bits)
+    }
+
+    /// Get the set as a bitmask (bit 0 for pin 0 and so on).
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Is the pin in the set?
+    pub fn contains(self, pin: PinNumber) -> bool {
+        self.0 & pin.mask() != 0
+    }
+
+    /// Add a pin to the set.
+    pub fn insert(&mut self, pin: PinNumber) {
+        self.0 |= pin.mask();
+    }
+
+    /// Remove a pin from the set.
+    pub fn remove(&mut self, pin: PinNumber) {
+        self.0 &= !pin.mask();
+    }
+
+    /// Get the number of pins in the set.
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Is the set empty?
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Get the pins in either set.
+    pub fn union(self, other: PinSet) -> PinSet {
+        PinSet(self.0 | other.0)
+    }
+
+    /// Get the pins in both sets.
+    pub fn intersection(self, other: PinSet) -> PinSet {
+        PinSet(self.0 & other.0)
+    }
+
+    /// Get the pins in this set but not in `other`.
+    pub fn difference(self, other: PinSet) -> PinSet {
+        PinSet(self.0 & !other.0)
+    }
+
+    /// Get the pins in exactly one of the sets.
+    pub fn symmetric_difference(self, other: PinSet) -> PinSet {
+        PinSet(self.0 ^ other.0)
+    }
+
+    /// Is every pin in this set also in `other`?
+    pub fn is_subset(self, other: PinSet) -> bool {
+        self.difference(other).is_empty()
+    }
+
+    /// Iterate over the pins in the set in ascending order.
+    pub fn iter(self) -> PinSetIter {
+        PinSetIter(self.0)
+    }
+}
+
+impl From<PinNumber> for PinSet {
+    fn from(pin: PinNumber) -> Self {
+        PinSet(pin.mask())
+    }
+}
+
+impl FromIterator<PinNumber> for PinSet {
+    fn from_iter<T: IntoIterator<Item = PinNumber>>(iter: T) -> Self {
+        let mut set = PinSet::EMPTY;
+        for pin in iter {
+            set.insert(pin);
+        }
+        set
+    }
+}
+
+impl Extend<PinNumber> for PinSet {
+    fn extend<T: IntoIterator<Item = PinNumber>>(&mut self, iter: T) {
+        for pin in iter {
+            self.insert(pin);
+        }
+    }
+}
+
+impl IntoIterator for PinSet {
+    type Item = PinNumber;
+    type IntoIter = PinSetIter;
+
+    fn into_iter(self) -> PinSetIter {
+        self.iter()
+    }
+}
+
+impl BitOr for PinSet {
+    type Output = PinSet;
+
+    fn bitor(self, rhs: PinSet) -> PinSet {
+        self.union(rhs)
+    }
+}
+
+impl BitOrAssign for PinSet {
+    fn bitor_assign(&mut self, rhs: PinSet) {
+        *self = self.union(rhs);
+    }
+}
+
+impl BitAnd for PinSet {
+    type Output = PinSet;
+
+    fn bitand(self, rhs: PinSet) -> PinSet {
+        self.intersection(rhs)
+    }
+}
+
+impl BitAndAssign for PinSet {
+    fn bitand_assign(&mut self, rhs: PinSet) {
+        *self = self.intersection(rhs);
+    }
+}
+
+impl BitXor for PinSet {
+    type Output = PinSet;
+
+    fn bitxor(self, rhs: PinSet) -> PinSet {
+        self.symmetric_difference(rhs)
+    }
+}
+
+impl Sub for PinSet {
+    type Output = PinSet;
+
+    fn sub(self, rhs: PinSet) -> PinSet {
+        self.difference(rhs)
+    }
+}
+
+impl Not for PinSet {
+    type Output = PinSet;
+
+    fn not(self) -> PinSet {
+        PinSet(!self.0)
+    }
+}
+
+impl fmt::Debug for PinSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter().map(u8::from)).finish()
+    }
+}
+
+impl fmt::Display for PinSet {
+    /// Formats as, for example, `{0, 3}`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl fmt::Binary for PinSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Binary::fmt(&self.0, f)
+    }
+}
+
+/// Iterator over the pins in a [`PinSet`] in ascending order.
+#[derive(Clone, Debug)]
+pub struct PinSetIter(u8);
+
+impl Iterator for PinSetIter {
+    type Item = PinNumber;
+
+    fn next(&mut self) -> Option<PinNumber> {
+        if self.0 == 0 {
+            return None;
+        }
+        let pin = self.0.trailing_zeros() as u8;
+        self.0 &= self.0 - 1;
+        Some(PinNumber(pin))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.0.count_ones() as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for PinSetIter {}
+
+impl FusedIterator for PinSetIter {}
+
+//--------------------------------------------------------------------------------------
+/// The levels of a set of pins, as returned by [`PiFaceDigital::poll_interrupts()`][crate::PiFaceDigital::poll_interrupts()].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PinLevels {
+    /// The pins that the levels are for.
+    pub pins: PinSet,
+    /// Those of the pins that are at [`Level::High`]; the rest are at [`Level::Low`].
+    pub high: PinSet,
+}
+
+impl PinLevels {
+    /// Get the level of a pin, or `None` if the pin isn't included.
+    pub fn level(&self, pin: PinNumber) -> Option<Level> {
+        self.pins
+            .contains(pin)
+            .then(|| Level::from(self.high.contains(pin)))
+    }
+
+    /// Is the pin included?
+    pub fn contains(&self, pin: PinNumber) -> bool {
+        self.pins.contains(pin)
+    }
+
+    /// Get the number of pins included.
+    pub fn len(&self) -> usize {
+        self.pins.len()
+    }
+
+    /// Are no pins included?
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Iterate over the pins included, with their levels, in ascending order of pin.
+    pub fn iter(&self) -> PinLevelsIter {
+        PinLevelsIter {
+            pins: self.pins.iter(),
+            high: self.high,
+        }
+    }
+
+    /// Record the level of a pin.
+    pub(crate) fn insert(&mut self, pin: PinNumber, level: Level) {
+        self.pins.insert(pin);
+        match level {
+            Level::High => self.high.insert(pin),
+            Level::Low => self.high.remove(pin),
+        }
+    }
+}
+
+impl IntoIterator for PinLevels {
+    type Item = (PinNumber, Level);
+    type IntoIter = PinLevelsIter;
+
+    fn into_iter(self) -> PinLevelsIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the pins in a [`PinLevels`], with their levels, in ascending order of
+/// pin.
+#[derive(Clone, Debug)]
+pub struct PinLevelsIter {
+    pins: PinSetIter,
+    high: PinSet,
+}
+
+impl Iterator for PinLevelsIter {
+    type Item = (PinNumber, Level);
+
+    fn next(&mut self) -> Option<(PinNumber, Level)> {
+        self.pins
+            .next()
+            .map(|pin| (pin, Level::from(self.high.contains(pin))))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pins.size_hint()
+    }
+}
+
+impl ExactSizeIterator for PinLevelsIter {}
+
+impl FusedIterator for PinLevelsIter {}
@@ -0,0 +1,239 @@
+//! Direct access to the MCP23S17's registers for advanced users.
+//!
+//! The driver configures the MCP23S17 to suit the PiFace Digital hardware and
+//! generally hides the registers, but there are device features (such as slew-rate
+//! control or an open-drain interrupt output) that the driver has no API for. The
+//! [`Registers`] accessor allows those to be used, while refusing writes that would
+//! be incompatible with the way the PiFace Digital is wired unless the caller
+//! explicitly takes responsibility by providing an [`ExpertToken`].
+
+use std::{fmt, time::Instant};
+
+use log::{info, warn};
+
+use crate::{
+    ExpectedConfig, IOCON, PiFaceDigital, PiFaceDigitalError, PiFaceDigitalState, PinSet,
+    RegisterAddress, Result, context::Context,
+};
+
+/// Token that unlocks register writes that the [`Registers`] accessor would otherwise
+/// refuse.
+///
+/// Writes made with the token bypass all the guardrails so can, for example, turn the
+/// outputs that drive the relays into inputs or change the register addressing so
+/// that the rest of the driver no longer works. Use with care!
+#[derive(Debug)]
+pub struct ExpertToken(());
+
+impl ExpertToken {
+    /// Create a token, accepting the risk of making writes that are incompatible with
+    /// the PiFace Digital hardware or the rest of the driver.
+    pub fn accept_risk() -> Self {
+        ExpertToken(())
+    }
+}
+
+/// Accessor for the MCP23S17's registers returned by [`PiFaceDigital::registers()`].
+///
+/// ```no_run
+/// use rppal_pfd::{ChipSelect, HardwareAddress, IOCON, PiFaceDigital, RegisterAddress, SpiBus, SpiMode};
+///
+/// let mut pfd = PiFaceDigital::new(
+///     HardwareAddress::new(0).unwrap(),
+///     SpiBus::Spi0,
+///     ChipSelect::Cs0,
+///     100_000,
+///     SpiMode::Mode0,
+/// ).expect("Failed to construct!");
+/// pfd.init().expect("Failed to initialise!");
+///
+/// // Make the interrupt output open-drain.
+/// let registers = pfd.registers();
+/// let iocon = registers.read(RegisterAddress::IOCON).expect("Bad read");
+/// registers
+///     .write(RegisterAddress::IOCON, iocon | IOCON::ODR_ON.bits())
+///     .expect("Bad write");
+/// ```
+#[derive(Debug)]
+pub struct Registers<'a> {
+    pfd: &'a PiFaceDigital,
+}
+
+impl PiFaceDigital {
+    /// Get an accessor for reading and writing the MCP23S17's registers directly.
+    pub fn registers(&self) -> Registers<'_> {
+        Registers { pfd: self }
+    }
+}
+
+/// The contents of all the MCP23S17's registers, read in one SPI transaction by
+/// [`Registers::snapshot()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterSnapshot {
+    data: [u8; RegisterAddress::LENGTH],
+}
+
+impl RegisterSnapshot {
+    /// Get the contents of a register.
+    pub fn get(&self, register: RegisterAddress) -> u8 {
+        self.data[register as usize]
+    }
+
+    /// Iterate over the registers, and their contents, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (RegisterAddress, u8)> + '_ {
+        self.data.iter().enumerate().map(|(register, &data)| {
+            // The array has an entry for each register so the address is always valid.
+            (RegisterAddress::try_from(register).unwrap(), data)
+        })
+    }
+}
+
+impl fmt::Display for RegisterSnapshot {
+    /// Formats one register per line, e.g. `"IOCON      : 0x08"`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (register, data) in self.iter() {
+            writeln!(f, "{register:10} : 0x{data:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Registers<'_> {
+    /// Read all the registers in one SPI transaction (a burst read using the
+    /// MCP23S17's sequential mode).
+    ///
+    /// Note that reading `INTCAPB` clears any pending interrupt without it being
+    /// queued for the pins, so avoid taking snapshots while relying on interrupts.
+    pub fn snapshot(&self) -> Result<RegisterSnapshot> {
+        self.pfd.pfd_state.borrow().snapshot()
+    }
+
+    /// Read a register.
+    pub fn read(&self, register: RegisterAddress) -> Result<u8> {
+        let mut pfd_state = self.pfd.pfd_state.borrow_mut();
+        let context = pfd_state.context("reading register").register(register);
+        pfd_state
+            .retry(|mcp23s17| Ok(mcp23s17.read(register)?))
+            .context(context)
+    }
+
+    /// Write a register, refusing writes that are incompatible with the PiFace Digital.
+    ///
+    /// A [`PiFaceDigitalError::RegisterWriteRefused`] is returned for writes that
+    /// would:
+    ///
+    /// - Make any of the `GPIOA` pins an input (they drive the relays and LEDs).
+    /// - Make any of the `GPIOB` pins an output (the push-switches would short them to
+    ///   ground).
+    /// - Change `IOCON.BANK` (the driver relies on the interleaved register addresses).
+    /// - Turn off `IOCON.HAEN` (hardware addressing distinguishes the PiFace Digitals
+    ///   sharing the SPI bus).
+    /// - Make the interrupt output active-high with `IOCON.INTPOL` (the Raspberry Pi
+    ///   detects interrupts on the falling edge).
+    /// - Disable sequential operation with `IOCON.SEQOP` (the driver reads consecutive
+    ///   registers in bursts).
+    /// - Write the read-only interrupt flag and capture registers.
+    ///
+    /// Successful writes to configuration registers are noted by the health monitor so
+    /// that they are preserved if the MCP23S17 has to be recovered from a reset.
+    pub fn write(&self, register: RegisterAddress, data: u8) -> Result<()> {
+        if let Some(reason) = refusal_reason(register, data) {
+            warn!("Refused write of 0x{data:02x} to {register}: {reason}");
+            return Err(PiFaceDigitalError::RegisterWriteRefused {
+                register,
+                data,
+                reason,
+            });
+        }
+        self.write_unchecked(register, data, ExpertToken::accept_risk())
+    }
+
+    /// Write a register without any of the checks made by [`Registers::write()`].
+    pub fn write_unchecked(
+        &self,
+        register: RegisterAddress,
+        data: u8,
+        _token: ExpertToken,
+    ) -> Result<()> {
+        info!("Direct write of 0x{data:02x} to {register}");
+        let mut pfd_state = self.pfd.pfd_state.borrow_mut();
+        let context = pfd_state.context("writing register").register(register);
+        pfd_state
+            .retry(|mcp23s17| Ok(mcp23s17.write(register, data)?))
+            .context(context)?;
+        pfd_state.track_register_write(register, data);
+        Ok(())
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Read all the registers in one burst.
+    ///
+    /// Retries but doesn't escalate so that it's safe to use for diagnostics, such as
+    /// [`PiFaceDigital`]'s `Display`.
+    pub(crate) fn snapshot(&self) -> Result<RegisterSnapshot> {
+        let mut data = [0u8; RegisterAddress::LENGTH];
+        let read_at = Instant::now();
+        self.attempt(|mcp23s17| {
+            self.burst
+                .read(mcp23s17, RegisterAddress::IODIRA, &mut data)
+        })
+        .context(self.context("reading register snapshot"))?;
+        let snapshot = RegisterSnapshot { data };
+        self.input_cache.update(
+            PinSet::from_bits(snapshot.get(RegisterAddress::GPIOB)),
+            read_at,
+        );
+        Ok(snapshot)
+    }
+
+    /// Keep the health monitor's expected configuration in step with a direct write.
+    ///
+    /// A direct write may change how the inputs read, so the input cache is discarded.
+    fn track_register_write(&mut self, register: RegisterAddress, data: u8) {
+        self.input_cache.invalidate();
+        let expected = &mut self.expected;
+        match register {
+            RegisterAddress::IOCON | RegisterAddress::IOCON2 => expected.iocon = data,
+            RegisterAddress::IODIRB => expected.iodirb = data,
+            RegisterAddress::IPOLB => expected.ipolb = data,
+            RegisterAddress::GPINTENB => expected.gpintenb = data,
+            RegisterAddress::DEFVALB => expected.defvalb = data,
+            RegisterAddress::INTCONB => expected.intconb = data,
+            RegisterAddress::GPPUB => expected.gppub = data,
+            RegisterAddress::GPIOA | RegisterAddress::OLATA => expected.olata = data,
+            _ => (),
+        }
+    }
+}
+
+/// Why a write of `data` to `register` would break the PiFace Digital, if it would.
+fn refusal_reason(register: RegisterAddress, data: u8) -> Option<&'static str> {
+    match register {
+        RegisterAddress::IODIRA if data != ExpectedConfig::IODIRA => {
+            Some("GPIOA drives the relays and LEDs so must remain outputs")
+        }
+        RegisterAddress::IODIRB if data != 0xFF => {
+            Some("GPIOB is wired to the switches so must remain inputs")
+        }
+        RegisterAddress::IOCON | RegisterAddress::IOCON2 => {
+            let iocon = IOCON::from_bits_truncate(data);
+            if iocon.contains(IOCON::BANK) {
+                Some("IOCON.BANK must remain off for the driver's register addressing")
+            } else if !iocon.contains(IOCON::HAEN) {
+                Some("IOCON.HAEN must remain on to address boards sharing the bus")
+            } else if iocon.contains(IOCON::INTPOL) {
+                Some("IOCON.INTPOL must remain active-low for the interrupt input")
+            } else if iocon.contains(IOCON::SEQOP) {
+                Some("IOCON.SEQOP must remain off for the driver's burst reads")
+            } else {
+                None
+            }
+        }
+        RegisterAddress::INTFA
+        | RegisterAddress::INTFB
+        | RegisterAddress::INTCAPA
+        | RegisterAddress::INTCAPB => Some("register is read-only"),
+        _ => None,
+    }
+}
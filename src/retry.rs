@@ -0,0 +1,169 @@
+//! Retry and recovery policy for failed accesses to the MCP23S17.
+//!
+//! The SPI bus on a Raspberry Pi is generally reliable but long cables and electrically
+//! noisy environments can cause the occasional transfer to fail or be corrupted. Rather
+//! than every application having to wrap every call in its own retry loop, each
+//! [`PiFaceDigital`] carries a [`RetryPolicy`] that is applied to pin reads and writes,
+//! the register reads made when handling interrupts and [`PiFaceDigital::init()`].
+
+use std::{thread, time::Duration};
+
+use log::{error, warn};
+
+use crate::{Mcp23s17, PiFaceDigital, PiFaceDigitalError, PiFaceDigitalState, Result};
+
+/// What to do when an operation has failed and its retries have been exhausted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RetryEscalation {
+    /// Give up and return the error.
+    #[default]
+    None,
+    /// Make a health check (see [`PiFaceDigital::check_health()`]), which restores the
+    /// configuration if the MCP23S17 has been reset, and then make one final attempt.
+    HealthCheck,
+    /// Unconditionally re-write the expected configuration into the MCP23S17 and then
+    /// make one final attempt.
+    Reset,
+}
+
+/// Policy for retrying failed accesses to the MCP23S17.
+///
+/// Only errors that might reasonably be transient (SPI transfer failures and failed
+/// write verification) are retried. Errors such as asking for a pin that is already
+/// in use are returned immediately.
+///
+/// The default policy makes no retries and so matches the behaviour of a driver
+/// without a retry policy.
+///
+/// ```no_run
+/// use rppal_pfd::{ChipSelect, HardwareAddress, PiFaceDigital, RetryEscalation, RetryPolicy, SpiBus, SpiMode};
+/// use std::time::Duration;
+///
+/// let mut pfd = PiFaceDigital::new(
+///     HardwareAddress::new(0).unwrap(),
+///     SpiBus::Spi0,
+///     ChipSelect::Cs0,
+///     100_000,
+///     SpiMode::Mode0,
+/// ).expect("Failed to construct!");
+///
+/// pfd.set_retry_policy(RetryPolicy {
+///     retries: 3,
+///     backoff: Duration::from_millis(1),
+///     verify_writes: true,
+///     escalation: RetryEscalation::HealthCheck,
+/// });
+/// pfd.init().expect("Failed to initialise!");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of further attempts to make after the first attempt fails.
+    pub retries: u32,
+    /// Delay before the first retry. The delay doubles for each subsequent retry.
+    pub backoff: Duration,
+    /// Read back every pin write to check that the output took the requested level.
+    pub verify_writes: bool,
+    /// Action to take once all retries have failed.
+    pub escalation: RetryEscalation,
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry` (counting from zero).
+    fn backoff_delay(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(1 << retry.min(16))
+    }
+
+    /// Run `operation` retrying according to the policy but without any escalation.
+    pub(crate) fn attempt<T>(&self, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut retry = 0;
+        loop {
+            match operation() {
+                Err(e) if e.is_transient() && retry < self.retries => {
+                    let delay = self.backoff_delay(retry);
+                    retry += 1;
+                    warn!(
+                        "MCP23S17 access failed ({e}) - retry {retry}/{} in {delay:?}",
+                        self.retries
+                    );
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl PiFaceDigitalError {
+    /// Could retrying the operation that raised this error reasonably succeed?
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            PiFaceDigitalError::Mcp23s17Error { source, .. } => matches!(
+                source,
+                rppal_mcp23s17::Mcp23s17Error::SpiError { .. }
+                    | rppal_mcp23s17::Mcp23s17Error::UnexpectedReadLength(_)
+            ),
+            PiFaceDigitalError::WriteVerifyFailed { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl PiFaceDigital {
+    /// Set the policy for retrying failed accesses to the MCP23S17.
+    ///
+    /// The interrupt registers read in the background follow the same policy, though
+    /// without any escalation.
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        pfd_state.retry_policy = policy;
+        #[cfg(not(any(test, feature = "mockspi")))]
+        if let Some(reader) = &pfd_state.interrupt_reader {
+            if let Ok(mut reader) = reader.lock() {
+                reader.retry_policy = policy;
+            }
+        }
+    }
+
+    /// Get the policy for retrying failed accesses to the MCP23S17.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.pfd_state.borrow().retry_policy
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Run `operation` retrying according to the retry policy but without any
+    /// escalation.
+    ///
+    /// The operation is given access to the MCP23S17 for the cases where it needs to
+    /// make register accesses.
+    pub(crate) fn attempt<T>(
+        &self,
+        mut operation: impl FnMut(&Mcp23s17) -> Result<T>,
+    ) -> Result<T> {
+        self.retry_policy.attempt(|| operation(&self.mcp23s17))
+    }
+
+    /// Run `operation` retrying and then escalating according to the retry policy.
+    pub(crate) fn retry<T>(
+        &mut self,
+        mut operation: impl FnMut(&Mcp23s17) -> Result<T>,
+    ) -> Result<T> {
+        match self.attempt(&mut operation) {
+            Err(e) if e.is_transient() && self.retry_policy.escalation != RetryEscalation::None => {
+                error!(
+                    "MCP23S17 access failed ({e}) - escalating with {:?}",
+                    self.retry_policy.escalation
+                );
+                match self.retry_policy.escalation {
+                    RetryEscalation::None => (),
+                    RetryEscalation::HealthCheck => {
+                        self.check_health()?;
+                    }
+                    RetryEscalation::Reset => self.restore_config()?,
+                }
+                operation(&self.mcp23s17)
+            }
+            result => result,
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Broadcast subscriptions to input events.
+//!
+//! Any number of independent consumers (a logger, a user interface and control logic,
+//! for example) can each [`PiFaceDigital::subscribe()`] for the input events that
+//! interest them. Every [`Subscription`] has its own bounded queue, filled by the
+//! dispatcher thread that runs the callbacks, and its own policy for what to do when
+//! the consumer falls behind and the queue fills up.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        Arc, Condvar, Mutex,
+        mpsc::{RecvError, RecvTimeoutError, TryRecvError},
+    },
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::{Edge, PiFaceDigital, PinChange, PinSet, Result};
+
+/// What to do with a new event when a subscriber's queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Discard the oldest queued event to make room for the new one.
+    #[default]
+    DropOldest,
+    /// Discard the new event.
+    DropNewest,
+    /// Wait for the subscriber to make room.
+    ///
+    /// The dispatcher thread waits, so every callback and every other subscriber is
+    /// held up until this subscriber catches up.
+    Block,
+}
+
+/// Which events a subscriber receives and how they are queued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubscriptionOptions {
+    /// The pins to receive events for.
+    pub pins: PinSet,
+    /// Only receive changes in this direction, or changes in both if `None`.
+    pub edge: Option<Edge>,
+    /// Maximum number of events queued for the subscriber.
+    pub capacity: usize,
+    /// What to do with a new event when the queue is full.
+    pub backpressure: Backpressure,
+}
+
+impl Default for SubscriptionOptions {
+    /// Receive both edges on all pins, queueing up to 64 events and dropping the
+    /// oldest when full.
+    fn default() -> Self {
+        SubscriptionOptions {
+            pins: PinSet::ALL,
+            edge: None,
+            capacity: 64,
+            backpressure: Backpressure::DropOldest,
+        }
+    }
+}
+
+/// The queue shared between a subscriber and the dispatcher thread.
+#[derive(Debug)]
+pub(crate) struct SubscriberQueue {
+    options: SubscriptionOptions,
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    events: VecDeque<PinChange>,
+    overflows: usize,
+    /// Set when either end goes away.
+    closed: bool,
+}
+
+impl SubscriberQueue {
+    /// Queue the change if it passes the subscriber's filter, applying the
+    /// backpressure policy if the queue is full.
+    pub(crate) fn offer(&self, change: PinChange) {
+        let options = &self.options;
+        if !options.pins.contains(change.pin)
+            || options.edge.is_some_and(|edge| edge != change.edge)
+        {
+            return;
+        }
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let mut waited = false;
+        while !state.closed && state.events.len() >= options.capacity.max(1) {
+            match options.backpressure {
+                Backpressure::DropOldest => {
+                    state.events.pop_front();
+                    state.overflows += 1;
+                }
+                Backpressure::DropNewest => {
+                    state.overflows += 1;
+                    return;
+                }
+                Backpressure::Block => {
+                    if !waited {
+                        debug!("Subscriber queue full - waiting");
+                        state.overflows += 1;
+                        waited = true;
+                    }
+                    match self.changed.wait(state) {
+                        Ok(guard) => state = guard,
+                        Err(_) => return,
+                    }
+                }
+            }
+        }
+        if !state.closed {
+            state.events.push_back(change);
+            self.changed.notify_all();
+        }
+    }
+
+    /// Has the subscriber gone away?
+    pub(crate) fn is_closed(&self) -> bool {
+        self.state.lock().map(|state| state.closed).unwrap_or(true)
+    }
+
+    /// Mark the queue as closed and wake anyone waiting on it.
+    pub(crate) fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+        }
+        self.changed.notify_all();
+    }
+}
+
+/// A subscription to input events returned by [`PiFaceDigital::subscribe()`].
+///
+/// Dropping the subscription unsubscribes.
+pub struct Subscription {
+    queue: Arc<SubscriberQueue>,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("options", &self.queue.options)
+            .field("overflows", &self.overflows())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Subscription {
+    /// Wait for the next event.
+    ///
+    /// Returns an error once the [`PiFaceDigital`] has been dropped and all the queued
+    /// events have been received.
+    pub fn recv(&self) -> std::result::Result<PinChange, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Wait up to `timeout` for the next event.
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> std::result::Result<PinChange, RecvTimeoutError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    /// Take the next event if there is one queued.
+    pub fn try_recv(&self) -> std::result::Result<PinChange, TryRecvError> {
+        let mut state = self
+            .queue
+            .state
+            .lock()
+            .map_err(|_| TryRecvError::Disconnected)?;
+        match state.events.pop_front() {
+            Some(change) => {
+                self.queue.changed.notify_all();
+                Ok(change)
+            }
+            None if state.closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Get the number of events discarded, or that the dispatcher had to wait to
+    /// queue, because the queue was full.
+    pub fn overflows(&self) -> usize {
+        self.queue
+            .state
+            .lock()
+            .map(|state| state.overflows)
+            .unwrap_or(0)
+    }
+
+    /// Get the number of events queued.
+    pub fn len(&self) -> usize {
+        self.queue
+            .state
+            .lock()
+            .map(|state| state.events.len())
+            .unwrap_or(0)
+    }
+
+    /// Are there no events queued?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the options the subscription was made with.
+    pub fn options(&self) -> SubscriptionOptions {
+        self.queue.options
+    }
+
+    fn recv_until(
+        &self,
+        deadline: Option<Instant>,
+    ) -> std::result::Result<PinChange, RecvTimeoutError> {
+        let mut state = self
+            .queue
+            .state
+            .lock()
+            .map_err(|_| RecvTimeoutError::Disconnected)?;
+        loop {
+            if let Some(change) = state.events.pop_front() {
+                self.queue.changed.notify_all();
+                return Ok(change);
+            }
+            if state.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            state = match deadline {
+                None => self
+                    .queue
+                    .changed
+                    .wait(state)
+                    .map_err(|_| RecvTimeoutError::Disconnected)?,
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    self.queue
+                        .changed
+                        .wait_timeout(state, timeout)
+                        .map_err(|_| RecvTimeoutError::Disconnected)?
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+impl PiFaceDigital {
+    /// Subscribe to input events.
+    ///
+    /// Each subscriber gets its own bounded queue of the [`PinChange`]s that pass its
+    /// filter, filled by the same dispatcher thread that runs the callbacks (see
+    /// [`InputPin::on_change()`][crate::InputPin::on_change()]). Interrupts must be
+    /// enabled on the pins of interest for there to be any events.
+    ///
+    /// ```no_run
+    /// use rppal_pfd::{
+    ///     Backpressure, ChipSelect, Edge, HardwareAddress, InterruptMode, PiFaceDigital,
+    ///     PinSet, SpiBus, SpiMode, SubscriptionOptions,
+    /// };
+    ///
+    /// let mut pfd = PiFaceDigital::new(
+    ///     HardwareAddress::new(0).unwrap(),
+    ///     SpiBus::Spi0,
+    ///     ChipSelect::Cs0,
+    ///     100_000,
+    ///     SpiMode::Mode0,
+    /// ).expect("Failed to construct!");
+    /// pfd.init().expect("Failed to initialise!");
+    ///
+    /// let _button = pfd
+    ///     .get_pull_up_input_pin(0)
+    ///     .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+    ///     .expect("Bad pin");
+    ///
+    /// // Log everything but only act on button 0 rising.
+    /// let logger = pfd.subscribe(SubscriptionOptions::default()).expect("Bad subscribe");
+    /// let control = pfd
+    ///     .subscribe(SubscriptionOptions {
+    ///         pins: PinSet::from_bits(0b0000_0001),
+    ///         edge: Some(Edge::Rising),
+    ///         capacity: 4,
+    ///         backpressure: Backpressure::Block,
+    ///     })
+    ///     .expect("Bad subscribe");
+    ///
+    /// std::thread::spawn(move || {
+    ///     while let Ok(change) = logger.recv() {
+    ///         println!("{change:?} ({} dropped)", logger.overflows());
+    ///     }
+    /// });
+    /// while let Ok(change) = control.recv() {
+    ///     println!("Button {} pressed", change.pin);
+    /// }
+    /// ```
+    pub fn subscribe(&mut self, options: SubscriptionOptions) -> Result<Subscription> {
+        if options.capacity == 0 {
+            warn!("Subscription with zero capacity will queue one event");
+        }
+        let queue = Arc::new(SubscriberQueue {
+            options,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        let mut pfd_state = self.pfd_state.borrow_mut();
+        let subscriber = Arc::clone(&queue);
+        pfd_state
+            .callback_dispatcher()?
+            .with_callbacks(|callbacks| callbacks.subscribers.push(subscriber));
+        pfd_state.clear_pending_interrupt()?;
+        Ok(Subscription { queue })
+    }
+}
@@ -0,0 +1,121 @@
+//! The configurations of an [`InputPin`] as types.
+//!
+//! An [`InputPin`] carries whether its pull-up is enabled and whether it raises
+//! interrupts in its type, so that mistakes such as polling a pin that can never
+//! interrupt are caught by the compiler rather than at run time. The configuration is
+//! changed by converting the pin, which consumes it and returns the pin with its new
+//! type:
+//!
+//! ```no_run
+//! use rppal_pfd::{
+//!     ChipSelect, HardwareAddress, InputPin, InterruptMode, InterruptsEnabled,
+//!     PiFaceDigital, PullUp, SpiBus, SpiMode,
+//! };
+//!
+//! let mut pfd = PiFaceDigital::new(
+//!     HardwareAddress::new(0).unwrap(),
+//!     SpiBus::Spi0,
+//!     ChipSelect::Cs0,
+//!     100_000,
+//!     SpiMode::Mode0,
+//! ).expect("Failed to construct!");
+//! pfd.init().expect("Failed to initialise!");
+//!
+//! let mut pin: InputPin<PullUp, InterruptsEnabled> = pfd
+//!     .get_input_pin(4)
+//!     .and_then(|pin| pin.into_pull_up())
+//!     .and_then(|pin| pin.into_interrupts_enabled(InterruptMode::BothEdges))
+//!     .expect("Failed to configure pin");
+//! pin.poll_interrupt(false, None).expect("Failed to poll");
+//! ```
+//!
+//! The types are markers only: they are never constructed and have no run time cost.
+
+use std::marker::PhantomData;
+
+use crate::{ExpectedConfig, InputPin, RegisterAddress, Result, context::Context};
+
+/// Marker for an [`InputPin`] with its pull-up disabled (a high-impedance input).
+#[derive(Debug)]
+pub enum Floating {}
+
+/// Marker for an [`InputPin`] with its pull-up enabled.
+#[derive(Debug)]
+pub enum PullUp {}
+
+/// Marker for an [`InputPin`] that doesn't raise interrupts.
+#[derive(Debug)]
+pub enum InterruptsDisabled {}
+
+/// Marker for an [`InputPin`] that raises interrupts.
+#[derive(Debug)]
+pub enum InterruptsEnabled {}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for super::InterruptsDisabled {}
+    impl Sealed for super::InterruptsEnabled {}
+}
+
+/// Whether an [`InputPin`] raises interrupts.
+///
+/// This trait is sealed: it is only implemented by [`InterruptsDisabled`] and
+/// [`InterruptsEnabled`].
+pub trait InterruptState: sealed::Sealed {
+    /// Are interrupts enabled?
+    const ENABLED: bool;
+}
+
+impl InterruptState for InterruptsDisabled {
+    const ENABLED: bool = false;
+}
+
+impl InterruptState for InterruptsEnabled {
+    const ENABLED: bool = true;
+}
+
+impl<I> InputPin<Floating, I> {
+    /// Enable the pin's pull-up resistor.
+    pub fn into_pull_up(self) -> Result<InputPin<PullUp, I>> {
+        self.set_pull_up(true)?;
+        Ok(self.retype())
+    }
+}
+
+impl<I> InputPin<PullUp, I> {
+    /// Disable the pin's pull-up resistor, making it a high-impedance input.
+    pub fn into_floating(self) -> Result<InputPin<Floating, I>> {
+        self.set_pull_up(false)?;
+        Ok(self.retype())
+    }
+}
+
+impl<P, I> InputPin<P, I> {
+    /// Change the pin's type without changing the pin.
+    pub(crate) fn retype<Q, J>(self) -> InputPin<Q, J> {
+        InputPin {
+            inner: self.inner,
+            _state: PhantomData,
+        }
+    }
+
+    fn set_pull_up(&self, pull_up: bool) -> Result<()> {
+        let pin_no = self.inner.pin_number();
+        let mut pfd_state = self.inner.pfd_state.borrow_mut();
+        let context = pfd_state
+            .context("setting pull-up")
+            .pin(pin_no)
+            .register(RegisterAddress::GPPUB);
+        pfd_state
+            .retry(|mcp23s17| {
+                if pull_up {
+                    Ok(mcp23s17.set_bit(RegisterAddress::GPPUB, pin_no.into())?)
+                } else {
+                    Ok(mcp23s17.clear_bit(RegisterAddress::GPPUB, pin_no.into())?)
+                }
+            })
+            .context(context)?;
+        ExpectedConfig::update_bit(&mut pfd_state.expected.gppub, pin_no, pull_up);
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+//! A virtual PiFace Digital for development without a Raspberry Pi.
+//!
+//! When built with the `mockspi` feature, the driver talks to mock MCP23S17 registers
+//! rather than real hardware. A virtual board connects those registers to a user
+//! interface, such as the `pfd-virtual` terminal UI, over a local socket: output
+//! writes are sent to the UI to be displayed and the UI sends changes to the inputs,
+//! which raise interrupts just as the MCP23S17 would. So an application can be run on
+//! any Linux machine by building it with the `mockspi` feature and connecting to the
+//! UI's socket, either with [`PiFaceDigital::connect_virtual_board()`] or through
+//! [`PiFaceDigitalBuilder::virtual_board()`].
+//!
+//! [`PiFaceDigital::connect_virtual_board()`]: crate::PiFaceDigital::connect_virtual_board
+//! [`PiFaceDigitalBuilder::virtual_board()`]: crate::PiFaceDigitalBuilder::virtual_board
+//!
+//! The protocol is one [`VirtualMessage`] per line of text in each direction.
+//!
+//! There is no interrupt line under `mockspi`, so nothing watches for the interrupts
+//! in the background: the application has to poll for them with
+//! [`PiFaceDigital::poll_interrupts()`] or [`InputPin::poll_interrupt()`]. Callbacks,
+//! such as those from [`PiFaceDigital::subscribe_async_interrupts()`], are only run
+//! for the interrupts those polls service and the file descriptor from
+//! [`PiFaceDigital::interrupt_fd()`] never becomes readable.
+//!
+//! [`InputPin::poll_interrupt()`]: crate::InputPin::poll_interrupt
+//!
+//! Note that the mock SPI reports every register access on standard output.
+
+use std::{fmt, str::FromStr};
+
+use crate::{Level, PiFaceDigital, PiFaceDigitalError, PinNumber, PinSet};
+#[cfg(any(test, feature = "mockspi"))]
+pub(crate) use bridge::VirtualBoard;
+
+/// A message between a virtual board and its user interface.
+///
+/// ```
+/// use rppal_pfd::{Level, PinNumber, PinSet, VirtualMessage};
+///
+/// let message: VirtualMessage = "input 3 0".parse().expect("Bad message");
+/// assert_eq!(message, VirtualMessage::Input(PinNumber::new(3).unwrap(), Level::Low));
+/// assert_eq!(
+///     VirtualMessage::Outputs(PinSet::from_bits(0x81)).to_string(),
+///     "outputs 0x81"
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VirtualMessage {
+    /// The outputs that are high, sent to the user interface whenever they change.
+    Outputs(PinSet),
+    /// The physical level on an input, sent by the user interface whenever it changes.
+    Input(PinNumber, Level),
+}
+
+impl fmt::Display for VirtualMessage {
+    /// Formats as, for example, `outputs 0x05` or `input 3 0`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VirtualMessage::Outputs(outputs) => write!(f, "outputs 0x{:02x}", outputs.bits()),
+            VirtualMessage::Input(pin, level) => {
+                write!(f, "input {pin} {}", u8::from(*level == Level::High))
+            }
+        }
+    }
+}
+
+impl FromStr for VirtualMessage {
+    type Err = PiFaceDigitalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PiFaceDigitalError::InvalidVirtualMessage(s.to_string());
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words[..] {
+            ["outputs", outputs] => outputs
+                .strip_prefix("0x")
+                .and_then(|outputs| u8::from_str_radix(outputs, 16).ok())
+                .map(|outputs| VirtualMessage::Outputs(PinSet::from_bits(outputs)))
+                .ok_or_else(invalid),
+            ["input", pin, level] => {
+                let pin = pin
+                    .parse::<u8>()
+                    .ok()
+                    .and_then(|pin| PinNumber::new(pin).ok())
+                    .ok_or_else(invalid)?;
+                let level = match level {
+                    "0" => Level::Low,
+                    "1" => Level::High,
+                    _ => return Err(invalid()),
+                };
+                Ok(VirtualMessage::Input(pin, level))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl PiFaceDigital {
+    /// The environment variable for the path of a virtual board's socket.
+    ///
+    /// `pfd-virtual` listens on the socket it names. The driver doesn't read it
+    /// itself, so an application opts in by passing it to
+    /// [`PiFaceDigitalBuilder::virtual_board()`][crate::PiFaceDigitalBuilder::virtual_board].
+    pub const VIRTUAL_BOARD_VAR: &str = "RPPAL_PFD_VIRTUAL";
+
+    /// The socket that the `pfd-virtual` user interface listens on by default.
+    pub const VIRTUAL_BOARD_SOCKET: &str = "/tmp/rppal-pfd-virtual.sock";
+}
+
+#[cfg(any(test, feature = "mockspi"))]
+mod bridge {
+    use std::{
+        cell::RefCell,
+        collections::VecDeque,
+        io::{ErrorKind, Read, Write},
+        os::unix::net::UnixStream,
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    use log::{debug, info, warn};
+
+    use super::VirtualMessage;
+    use crate::{
+        CancelToken, Level, Mcp23s17, PiFaceDigital, PiFaceDigitalError, PiFaceDigitalState,
+        PinNumber, PinSet, RegisterAddress, Result,
+    };
+
+    /// The connection between the mock registers and a virtual board's user interface.
+    ///
+    /// The mock registers can only be reached from the thread that owns the
+    /// [`PiFaceDigital`], so the connection is serviced ("pumped") whenever an output
+    /// pin is written, before the inputs are read and while the driver waits for
+    /// interrupts.
+    #[derive(Debug)]
+    pub(crate) struct VirtualBoard {
+        stream: UnixStream,
+        state: RefCell<BridgeState>,
+    }
+
+    #[derive(Debug)]
+    struct BridgeState {
+        connected: bool,
+        /// Bytes received that don't yet make a complete line.
+        received: Vec<u8>,
+        /// Input changes waiting for the previous interrupt to be cleared.
+        events: VecDeque<(PinNumber, Level)>,
+        /// The physical levels on the inputs: the pins that are high.
+        inputs: PinSet,
+        /// The output registers as last seen.
+        gpioa: u8,
+        olata: u8,
+        /// Reads of the registers that clear an interrupt, as last seen.
+        clearing_reads: usize,
+        /// The outputs as last sent.
+        outputs: Option<PinSet>,
+    }
+
+    impl VirtualBoard {
+        /// Connect to the user interface listening on `path`.
+        pub(crate) fn connect(path: &Path) -> Result<Self> {
+            info!("Connect to virtual board at {}", path.display());
+            let stream = UnixStream::connect(path)
+                .map_err(|source| PiFaceDigitalError::VirtualBoardFailed { source })?;
+            Ok(VirtualBoard {
+                stream,
+                state: RefCell::new(BridgeState {
+                    connected: true,
+                    received: Vec::new(),
+                    events: VecDeque::new(),
+                    // The inputs have pull-ups on the board.
+                    inputs: PinSet::ALL,
+                    gpioa: 0,
+                    olata: 0,
+                    clearing_reads: 0,
+                    outputs: None,
+                }),
+            })
+        }
+
+        /// Is the user interface still connected?
+        pub(crate) fn is_connected(&self) -> bool {
+            self.state.borrow().connected
+        }
+
+        /// Exchange any changes with the user interface without blocking.
+        pub(crate) fn pump(&self, mcp23s17: &Mcp23s17) {
+            if !self.is_connected() {
+                return;
+            }
+            self.receive(None);
+            self.update_outputs(mcp23s17);
+            self.update_inputs(mcp23s17);
+        }
+
+        /// Wait up to `timeout` for anything from the user interface.
+        pub(crate) fn wait(&self, timeout: Duration) {
+            if self.is_connected() && !timeout.is_zero() {
+                self.receive(Some(timeout));
+            }
+        }
+
+        /// Receive whatever has arrived, waiting up to `timeout` if given.
+        fn receive(&self, timeout: Option<Duration>) {
+            let configured = match timeout {
+                Some(timeout) => self
+                    .stream
+                    .set_nonblocking(false)
+                    .and_then(|()| self.stream.set_read_timeout(Some(timeout))),
+                None => self.stream.set_nonblocking(true),
+            };
+            let mut state = self.state.borrow_mut();
+            if let Err(e) = configured {
+                warn!("Virtual board connection failed: {e}");
+                state.connected = false;
+                return;
+            }
+            let mut buffer = [0u8; 256];
+            loop {
+                match (&self.stream).read(&mut buffer) {
+                    Ok(0) => {
+                        warn!("Virtual board disconnected");
+                        state.connected = false;
+                        return;
+                    }
+                    Ok(length) => state.received.extend_from_slice(&buffer[..length]),
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        break;
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        warn!("Virtual board connection failed: {e}");
+                        state.connected = false;
+                        return;
+                    }
+                }
+                // Having waited for something, collect anything else without waiting.
+                if timeout.is_some() {
+                    drop(state);
+                    return self.receive(None);
+                }
+            }
+
+            while let Some(end) = state.received.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = state.received.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                match line.trim().parse() {
+                    Ok(VirtualMessage::Input(pin, level)) => state.events.push_back((pin, level)),
+                    Ok(message) => warn!("Unexpected message from virtual board: {message}"),
+                    Err(e) => warn!("{e}"),
+                }
+            }
+        }
+
+        /// Emulate the output latch and send the outputs if they have changed.
+        fn update_outputs(&self, mcp23s17: &Mcp23s17) {
+            let mut state = self.state.borrow_mut();
+
+            // Writing GPIOA writes the latch, and the latch drives GPIOA.
+            let gpioa = mcp23s17.get_mock_data(RegisterAddress::GPIOA).0;
+            let olata = mcp23s17.get_mock_data(RegisterAddress::OLATA).0;
+            let latch = if gpioa != state.gpioa { gpioa } else { olata };
+            mcp23s17.set_mock_data(RegisterAddress::GPIOA, latch);
+            mcp23s17.set_mock_data(RegisterAddress::OLATA, latch);
+            (state.gpioa, state.olata) = (latch, latch);
+
+            // Only the pins configured as outputs are driven.
+            let iodira = mcp23s17.get_mock_data(RegisterAddress::IODIRA).0;
+            let outputs = PinSet::from_bits(latch & !iodira);
+            if state.outputs != Some(outputs) {
+                debug!("Virtual board outputs {outputs}");
+                let message = format!("{}\n", VirtualMessage::Outputs(outputs));
+                if let Err(e) = (&self.stream).write_all(message.as_bytes()) {
+                    warn!("Virtual board connection failed: {e}");
+                    state.connected = false;
+                    return;
+                }
+                state.outputs = Some(outputs);
+            }
+        }
+
+        /// Apply the input changes, raising an interrupt for each that should.
+        ///
+        /// Reading `GPIOB` or `INTCAPB` clears the interrupt, as it does in the
+        /// MCP23S17. While an interrupt is pending any further changes are held back
+        /// so that each gets its own interrupt.
+        fn update_inputs(&self, mcp23s17: &Mcp23s17) {
+            let mut state = self.state.borrow_mut();
+            let register = |register| mcp23s17.get_mock_data(register).0;
+
+            let clearing_reads = mcp23s17.get_mock_data(RegisterAddress::GPIOB).1
+                + mcp23s17.get_mock_data(RegisterAddress::INTCAPB).1;
+            if clearing_reads != state.clearing_reads {
+                mcp23s17.set_mock_data(RegisterAddress::INTFB, 0x00);
+                state.clearing_reads = clearing_reads;
+            }
+
+            let ipolb = register(RegisterAddress::IPOLB);
+            while register(RegisterAddress::INTFB) == 0 {
+                let Some((pin, level)) = state.events.pop_front() else {
+                    break;
+                };
+                let before = state.inputs;
+                match level {
+                    Level::High => state.inputs.insert(pin),
+                    Level::Low => state.inputs.remove(pin),
+                }
+                if before == state.inputs {
+                    continue;
+                }
+                let gpiob = state.inputs.bits() ^ ipolb;
+                let enabled = PinSet::from_bits(register(RegisterAddress::GPINTENB));
+                let compare = PinSet::from_bits(register(RegisterAddress::INTCONB));
+                let defval = PinSet::from_bits(register(RegisterAddress::DEFVALB));
+                let changed = PinSet::from_bits(gpiob).contains(pin) != defval.contains(pin);
+                if enabled.contains(pin) && (!compare.contains(pin) || changed) {
+                    debug!("Virtual board interrupt on pin {pin}");
+                    mcp23s17.set_mock_data(RegisterAddress::INTCAPB, gpiob);
+                    mcp23s17.set_mock_data(RegisterAddress::INTFB, PinSet::from(pin).bits());
+                }
+            }
+            mcp23s17.set_mock_data(RegisterAddress::GPIOB, state.inputs.bits() ^ ipolb);
+        }
+    }
+
+    impl PiFaceDigital {
+        /// Connect to a virtual board's user interface listening on the socket at
+        /// `path`.
+        ///
+        /// The connection can also be made when the board is built, with
+        /// [`PiFaceDigitalBuilder::virtual_board()`][crate::PiFaceDigitalBuilder::virtual_board].
+        pub fn connect_virtual_board(&self, path: impl AsRef<Path>) -> Result<()> {
+            let virtual_board = VirtualBoard::connect(path.as_ref())?;
+            let mut pfd_state = self.pfd_state.borrow_mut();
+            virtual_board.pump(&pfd_state.mcp23s17);
+            pfd_state.virtual_board = Some(virtual_board);
+            Ok(())
+        }
+    }
+
+    impl PiFaceDigitalState {
+        /// Is a virtual board connected?
+        pub(crate) fn virtual_board_connected(&self) -> bool {
+            self.virtual_board
+                .as_ref()
+                .is_some_and(VirtualBoard::is_connected)
+        }
+
+        /// Exchange any changes with the virtual board, if there is one.
+        pub(crate) fn pump_virtual_board(&self) {
+            if let Some(virtual_board) = &self.virtual_board {
+                virtual_board.pump(&self.mcp23s17);
+            }
+        }
+
+        /// Wait up to `timeout` (or indefinitely) for the virtual board to raise an
+        /// interrupt, in slices so that the wait can be cancelled.
+        ///
+        /// Returns immediately if there's no virtual board connected.
+        pub(crate) fn wait_for_virtual_interrupt(&self, timeout: Option<Duration>) -> Result<()> {
+            let wait_until = timeout.map(|timeout| Instant::now() + timeout);
+            loop {
+                self.cancel_token.check()?;
+                self.pump_virtual_board();
+                let Some(virtual_board) = self
+                    .virtual_board
+                    .as_ref()
+                    .filter(|virtual_board| virtual_board.is_connected())
+                else {
+                    return Ok(());
+                };
+                if self.mcp23s17.get_mock_data(RegisterAddress::INTFB).0 != 0 {
+                    return Ok(());
+                }
+                let remaining = wait_until
+                    .map(|wait_until| wait_until.saturating_duration_since(Instant::now()));
+                if remaining.is_some_and(|remaining| remaining.is_zero()) {
+                    return Ok(());
+                }
+                virtual_board.wait(remaining.map_or(CancelToken::CHECK_INTERVAL, |remaining| {
+                    remaining.min(CancelToken::CHECK_INTERVAL)
+                }));
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Recovery of an interrupt line that is stuck asserted.
+//!
+//! The Raspberry Pi detects interrupts on the falling edge of the MCP23S17's interrupt
+//! output, which stays asserted until the interrupt capture is read. If that read is
+//! ever missed (for example because the interrupt was already asserted when the GPIO
+//! started being watched) no further edges arrive and interrupts stop for good.
+//!
+//! On the Raspberry Pi a watchdog thread samples the level of the interrupt line in
+//! the background, which needs no SPI traffic. A line that stays asserted across two
+//! samples with no interrupt delivered in between is stuck. Only then does the
+//! watchdog service the interrupt itself, which releases the line, and count the
+//! recovery.
+
+use std::sync::atomic::Ordering;
+#[cfg(not(any(test, feature = "mockspi")))]
+use std::{
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::AtomicUsize,
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::warn;
+#[cfg(not(any(test, feature = "mockspi")))]
+use log::{debug, error};
+
+use crate::{PiFaceDigital, PiFaceDigitalState, RegisterAddress, Result, context::Context};
+#[cfg(not(any(test, feature = "mockspi")))]
+use crate::{PiFaceDigitalError, callbacks::InterruptDelivery, dispatch::InterruptReader};
+#[cfg(not(any(test, feature = "mockspi")))]
+use rppal::gpio::InputPin;
+
+/// Decides whether the interrupt line is stuck from successive samples of its level.
+#[cfg(any(test, not(feature = "mockspi")))]
+#[derive(Debug, Default)]
+pub(crate) struct StuckLineDetector {
+    /// Was the line asserted at the last check?
+    asserted: bool,
+    /// Number of interrupts delivered at the last check.
+    delivered: usize,
+}
+
+#[cfg(any(test, not(feature = "mockspi")))]
+impl StuckLineDetector {
+    /// Make a check given whether the line is asserted and the number of interrupts
+    /// delivered so far, returning `true` if the line is stuck.
+    pub(crate) fn check(&mut self, asserted: bool, delivered: usize) -> bool {
+        let stuck = asserted && self.asserted && delivered == self.delivered;
+        self.asserted = asserted && !stuck;
+        self.delivered = delivered;
+        stuck
+    }
+}
+
+/// The watchdog thread that recovers a stuck interrupt line.
+#[cfg(not(any(test, feature = "mockspi")))]
+#[derive(Debug)]
+pub(crate) struct LineWatchdog {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(not(any(test, feature = "mockspi")))]
+impl LineWatchdog {
+    /// Interval between samples of the interrupt line.
+    const INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Start the watchdog thread.
+    ///
+    /// The `reader` is shared with the GPIO interrupt handler, which counts the
+    /// interrupts that it has `delivered`.
+    pub(crate) fn start(
+        interrupt_pin: Arc<Mutex<InputPin>>,
+        reader: Arc<Mutex<InterruptReader>>,
+        delivered: Arc<AtomicUsize>,
+        recoveries: Arc<AtomicUsize>,
+        delivery: InterruptDelivery,
+    ) -> Result<Self> {
+        let (stop, stop_receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("pfd-watchdog".to_string())
+            .spawn(move || {
+                let mut detector = StuckLineDetector::default();
+                while let Err(RecvTimeoutError::Timeout) =
+                    stop_receiver.recv_timeout(Self::INTERVAL)
+                {
+                    let asserted = interrupt_pin
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .is_low();
+                    if !detector.check(asserted, delivered.load(Ordering::Relaxed)) {
+                        continue;
+                    }
+                    let Ok(reader) = reader.lock() else {
+                        error!("Interrupt reader poisoned - stopping watchdog");
+                        return;
+                    };
+                    match reader.read() {
+                        // The line is shared with any other devices on the bus.
+                        Ok(interrupt) if interrupt.flags.is_empty() => {
+                            debug!("Interrupt line held asserted by another device")
+                        }
+                        Ok(interrupt) => {
+                            warn!("Interrupt line stuck - recovered {interrupt:?}");
+                            recoveries.fetch_add(1, Ordering::Relaxed);
+                            delivery.deliver(interrupt);
+                        }
+                        Err(e) => error!("Failed to recover stuck interrupt line: {e}"),
+                    }
+                }
+            })
+            .map_err(|source| PiFaceDigitalError::CallbackDispatcherFailed { source })?;
+        Ok(LineWatchdog {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(not(any(test, feature = "mockspi")))]
+impl Drop for LineWatchdog {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Interrupt watchdog thread panicked");
+            }
+        }
+    }
+}
+
+impl PiFaceDigitalState {
+    /// Service any interrupt that is already pending so that the interrupt line is
+    /// released and the next interrupt produces a falling edge.
+    pub(crate) fn clear_pending_interrupt(&mut self) -> Result<()> {
+        let context = self.context("clearing pending interrupt");
+        let flags = self
+            .retry(|mcp23s17| Ok(mcp23s17.read(RegisterAddress::INTFB)?))
+            .context(context.register(RegisterAddress::INTFB))?;
+        if flags == 0 {
+            // Read the capture anyway in case the flags were cleared in between.
+            self.retry(|mcp23s17| Ok(mcp23s17.read(RegisterAddress::INTCAPB)?))
+                .context(context.register(RegisterAddress::INTCAPB))?;
+            return Ok(());
+        }
+        warn!("Interrupt already pending (flags 0x{flags:02x}) - servicing it");
+        self.read_and_queue_interrupt()?;
+        self.interrupt_recoveries.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl PiFaceDigital {
+    /// Get the number of times that an interrupt line stuck asserted has been recovered.
+    ///
+    /// Counts interrupts that were already pending when a callback was registered, as
+    /// well as those recovered by the watchdog that runs in the background on the
+    /// Raspberry Pi once callbacks are in use. A steadily rising count suggests that
+    /// interrupts are being missed.
+    pub fn interrupt_recoveries(&self) -> usize {
+        self.pfd_state
+            .borrow()
+            .interrupt_recoveries
+            .load(Ordering::Relaxed)
+    }
+}